@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use eframe::egui;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{self, Receiver};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use crate::arm::SERVO_COUNT;
+use crate::stream::{self, Channel, StreamEvent, Subscription};
+
+// Only reachable from this machine unless another address is given
+pub const DEFAULT_API_ADDRESS: &str = "127.0.0.1:8090";
+// How long a request waits for the controller before giving up, e.g. while the window is being dragged
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Servo angles in degrees by servo name. Servos left out keep their current angle.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServoAngles {
+    pub top: Option<f64>,
+    pub shoulder: Option<f64>,
+    pub upper: Option<f64>,
+    pub elbow: Option<f64>,
+    pub lower: Option<f64>,
+}
+
+impl ServoAngles {
+    // Ordered as in arm::SERVO_NAMES
+    pub fn in_wire_order(&self) -> [Option<f64>; SERVO_COUNT] {
+        [self.top, self.shoulder, self.upper, self.elbow, self.lower]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Solver {
+    // Damped least squares, works for any chain
+    #[default]
+    Numeric,
+    // Closed form, only for the five servo arm
+    ClosedForm,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct MoveTarget {
+    pub i: f64,
+    pub j: f64,
+    pub k: f64,
+    #[serde(default)]
+    pub solver: Solver,
+}
+
+pub enum ApiCommand {
+    GetServos,
+    SetServos(ServoAngles),
+    MoveTo(MoveTarget),
+    GetTelemetry,
+    GetDevices,
+    EmergencyStop,
+    // The kinematic chain as loaded now, for the ROS bridge rather than HTTP
+    GetChain,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into() }
+    }
+}
+
+pub type ApiReply = Result<Value, ApiError>;
+
+// A request waiting for the controller, which answers it on its next frame
+pub struct ApiRequest {
+    pub command: ApiCommand,
+    pub reply: oneshot::Sender<ApiReply>,
+}
+
+// The controller's end of the API
+pub struct ApiLink {
+    pub requests: UnboundedReceiver<ApiRequest>,
+    // Feeds the WebSocket stream, sending with nobody listening is fine
+    pub events: broadcast::Sender<StreamEvent>,
+}
+
+impl ApiLink {
+    pub fn publish(&self, event: StreamEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+// The other end, shared by the HTTP server and the MQTT bridge
+#[derive(Clone)]
+pub struct ApiClient {
+    requests: UnboundedSender<ApiRequest>,
+    events: broadcast::Sender<StreamEvent>,
+    repaint: egui::Context,
+}
+
+// The UI context is needed to wake the controller up when a request comes in
+pub fn channel(repaint: egui::Context) -> (ApiClient, ApiLink) {
+    let (requests, receiver) = mpsc::unbounded_channel();
+    let events = stream::channel();
+    (ApiClient { requests, events: events.clone(), repaint }, ApiLink { requests: receiver, events })
+}
+
+impl ApiClient {
+    // Hands the command to the controller and wakes the UI up so it's answered straight away
+    pub async fn call(&self, command: ApiCommand) -> ApiReply {
+        let (reply, replied) = oneshot::channel();
+        if self.requests.send(ApiRequest { command, reply }).is_err() {
+            return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "The controller has shut down"));
+        }
+        self.repaint.request_repaint();
+        match tokio::time::timeout(REPLY_TIMEOUT, replied).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "The controller dropped the request")),
+            Err(_) => Err(ApiError::new(StatusCode::GATEWAY_TIMEOUT, "The controller didn't answer in time")),
+        }
+    }
+
+    async fn respond(&self, command: ApiCommand) -> Response {
+        match self.call(command).await {
+            Ok(value) => Json(value).into_response(),
+            Err(e) => error_response(e),
+        }
+    }
+}
+
+fn error_response(error: ApiError) -> Response {
+    (error.status, Json(serde_json::json!({ "error": error.message }))).into_response()
+}
+
+// Serves the API until the process exits. Every request is passed on to the controller, so it goes through
+// the same limits and checks as the UI does.
+pub async fn serve(address: String, client: ApiClient) {
+    let router = Router::new()
+        .route("/servos", get(get_servos).put(set_servos))
+        .route("/target", post(move_to))
+        .route("/telemetry", get(get_telemetry))
+        .route("/devices", get(get_devices))
+        .route("/estop", post(emergency_stop))
+        .route("/stream", get(stream))
+        .with_state(client);
+
+    let listener = match address.parse::<SocketAddr>() {
+        Ok(socket_address) => tokio::net::TcpListener::bind(socket_address).await,
+        Err(_) => {
+            log::error!("{} is not an address for the API to listen on", address);
+            return;
+        }
+    };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Unable to start the API on {}: {}", address, e);
+            return;
+        }
+    };
+    log::info!("API listening on http://{}", address);
+    if let Err(e) = axum::serve(listener, router).await {
+        log::error!("API stopped: {}", e);
+    }
+}
+
+async fn get_servos(State(client): State<ApiClient>) -> Response {
+    client.respond(ApiCommand::GetServos).await
+}
+
+async fn set_servos(State(client): State<ApiClient>, Json(angles): Json<ServoAngles>) -> Response {
+    client.respond(ApiCommand::SetServos(angles)).await
+}
+
+async fn move_to(State(client): State<ApiClient>, Json(target): Json<MoveTarget>) -> Response {
+    client.respond(ApiCommand::MoveTo(target)).await
+}
+
+async fn get_telemetry(State(client): State<ApiClient>) -> Response {
+    client.respond(ApiCommand::GetTelemetry).await
+}
+
+async fn get_devices(State(client): State<ApiClient>) -> Response {
+    client.respond(ApiCommand::GetDevices).await
+}
+
+async fn emergency_stop(State(client): State<ApiClient>) -> Response {
+    client.respond(ApiCommand::EmergencyStop).await
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    // Comma separated, every channel if left out
+    channels: Option<String>,
+}
+
+async fn stream(State(client): State<ApiClient>, Query(query): Query<StreamQuery>, upgrade: WebSocketUpgrade) -> Response {
+    let channels = match query.channels.as_deref().map(stream::parse_channels) {
+        Some(Ok(channels)) => channels,
+        Some(Err(e)) => return error_response(ApiError::new(StatusCode::BAD_REQUEST, e)),
+        None => Channel::all(),
+    };
+    // Subscribe before upgrading so nothing sent in between is missed
+    let events = client.events.subscribe();
+    upgrade.on_upgrade(move |socket| stream_events(socket, events, channels))
+}
+
+// Pushes events on the subscribed channels until the client goes away. Clients change their channels
+// by sending a Subscription, and are told how many events they missed if they fall behind.
+async fn stream_events(mut socket: WebSocket, mut events: Receiver<StreamEvent>, mut channels: HashSet<Channel>) {
+    loop {
+        let reply = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if channels.contains(&event.channel()) => serde_json::to_value(&event).ok(),
+                Ok(_) => None,
+                Err(RecvError::Lagged(missed)) => Some(serde_json::json!({ "type": "lagged", "missed": missed })),
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<Subscription>(&text) {
+                    Ok(subscription) => {
+                        channels = subscription.subscribe;
+                        Some(serde_json::json!({ "type": "subscribed", "channels": channels }))
+                    }
+                    Err(e) => Some(serde_json::json!({ "type": "error", "error": e.to_string() })),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => None,
+            },
+        };
+        if let Some(reply) = reply {
+            if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
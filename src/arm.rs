@@ -1,140 +1,139 @@
 use std::error::Error;
 use std::f64;
+use std::fmt;
+use std::ops::RangeInclusive;
 
-use approx;
 use nalgebra as na;
-use nalgebra::Point;
 
+use serde::{Deserialize, Serialize};
+
+use crate::kinematics::{ChainDescription, ChainPoses};
+
+// Servos in the order they are sent on the wire
+pub const SERVO_COUNT: usize = 5;
+pub const SERVO_NAMES: [&str; SERVO_COUNT] = ["Top", "Shoulder", "Upper", "Elbow", "Lower"];
+
+// Servo angles of the last recorded home position. Home is the zero pose of the default five servo chain,
+// with the arm pointing straight up the k axis.
+// The lower rotation had no recorded home, so it is taken as the middle of its travel.
+pub const HOME_SERVO_ANGLES: [f64; SERVO_COUNT] = [95.0, 40.0, 90.0, 0.0, 90.0];
+
+// Travel of one servo in degrees. Anything taken from a file or the UI is checked with validate() first.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServoLimit {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ServoLimit {
+    pub fn new(min: f64, max: f64) -> Result<Self, LimitError> {
+        let limit = ServoLimit { min, max };
+        limit.validate()?;
+        Ok(limit)
+    }
+
+    pub fn validate(&self) -> Result<(), LimitError> {
+        if !self.min.is_finite() || !self.max.is_finite() {
+            return Err(LimitError::NotFinite { min: self.min, max: self.max });
+        }
+        if self.min > self.max {
+            return Err(LimitError::Reversed { min: self.min, max: self.max });
+        }
+        Ok(())
+    }
+
+    // Written with max and min rather than f64::clamp, which panics if a bad limit ever gets this far
+    pub fn clamp(&self, angle: f64) -> f64 {
+        angle.max(self.min).min(self.max)
+    }
+
+    pub fn contains(&self, angle: f64) -> bool {
+        (self.min..=self.max).contains(&angle)
+    }
+
+    pub fn range(&self) -> RangeInclusive<f64> {
+        self.min..=self.max
+    }
+}
 
 pub struct Arm {
-    u: f64,
-    l: f64,
+    chain: ChainDescription,
     shoulder_servo: f64,
     elbow_servo: f64,
     top_servo: f64,
     upper_arm_servo: f64,
     lower_arm_servo: f64,
-    a: na::Vector3<f64>,
-    b: na::Vector3<f64>,
-    c: na::Vector3<f64>,
+    poses: ChainPoses,
 }
 
 impl Arm {
     pub fn new(u: f64, l: f64) -> Arm {
+        Arm::from_chain(ChainDescription::five_servo(u, l, 0.0))
+    }
+
+    pub fn from_chain(chain: ChainDescription) -> Arm {
+        let home = chain.home_servo_angles();
         let mut arm = Arm {
-            u,
-            l,
-            shoulder_servo: 40.0,
-            elbow_servo: 0.0,
-            top_servo: 95.0,
-            upper_arm_servo: 90.0,
-            lower_arm_servo: 90.0,
-            a: na::Vector3::new(0.0, 0.0, 0.0), // Shoulder (origin)
-            b: na::Vector3::new(0.0, 0.0, 0.0), // Elbow
-            c: na::Vector3::new(0.0, 0.0, 0.0), // End effector
+            chain,
+            shoulder_servo: home[1],
+            elbow_servo: home[3],
+            top_servo: home[0],
+            upper_arm_servo: home[2],
+            lower_arm_servo: home[4],
+            poses: ChainPoses {
+                joints: Vec::new(),
+                tool: na::Isometry3::identity(),
+            },
         };
         arm.calculate_values();
         arm
     }
 
     fn calculate_values(&mut self) {
-        /*
-        Last recorded home position:
-        Top: 95
-        Shoulder: 40
-        Upper_Rotation: 90
-        Elbow: 0
-        Lower_Rotation: N/A
-         */
-
-        // Initial State with all angles at 0
-        let mut dummy_elbow = na::Vector3::new(0.0, 0.0, self.u);
-
-        // perform the rotation of the elbow, representing the elbow servo, v2
-        // This is done be translating the end effector to imagine the elbow is at to the origin,
-        // rotating it about the i axis, and then moving it back to its original position.
-        let mut dummy_end = na::Vector3::new(0.0, 0.0, self.l); // the end effector, minus the length of the lower arm
-        let mut rot = na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), self.elbow_servo.to_radians()); // the negative is because the servo is rotating clockwise
-        dummy_end = rot * dummy_end;
-        // now bring back to the correct position
-        dummy_end = na::Vector3::new(dummy_end.x, dummy_end.y, dummy_end.z + self.u);
-
-        // Represent the rotation of the upper arm, h2, by rotating about the k axis
-        rot = na::Rotation3::from_axis_angle(&na::Vector3::z_axis(), -self.upper_arm_servo.to_radians()); // negative for clockwise rotation
-        dummy_end = rot * dummy_end;
-
-        // perform a rotation about the j axis, representing the rotation of the shoulder servo, v1
-        rot = na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), self.shoulder_servo.to_radians()); // Counter clockwise so positive
-        dummy_end = rot * dummy_end;
-        dummy_elbow = rot * dummy_elbow;
-
-        // // perform the rotation about the i, representing the rotation of the top servo, h1
-        // rot = na::Rotation3::from_axis_angle(&na::Vector3::x_axis(), self.top_servo.to_radians());
-        // dummy_end = rot * dummy_end;
-        // dummy_elbow = rot * dummy_elbow;
-        //
-        // // Now we have the position of the end effector, c, and the elbow, b, in the 3d space
-        self.b = dummy_elbow;
-        self.c = dummy_end;
-
-        // Now perform the rotation of the
-        // // Get the position of point b, the elbow, if it lay on the ik plane, assuming every other angle is 0
-        // self.b = self.get_b_2d();
-        //
-        // // now, using the position of the elbow, calculate the position of the end effector, c
-        // self.c = self.get_c_2d();
-        // // Now we rotate the end about the ab axis by the angle h2, to get the current position of the end effector, taking into account
-        // // only v1, v2, and h2.
-        //
-        // // Perform the rotation about the AB axis, representing the rotation of h2
-        // let normal_ab = na::Unit::new_normalize(*&self.b);
-        // let mut rot = na::Rotation3::from_axis_angle(&normal_ab, self.h2.to_radians());
-        // self.c = rot * self.c;
-        //
-        // // Perform the rotation about the i axis, representing the rotation of v1.
-        // let mut rot = na::Rotation3::from_axis_angle(&na::Vector3::x_axis(), self.h1.to_radians());
-        //
-        // self.b = rot * self.b;
-        // self.c = rot * self.c;
-    }
-
-    fn get_b_2d(&self) -> na::Vector3<f64> {
-        // Calculate the position of point b, the elbow, if it lay on the ik plane
-        // This is simple since we can assume it is only affected by v1, we assume h1 is 0.
-
-        // Firstly we get i
-        let i = self.u * self.shoulder_servo.to_radians().sin();
-        // j is 0
-        let j = 0.0;
-        let k = self.u * self.shoulder_servo.to_radians().cos();
-
-        na::Vector3::new(i, j, k)
-    }
-    fn get_c_2d(&self) -> na::Vector3<f64> {
-        // Calculate the position of point c, the end effector, if it lay on the ij plane
-        // More complicated than the elbow since it is affected by both v1 and v2.
-
-
-        // Firstly we get i, which will be the same process as for the elbow, but we then add the elbow's i to it.
-        let i = self.b.x + self.l * self.elbow_servo.to_radians().sin();
-        // j is 0
-        let j = 0.0;
-        // k is the same process as for i, but we then add the elbow's k to it.
-        let k = self.b.z + self.l * self.elbow_servo.to_radians().cos();
-
-        na::Vector3::new(i, j, k)
-        // this is the value of c if everything is 0, except for v1 and v2.
+        self.poses = self.chain.forward(&self.servo_angles());
+    }
+
+    // Position of the end effector for a set of servo angles in degrees, ordered as in SERVO_NAMES
+    pub fn forward(&self, angles: &[f64; SERVO_COUNT]) -> na::Vector3<f64> {
+        self.chain.forward(angles).tool.translation.vector
     }
 
     pub fn get_ijk(&self) -> (f64, f64, f64) {
-        (self.c.x, self.c.y, self.c.z)
+        let tool = self.poses.tool.translation.vector;
+        (tool.x, tool.y, tool.z)
     }
 
-    pub fn get_elbow(&self) -> (f64, f64, f64) {
-        (self.b.x, self.b.y, self.b.z)
+    // Positions of the origin, every joint along the chain, and the end effector
+    pub fn get_joint_positions(&self) -> Vec<(f64, f64, f64)> {
+        let mut positions = vec![(0.0, 0.0, 0.0)];
+        for pose in self.poses.joints.iter().chain(std::iter::once(&self.poses.tool)) {
+            let position = pose.translation.vector;
+            positions.push((position.x, position.y, position.z));
+        }
+        positions
     }
-    pub fn settable_arm_lengths(&mut self) -> (&mut f64, &mut f64) {
-        (&mut self.u, &mut self.l)
+
+    // The upper, lower and tool lengths, only available while the chain has the five servo layout
+    pub fn settable_arm_lengths(&mut self) -> Option<(&mut f64, &mut f64, &mut f64)> {
+        self.chain.five_servo_lengths_mut()
+    }
+
+    pub fn chain(&self) -> &ChainDescription {
+        &self.chain
+    }
+
+    // Swaps in a new chain description, keeping the current servo angles
+    pub fn set_chain(&mut self, chain: ChainDescription) {
+        self.chain = chain;
+        self.calculate_values();
+    }
+
+    pub fn home_servo_angles(&self) -> [f64; SERVO_COUNT] {
+        self.chain.home_servo_angles()
+    }
+
+    pub fn poses(&self) -> &ChainPoses {
+        &self.poses
     }
 
 
@@ -163,23 +162,621 @@ impl Arm {
         &mut self.lower_arm_servo
     }
 
-    fn go_to_middle(&mut self) {
-        self.shoulder_servo = 90.0;
-        self.elbow_servo = 90.0;
-        self.top_servo = 90.0;
-        self.upper_arm_servo = 90.0;
-        self.lower_arm_servo = 90.0;
+    pub fn update(&mut self) {
+        self.calculate_values();
+    }
 
+    // All servo angles in degrees, ordered as in SERVO_NAMES
+    pub fn servo_angles(&self) -> [f64; SERVO_COUNT] {
+        [self.top_servo, self.shoulder_servo, self.upper_arm_servo, self.elbow_servo, self.lower_arm_servo]
     }
 
-    pub fn update(&mut self) {
+    pub fn set_servo_angles(&mut self, angles: &[f64; SERVO_COUNT]) {
+        [self.top_servo, self.shoulder_servo, self.upper_arm_servo, self.elbow_servo, self.lower_arm_servo] = *angles;
         self.calculate_values();
     }
 
-    pub fn angle_from_point(&mut self, i: f64, j: f64, k: f64) -> (f64, f64, f64){
-        //TODO: Implement this function
-        (0.0, 0.0, 0.0)
+    pub fn angle_from_point(&self, i: f64, j: f64, k: f64) -> Result<Vec<IkSolution>, IkError> {
+        // Closed form inverse of the five servo chain for the shoulder (v1), upper rotation (h2) and elbow (v2),
+        // keeping the top servo where it is. The tool sits on the lower arm's axis, so it just lengthens it.
+        // Undoing the top rotation, with the elbow at e and the upper rotation at h, the end effector before the shoulder rotation is
+        //   p = (l sin(e) cos(h), l sin(e) sin(h), u + l cos(e))
+        // and the shoulder only rotates p about the j axis, so |c| = |p| depends on the elbow alone.
+        let (u, lower_length, tool_length) = self.chain.five_servo_lengths().ok_or(IkError::UnsupportedChain)?;
+        let l = lower_length + tool_length;
+        let mapping = |servo: usize| self.chain.joint(servo).map(|joint| joint.mapping).ok_or(IkError::UnsupportedChain);
+        let (top_mapping, shoulder_mapping, upper_mapping, elbow_mapping) = (mapping(0)?, mapping(1)?, mapping(2)?, mapping(3)?);
+
+        let top = top_mapping.to_joint(self.top_servo);
+        let current_shoulder = shoulder_mapping.to_joint(self.shoulder_servo);
+        let current_upper = upper_mapping.to_joint(self.upper_arm_servo);
+        let top_rotation = na::Rotation3::from_axis_angle(&na::Vector3::x_axis(), top.to_radians());
+        let target = top_rotation.inverse() * na::Vector3::new(i, j, k);
+        let (i, j, k) = (target.x, target.y, target.z);
+
+        let reach_sq = i * i + j * j + k * k;
+        let reach = reach_sq.sqrt();
+        let min_reach = (u - l).abs();
+        let max_reach = u + l;
+        if u <= IK_EPSILON || l <= IK_EPSILON || reach > max_reach + IK_EPSILON || reach < min_reach - IK_EPSILON {
+            return Err(IkError::Unreachable { distance: reach, min_reach, max_reach });
+        }
+
+        // Law of cosines gives the elbow, clamped so points right on the reach boundary still solve
+        let mut cos_e = ((reach_sq - u * u - l * l) / (2.0 * u * l)).clamp(-1.0, 1.0);
+        // Snap a nearly straight or folded elbow so rounding doesn't create an almost singular pose
+        if 1.0 - cos_e.abs() < IK_SINGULAR_EPSILON {
+            cos_e = cos_e.signum();
+        }
+        let elbow = cos_e.acos();
+
+        let mut solutions: Vec<IkSolution> = Vec::new();
+        // Elbow up is a positive elbow angle, elbow down is the mirrored negative one
+        for e in [elbow, -elbow] {
+            let radial = l * e.sin();
+
+            let upper_candidates = if radial.abs() < IK_EPSILON {
+                // Arm is fully stretched or folded, so the upper rotation can't move the end effector.
+                // j has to be 0 and we keep whatever the upper rotation currently is.
+                if j.abs() > IK_EPSILON {
+                    continue;
+                }
+                vec![current_upper.to_radians()]
+            } else {
+                let sin_h = j / radial;
+                if sin_h.abs() > 1.0 + IK_EPSILON {
+                    continue;
+                }
+                let h = sin_h.clamp(-1.0, 1.0).asin();
+                vec![h, f64::consts::PI - h]
+            };
+
+            for h in upper_candidates {
+                let px = radial * h.cos();
+                let pz = u + l * e.cos();
+                // Rotating about j by s adds s to atan2(x, z); when the end effector is at the origin the shoulder is free
+                let s = if reach < IK_EPSILON {
+                    current_shoulder.to_radians()
+                } else {
+                    i.atan2(k) - px.atan2(pz)
+                };
+
+                let solution = IkSolution {
+                    shoulder: shoulder_mapping.to_servo(normalise_degrees(s.to_degrees())),
+                    upper_arm: upper_mapping.to_servo(normalise_degrees(h.to_degrees())),
+                    elbow: elbow_mapping.to_servo(normalise_degrees(e.to_degrees())),
+                    elbow_up: e >= 0.0,
+                };
+                if !solutions.iter().any(|other| other.approx_eq(&solution)) {
+                    solutions.push(solution);
+                }
+            }
+        }
+
+        if solutions.is_empty() {
+            Err(IkError::Unreachable { distance: reach, min_reach, max_reach })
+        } else {
+            Ok(solutions)
+        }
+    }
+
+    // Sets the shoulder, upper rotation and elbow servos from an IK solution and recalculates the arm
+    pub fn apply_solution(&mut self, solution: &IkSolution) {
+        self.shoulder_servo = solution.shoulder;
+        self.upper_arm_servo = solution.upper_arm;
+        self.elbow_servo = solution.elbow;
+        self.calculate_values();
+    }
+}
+
+impl Arm {
+    // Iterative damped least squares IK. This only relies on forward(), so it keeps working however the chain is modelled.
+    // Each servo is kept within its range; a range with equal ends locks that servo in place.
+    // The solve starts from the current pose, and if that gets stuck it restarts from a few fixed poses spread across the limits.
+    // Limits that are reversed or not numbers are refused before anything is solved.
+    pub fn solve_numeric(&self, i: f64, j: f64, k: f64, limits: &[ServoLimit; SERVO_COUNT], options: &IkOptions) -> Result<IkReport, IkError> {
+        for (servo, limit) in limits.iter().enumerate() {
+            limit.validate().map_err(|error| IkError::InvalidLimit { servo, error })?;
+        }
+        let target = na::Vector3::new(i, j, k);
+        let current = self.servo_angles();
+
+        let mut best: Option<IkReport> = None;
+        for (restarts, fraction) in [None, Some(0.5), Some(0.25), Some(0.75)].into_iter().enumerate() {
+            let mut seed = current;
+            for (joint, limit) in limits.iter().enumerate() {
+                seed[joint] = match fraction {
+                    Some(fraction) => limit.min + (limit.max - limit.min) * fraction,
+                    None => limit.clamp(seed[joint]),
+                };
+            }
+
+            let mut report = self.solve_from(seed, target, limits, options);
+            report.restarts = restarts;
+            if report.converged() {
+                return Ok(report);
+            }
+            if best.as_ref().is_none_or(|best| report.error < best.error) {
+                best = Some(report);
+            }
+        }
+        Ok(best.expect("At least one IK attempt is always made"))
+    }
+
+    fn solve_from(&self, mut angles: [f64; SERVO_COUNT], target: na::Vector3<f64>, limits: &[ServoLimit; SERVO_COUNT], options: &IkOptions) -> IkReport {
+        let mut error = target - self.forward(&angles);
+        let mut damping = options.damping.max(IK_MIN_DAMPING);
+        let mut manipulability = 0.0;
+        let mut iterations = 0;
+        let mut status = IkStatus::MaxIterations;
+
+        while iterations < options.max_iterations {
+            if error.norm() <= options.tolerance {
+                status = IkStatus::Converged;
+                break;
+            }
+            iterations += 1;
+
+            let full_jacobian = self.jacobian(&angles);
+            manipulability = (full_jacobian * full_jacobian.transpose()).determinant().max(0.0).sqrt();
+
+            // Servos with no travel never move
+            let mut locked = [false; SERVO_COUNT];
+            for (joint, limit) in limits.iter().enumerate() {
+                locked[joint] = limit.min >= limit.max;
+            }
+
+            // A servo sitting on a limit that the step would push further out can't help,
+            // so take it out of the solve and let the others make up for it
+            let mut step = na::SVector::<f64, SERVO_COUNT>::zeros();
+            let mut singular = false;
+            for _ in 0..SERVO_COUNT {
+                let mut jacobian = full_jacobian;
+                for (joint, is_locked) in locked.iter().enumerate() {
+                    if *is_locked {
+                        jacobian.column_mut(joint).fill(0.0);
+                    }
+                }
+
+                // dq = J^T (J J^T + lambda^2 I)^-1 e, in radians
+                let jjt = jacobian * jacobian.transpose() + na::Matrix3::identity() * damping * damping;
+                match jjt.try_inverse() {
+                    Some(inverse) => step = jacobian.transpose() * inverse * error,
+                    None => {
+                        singular = true;
+                        break;
+                    }
+                }
+
+                let mut newly_locked = false;
+                for (joint, limit) in limits.iter().enumerate() {
+                    let pushing_start = angles[joint] <= limit.min + IK_EPSILON && step[joint] < 0.0;
+                    let pushing_end = angles[joint] >= limit.max - IK_EPSILON && step[joint] > 0.0;
+                    if !locked[joint] && (pushing_start || pushing_end) {
+                        locked[joint] = true;
+                        newly_locked = true;
+                    }
+                }
+                if !newly_locked {
+                    break;
+                }
+            }
+            if singular {
+                status = IkStatus::Singular;
+                break;
+            }
+
+            let largest = step.amax().to_degrees();
+            let scale = if largest > options.max_step { options.max_step / largest } else { 1.0 };
+            let mut candidate = angles;
+            for (joint, limit) in limits.iter().enumerate() {
+                if !locked[joint] {
+                    candidate[joint] = limit.clamp(angles[joint] + step[joint].to_degrees() * scale);
+                }
+            }
+
+            let candidate_error = target - self.forward(&candidate);
+            if candidate_error.norm() < error.norm() {
+                // Good step, so trust the linearisation a little more
+                angles = candidate;
+                error = candidate_error;
+                damping = (damping * 0.5).max(IK_MIN_DAMPING);
+            } else {
+                // Overshot, or a limit blocked the step, so damp harder and try again from the same pose
+                damping *= 4.0;
+                if damping > IK_MAX_DAMPING {
+                    status = IkStatus::Stalled;
+                    break;
+                }
+            }
+        }
+        if status == IkStatus::MaxIterations && error.norm() <= options.tolerance {
+            status = IkStatus::Converged;
+        }
+
+        IkReport {
+            angles,
+            status,
+            iterations,
+            restarts: 0,
+            error: error.norm(),
+            manipulability,
+        }
+    }
+
+    // Numerical jacobian of the end effector position with respect to each servo, per radian
+    fn jacobian(&self, angles: &[f64; SERVO_COUNT]) -> na::SMatrix<f64, 3, SERVO_COUNT> {
+        let mut jacobian = na::SMatrix::<f64, 3, SERVO_COUNT>::zeros();
+        for joint in 0..SERVO_COUNT {
+            let mut forward_angles = *angles;
+            let mut backward_angles = *angles;
+            forward_angles[joint] += IK_JACOBIAN_STEP;
+            backward_angles[joint] -= IK_JACOBIAN_STEP;
+            let difference = self.forward(&forward_angles) - self.forward(&backward_angles);
+            jacobian.set_column(joint, &(difference / (2.0 * IK_JACOBIAN_STEP.to_radians())));
+        }
+        jacobian
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IkOptions {
+    // Distance from the target, in metres, that counts as reached
+    pub tolerance: f64,
+    pub max_iterations: usize,
+    // Starting damping factor, larger values trade accuracy near singularities for stability
+    pub damping: f64,
+    // Largest single servo move per iteration, in degrees
+    pub max_step: f64,
+}
+
+impl Default for IkOptions {
+    fn default() -> Self {
+        IkOptions {
+            tolerance: 0.0005,
+            max_iterations: 200,
+            damping: 0.05,
+            max_step: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IkStatus {
+    Converged,
+    MaxIterations,
+    // Every step made the error worse, usually a target outside the reachable space or the servo limits
+    Stalled,
+    Singular,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IkReport {
+    // Servo angles in degrees, ordered as in SERVO_NAMES
+    pub angles: [f64; SERVO_COUNT],
+    pub status: IkStatus,
+    // Iterations of the attempt that produced this result
+    pub iterations: usize,
+    // How many times the solve was restarted from another pose
+    pub restarts: usize,
+    // Remaining distance from the target in metres
+    pub error: f64,
+    // sqrt(det(J J^T)) at the last iteration, close to 0 near a singularity
+    pub manipulability: f64,
+}
+
+impl IkReport {
+    pub fn converged(&self) -> bool {
+        self.status == IkStatus::Converged
+    }
+}
+
+impl fmt::Display for IkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} after {} iterations ({} restarts), error {:.4} m, manipulability {:.4}",
+            self.status, self.iterations, self.restarts, self.error, self.manipulability
+        )
     }
 }
 
+const IK_EPSILON: f64 = 1e-9;
+const IK_MIN_DAMPING: f64 = 1e-6;
+const IK_MAX_DAMPING: f64 = 1e3;
+// Degrees either side of the current angle used for the central difference
+const IK_JACOBIAN_STEP: f64 = 1e-3;
+const IK_SINGULAR_EPSILON: f64 = 1e-12;
+
+// Wraps an angle in degrees into (-180, 180]
+fn normalise_degrees(angle: f64) -> f64 {
+    let wrapped = (angle + 180.0).rem_euclid(360.0) - 180.0;
+    if wrapped <= -180.0 { wrapped + 360.0 } else { wrapped }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IkSolution {
+    pub shoulder: f64,
+    pub upper_arm: f64,
+    pub elbow: f64,
+    pub elbow_up: bool,
+}
+
+impl IkSolution {
+    fn approx_eq(&self, other: &IkSolution) -> bool {
+        approx::abs_diff_eq!(self.shoulder, other.shoulder, epsilon = 1e-6)
+            && approx::abs_diff_eq!(self.upper_arm, other.upper_arm, epsilon = 1e-6)
+            && approx::abs_diff_eq!(self.elbow, other.elbow, epsilon = 1e-6)
+    }
+
+    // True if every joint of the solution sits inside its servo range
+    pub fn within_limits(&self, shoulder: &ServoLimit, upper_arm: &ServoLimit, elbow: &ServoLimit) -> bool {
+        shoulder.contains(self.shoulder) && upper_arm.contains(self.upper_arm) && elbow.contains(self.elbow)
+    }
+
+    // Sum of squared joint moves needed to get from the given angles to this solution
+    pub fn distance_from(&self, shoulder: f64, upper_arm: f64, elbow: f64) -> f64 {
+        (self.shoulder - shoulder).powi(2) + (self.upper_arm - upper_arm).powi(2) + (self.elbow - elbow).powi(2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IkError {
+    Unreachable { distance: f64, min_reach: f64, max_reach: f64 },
+    OutsideLimits,
+    // The closed form only covers the five servo layout, other chains need the numeric solver
+    UnsupportedChain,
+    // A servo's limits can't be solved within, numbered as in SERVO_NAMES
+    InvalidLimit { servo: usize, error: LimitError },
+}
+
+impl fmt::Display for IkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IkError::Unreachable { distance, min_reach, max_reach } => write!(
+                f,
+                "Target is unreachable: {:.3} m from the shoulder, arm reaches {:.3} m to {:.3} m",
+                distance, min_reach, max_reach
+            ),
+            IkError::OutsideLimits => write!(f, "Target is reachable, but not within the servo limits"),
+            IkError::UnsupportedChain => write!(f, "The closed form solver only supports the five servo arm, use the numeric solver"),
+            IkError::InvalidLimit { servo, error } => write!(f, "{} servo {}", SERVO_NAMES[*servo], error),
+        }
+    }
+}
+
+impl Error for IkError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitError {
+    NotFinite { min: f64, max: f64 },
+    Reversed { min: f64, max: f64 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::NotFinite { min, max } => write!(f, "limits {}° to {}° must both be numbers", min, max),
+            LimitError::Reversed { min, max } => write!(f, "minimum {}° is above its maximum {}°", min, max),
+        }
+    }
+}
+
+impl Error for LimitError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPPER: f64 = 1.0;
+    const LOWER: f64 = 0.8;
+    const TOLERANCE: f64 = 1e-6;
 
+    // Poses spread over the servo ranges, none of them singular
+    const POSES: [[f64; SERVO_COUNT]; 5] = [
+        [95.0, 40.0, 90.0, 30.0, 90.0],
+        [95.0, 70.0, 120.0, 60.0, 90.0],
+        [60.0, 20.0, 45.0, 100.0, 90.0],
+        [130.0, 90.0, 160.0, 150.0, 90.0],
+        [10.0, 150.0, 10.0, 45.0, 90.0],
+    ];
+
+    fn arm_at(angles: &[f64; SERVO_COUNT]) -> Arm {
+        let mut arm = Arm::new(UPPER, LOWER);
+        arm.set_servo_angles(angles);
+        arm
+    }
+
+    // Applies every solution to the arm it was solved from and checks the end effector lands on the target
+    fn assert_solutions_reach(arm: &Arm, target: (f64, f64, f64)) -> Vec<IkSolution> {
+        let solutions = arm.angle_from_point(target.0, target.1, target.2).expect("Target should be reachable");
+        for solution in &solutions {
+            let mut solved = arm_at(&arm.servo_angles());
+            solved.apply_solution(solution);
+            let (i, j, k) = solved.get_ijk();
+            assert!(
+                approx::abs_diff_eq!(i, target.0, epsilon = TOLERANCE)
+                    && approx::abs_diff_eq!(j, target.1, epsilon = TOLERANCE)
+                    && approx::abs_diff_eq!(k, target.2, epsilon = TOLERANCE),
+                "{:?} reaches {:?}, not {:?}",
+                solution,
+                (i, j, k),
+                target
+            );
+        }
+        solutions
+    }
+
+    #[test]
+    fn closed_form_solutions_reach_the_target() {
+        for pose in POSES {
+            let arm = arm_at(&pose);
+            let solutions = assert_solutions_reach(&arm, arm.get_ijk());
+            assert!(solutions.iter().any(|solution| solution.elbow_up), "No elbow up solution for {:?}", pose);
+            assert!(solutions.iter().any(|solution| !solution.elbow_up), "No elbow down solution for {:?}", pose);
+        }
+    }
+
+    #[test]
+    fn closed_form_rejects_targets_out_of_reach() {
+        let arm = Arm::new(UPPER, LOWER);
+        for target in [(0.0, 0.0, UPPER + LOWER + 0.01), (0.0, 1.5, 1.5), (0.0, 0.0, UPPER - LOWER - 0.01)] {
+            match arm.angle_from_point(target.0, target.1, target.2) {
+                Err(IkError::Unreachable { min_reach, max_reach, .. }) => {
+                    assert!(approx::abs_diff_eq!(min_reach, UPPER - LOWER, epsilon = TOLERANCE));
+                    assert!(approx::abs_diff_eq!(max_reach, UPPER + LOWER, epsilon = TOLERANCE));
+                }
+                result => panic!("{:?} should be unreachable, got {:?}", target, result),
+            }
+        }
+    }
+
+    #[test]
+    fn closed_form_solves_stretched_and_folded_poses() {
+        let arm = Arm::new(UPPER, LOWER);
+        let reach = UPPER + LOWER;
+        // Fully stretched, straight up and tilted over, where the upper rotation can't move the end effector
+        for target in [(0.0, 0.0, reach), (reach * 0.6, 0.0, reach * 0.8)] {
+            let solutions = assert_solutions_reach(&arm, target);
+            for solution in solutions {
+                let elbow = arm.chain().joint(3).unwrap().mapping.to_joint(solution.elbow);
+                assert!(approx::abs_diff_eq!(elbow, 0.0, epsilon = TOLERANCE), "Stretched elbow at {}", elbow);
+            }
+        }
+        // Folded back on itself, the closest the arm gets to the shoulder
+        let solutions = assert_solutions_reach(&arm, (0.0, 0.0, UPPER - LOWER));
+        for solution in solutions {
+            let elbow = arm.chain().joint(3).unwrap().mapping.to_joint(solution.elbow);
+            assert!(approx::abs_diff_eq!(elbow.abs(), 180.0, epsilon = TOLERANCE), "Folded elbow at {}", elbow);
+        }
+    }
+
+    fn full_travel() -> [ServoLimit; SERVO_COUNT] {
+        [ServoLimit { min: 0.0, max: 180.0 }; SERVO_COUNT]
+    }
+
+    fn assert_within(angles: &[f64; SERVO_COUNT], limits: &[ServoLimit; SERVO_COUNT]) {
+        for (servo, (angle, limit)) in angles.iter().zip(limits).enumerate() {
+            assert!(limit.contains(*angle), "{} servo at {} is outside {:?}", SERVO_NAMES[servo], angle, limit);
+        }
+    }
+
+    #[test]
+    fn numeric_converges_on_reachable_targets() {
+        let options = IkOptions::default();
+        let limits = full_travel();
+        let arm = Arm::new(UPPER, LOWER);
+        for pose in POSES {
+            let (i, j, k) = arm_at(&pose).get_ijk();
+            let report = arm.solve_numeric(i, j, k, &limits, &options).unwrap();
+            assert!(report.converged(), "{:?} from {:?}", report, pose);
+            assert!((arm.forward(&report.angles) - na::Vector3::new(i, j, k)).norm() <= options.tolerance);
+            assert_within(&report.angles, &limits);
+        }
+    }
+
+    #[test]
+    fn numeric_keeps_servos_within_narrow_and_locked_limits() {
+        let options = IkOptions::default();
+        for pose in POSES {
+            // Lock the top and lower rotation where the pose has them, and only allow the others a little either side
+            let limits: [ServoLimit; SERVO_COUNT] = std::array::from_fn(|servo| match servo {
+                0 | 4 => ServoLimit { min: pose[servo], max: pose[servo] },
+                _ => ServoLimit { min: (pose[servo] - 20.0).max(0.0), max: (pose[servo] + 20.0).min(180.0) },
+            });
+            let (i, j, k) = arm_at(&pose).get_ijk();
+            let report = Arm::new(UPPER, LOWER).solve_numeric(i, j, k, &limits, &options).unwrap();
+            assert!(report.converged(), "{:?} from {:?}", report, pose);
+            assert_within(&report.angles, &limits);
+            assert_eq!(report.angles[0], pose[0]);
+            assert_eq!(report.angles[4], pose[4]);
+        }
+    }
+
+    #[test]
+    fn numeric_gives_up_on_unreachable_targets_within_limits() {
+        let options = IkOptions::default();
+        let arm = Arm::new(UPPER, LOWER);
+        let narrow = [(95.0, 95.0), (30.0, 60.0), (80.0, 100.0), (20.0, 40.0), (90.0, 90.0)].map(|(min, max)| ServoLimit { min, max });
+        // Beyond the arm's reach, and within reach but behind where the narrow limits let it go
+        let cases = [((0.0, 0.0, 2.5), full_travel()), ((3.0, 1.0, -1.0), full_travel()), ((-1.0, 0.0, -0.5), narrow)];
+        for ((i, j, k), limits) in cases {
+            let report = arm.solve_numeric(i, j, k, &limits, &options).unwrap();
+            assert!(matches!(report.status, IkStatus::Stalled | IkStatus::MaxIterations), "{:?} for {:?}", report, (i, j, k));
+            assert!(report.error > options.tolerance);
+            assert_within(&report.angles, &limits);
+        }
+    }
+
+    #[test]
+    fn limits_clamp_without_panicking() {
+        let limit = ServoLimit::new(30.0, 150.0).unwrap();
+        assert_eq!(limit.clamp(10.0), 30.0);
+        assert_eq!(limit.clamp(90.0), 90.0);
+        assert_eq!(limit.clamp(200.0), 150.0);
+        assert_eq!(ServoLimit::new(150.0, 30.0), Err(LimitError::Reversed { min: 150.0, max: 30.0 }));
+        assert!(ServoLimit::new(f64::NAN, 30.0).is_err());
+        // A limit that skipped validation still doesn't panic
+        assert_eq!(ServoLimit { min: 150.0, max: 30.0 }.clamp(90.0), 30.0);
+    }
+
+    #[test]
+    fn numeric_refuses_invalid_limits() {
+        let arm = Arm::new(UPPER, LOWER);
+        let (i, j, k) = arm.get_ijk();
+        let mut limits = full_travel();
+        limits[1] = ServoLimit { min: 120.0, max: 60.0 };
+        let result = arm.solve_numeric(i, j, k, &limits, &IkOptions::default());
+        assert_eq!(result.unwrap_err(), IkError::InvalidLimit { servo: 1, error: LimitError::Reversed { min: 120.0, max: 60.0 } });
+        limits[1] = full_travel()[1];
+        limits[3].max = f64::NAN;
+        assert!(matches!(arm.solve_numeric(i, j, k, &limits, &IkOptions::default()), Err(IkError::InvalidLimit { servo: 3, .. })));
+    }
+
+    fn assert_at(position: (f64, f64, f64), expected: (f64, f64, f64)) {
+        assert!(
+            approx::abs_diff_eq!(position.0, expected.0, epsilon = TOLERANCE)
+                && approx::abs_diff_eq!(position.1, expected.1, epsilon = TOLERANCE)
+                && approx::abs_diff_eq!(position.2, expected.2, epsilon = TOLERANCE),
+            "{:?} isn't {:?}",
+            position,
+            expected
+        );
+    }
+
+    #[test]
+    fn home_points_straight_up() {
+        let arm = Arm::new(UPPER, LOWER);
+        assert_eq!(arm.servo_angles(), HOME_SERVO_ANGLES);
+        let positions = arm.get_joint_positions();
+        assert_at(positions[4], (0.0, 0.0, UPPER));
+        assert_at(arm.get_ijk(), (0.0, 0.0, UPPER + LOWER));
+    }
+
+    #[test]
+    fn forward_matches_the_arm_pose() {
+        for pose in POSES {
+            let arm = arm_at(&pose);
+            let tool = arm.forward(&pose);
+            assert_at(arm.get_ijk(), (tool.x, tool.y, tool.z));
+            // However the joints turn, the links keep their lengths
+            let positions = arm.get_joint_positions();
+            let elbow = na::Vector3::new(positions[4].0, positions[4].1, positions[4].2);
+            assert!(approx::abs_diff_eq!(elbow.norm(), UPPER, epsilon = TOLERANCE));
+            assert!(approx::abs_diff_eq!((tool - elbow).norm(), LOWER, epsilon = TOLERANCE));
+        }
+    }
+
+    #[test]
+    fn shoulder_and_elbow_tilt_towards_i() {
+        let mut angles = HOME_SERVO_ANGLES;
+        angles[1] += 90.0;
+        assert_at(arm_at(&angles).get_ijk(), (UPPER + LOWER, 0.0, 0.0));
+        let mut angles = HOME_SERVO_ANGLES;
+        angles[3] += 90.0;
+        let arm = arm_at(&angles);
+        assert_at(arm.get_joint_positions()[4], (0.0, 0.0, UPPER));
+        assert_at(arm.get_ijk(), (LOWER, 0.0, UPPER));
+    }
+}
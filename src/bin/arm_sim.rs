@@ -0,0 +1,607 @@
+// Stands in for the ESP32 on the arm, so the controller can be developed away from the bench.
+// Servo positions are simulated in wire values, so an uncalibrated controller sees degrees.
+//
+// cargo run --bin arm_sim -- --slew-rate 90 --latency-ms 40 --loss 0.05 --stall 3
+
+// The simulator speaks the same protocol as the controller, so share the modules rather than copy them
+#[allow(dead_code)]
+#[path = "../arm.rs"]
+mod arm;
+#[allow(dead_code)]
+#[path = "../kinematics.rs"]
+mod kinematics;
+#[allow(dead_code)]
+#[path = "../protocol.rs"]
+mod protocol;
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use arm::{HOME_SERVO_ANGLES, SERVO_COUNT};
+use protocol::{Message, MessageType, MAX_PACKET_LENGTH, PROTOCOL_VERSION};
+
+const SERVICE_TYPE: &str = "_controller._udp.local";
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+// How long the controller may remember the advertisement, and how often it's repeated so it never forgets.
+// The controller doesn't loop its queries back, so on the same machine the announcements are all it hears.
+const MDNS_TTL: u32 = 120;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+// Announced this many times a second apart at startup, in case the first is lost
+const STARTUP_ANNOUNCEMENTS: u32 = 3;
+// DNS record types and classes
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// Set on a record's class when this is the only host that has it, and on a question's class to ask for a unicast reply
+const CACHE_FLUSH: u16 = 0x8000;
+const UNICAST_RESPONSE: u16 = 0x8000;
+// How often the simulation steps, and how long a receive waits for a packet
+const TICK: Duration = Duration::from_millis(5);
+// Raw ADC reading for a servo sitting still, and how much it rises with speed and when stalled against a load
+const ADC_IDLE: f64 = 400.0;
+const ADC_PER_SPEED: f64 = 8.0;
+const ADC_STALLED: f64 = 3000.0;
+const ADC_NOISE: f64 = 20.0;
+
+#[derive(Parser, Debug)]
+#[command(about = "Simulates the ESP32 on the arm for offline development")]
+struct Args {
+    /// UDP port to listen for servo commands on
+    #[arg(long, default_value_t = 1234)]
+    port: u16,
+    /// Instance name advertised over mDNS
+    #[arg(long, default_value = "arm-sim")]
+    name: String,
+    /// Number of servos fitted, the rest ignore commands. Advertised over mDNS and in the Hello.
+    #[arg(long, default_value_t = SERVO_COUNT)]
+    servos: usize,
+    /// Firmware version to advertise
+    #[arg(long, default_value = env!("CARGO_PKG_VERSION"))]
+    firmware: String,
+    /// How fast the servos move, in wire units per second
+    #[arg(long, default_value_t = 120.0)]
+    slew_rate: f64,
+    /// Feedback and ADC reports sent per second
+    #[arg(long, default_value_t = 20.0)]
+    feedback_rate: f64,
+    /// Delay added to every reply
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+    /// Extra random delay of up to this much, which lets replies arrive out of order
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+    /// Chance of dropping each packet, in either direction
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+    /// Chance of flipping a bit in each reply, to exercise the checksum
+    #[arg(long, default_value_t = 0.0)]
+    corrupt: f64,
+    /// Servo, in wire order, that is jammed and never moves. Can be given more than once.
+    #[arg(long)]
+    stall: Vec<usize>,
+    /// Chance per second of each servo jamming for a while
+    #[arg(long, default_value_t = 0.0)]
+    stall_chance: f64,
+    /// How long a random jam lasts
+    #[arg(long, default_value_t = 1000)]
+    stall_ms: u64,
+}
+
+struct Servo {
+    position: f64,
+    target: f64,
+    // Speed over the last step, in wire units per second, for the synthetic ADC
+    speed: f64,
+    stalled_until: Option<Instant>,
+    jammed: bool,
+}
+
+impl Servo {
+    fn is_stalled(&self, now: Instant) -> bool {
+        self.jammed || self.stalled_until.is_some_and(|until| now < until)
+    }
+}
+
+// A reply waiting out its injected latency
+struct Outgoing {
+    due: Instant,
+    packet: Vec<u8>,
+    to: SocketAddr,
+}
+
+struct Simulator {
+    args: Args,
+    socket: UdpSocket,
+    servos: Vec<Servo>,
+    sequence: u16,
+    peers: HashSet<SocketAddr>,
+    outgoing: Vec<Outgoing>,
+    // Sequence number of the last emergency stop, servo commands sent before it are ignored
+    stopped_at: Option<u16>,
+}
+
+impl Simulator {
+    fn new(args: Args) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", args.port))?;
+        socket.set_read_timeout(Some(TICK))?;
+        let servos = (0..SERVO_COUNT)
+            .map(|servo| Servo {
+                position: HOME_SERVO_ANGLES[servo],
+                target: HOME_SERVO_ANGLES[servo],
+                speed: 0.0,
+                stalled_until: None,
+                // Servos that aren't fitted never move
+                jammed: args.stall.contains(&servo) || servo >= args.servos,
+            })
+            .collect();
+        Ok(Simulator {
+            args,
+            socket,
+            servos,
+            sequence: 0,
+            peers: HashSet::new(),
+            outgoing: Vec::new(),
+            stopped_at: None,
+        })
+    }
+
+    fn run(&mut self) {
+        let feedback_interval = Duration::from_secs_f64(1.0 / self.args.feedback_rate.max(0.1));
+        let mut buffer = vec![0; MAX_PACKET_LENGTH];
+        let mut last_step = Instant::now();
+        let mut last_feedback = Instant::now();
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, from)) => self.handle(&buffer[..length], from),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(e) => log::warn!("Receive failed: {}", e),
+            }
+
+            let now = Instant::now();
+            self.step(now, (now - last_step).as_secs_f64());
+            last_step = now;
+            if now - last_feedback >= feedback_interval {
+                last_feedback = now;
+                self.report();
+            }
+            self.flush(now);
+        }
+    }
+
+    fn handle(&mut self, packet: &[u8], from: SocketAddr) {
+        if fastrand::f64() < self.args.loss {
+            log::debug!("Dropping packet from {}", from);
+            return;
+        }
+        let packet = match Message::decode(packet) {
+            Ok(packet) => packet,
+            Err(e) => {
+                log::warn!("Bad packet from {}: {}", from, e);
+                self.send(Message::Error { code: 1, message: e.to_string() }, from);
+                return;
+            }
+        };
+        // Introduce ourselves to anyone new, the same as the ESP32 does
+        if self.peers.insert(from) {
+            log::info!("New controller at {}", from);
+            self.send(self.hello(), from);
+        }
+        match packet.message {
+            Message::ServoCommand { id, .. } if self.stopped_at.is_some_and(|stop| packet.sequence.wrapping_sub(stop) as i16 <= 0) => {
+                log::info!("Ignoring command {} from before the emergency stop", id);
+            }
+            Message::ServoCommand { id, servos } => {
+                log::debug!("Command {} from {}: {:?}", id, from, servos);
+                for (servo, target) in self.servos.iter_mut().zip(servos) {
+                    servo.target = target as f64;
+                }
+                self.send(Message::Ack { message_type: MessageType::ServoCommand as u8, id }, from);
+            }
+            Message::Hello { .. } => {
+                self.send(self.hello(), from);
+            }
+            Message::Ping { id } => {
+                self.send(Message::Pong { id }, from);
+            }
+            Message::EmergencyStop { id } => {
+                log::warn!("Emergency stop {} from {}", id, from);
+                // Relaxed servos stay wherever they are
+                for servo in self.servos.iter_mut() {
+                    servo.target = servo.position;
+                }
+                self.stopped_at = Some(packet.sequence);
+                self.send(Message::Ack { message_type: MessageType::EmergencyStop as u8, id }, from);
+            }
+            message => log::debug!("Ignoring {:?} from {}", message.message_type(), from),
+        }
+    }
+
+    fn hello(&self) -> Message {
+        Message::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            servo_count: self.args.servos as u8,
+            name: self.args.name.clone(),
+        }
+    }
+
+    // Moves every servo towards its target at the slew rate, unless it is stalled
+    fn step(&mut self, now: Instant, elapsed: f64) {
+        let stall_chance = self.args.stall_chance * elapsed;
+        for servo in self.servos.iter_mut() {
+            if servo.stalled_until.is_some_and(|until| now >= until) {
+                servo.stalled_until = None;
+            }
+            if !servo.is_stalled(now) && fastrand::f64() < stall_chance {
+                log::info!("Servo stalled");
+                servo.stalled_until = Some(now + Duration::from_millis(self.args.stall_ms));
+            }
+            if servo.is_stalled(now) {
+                servo.speed = 0.0;
+                continue;
+            }
+            let max_move = self.args.slew_rate * elapsed;
+            let movement = (servo.target - servo.position).clamp(-max_move, max_move);
+            servo.position += movement;
+            servo.speed = if elapsed > 0.0 { movement.abs() / elapsed } else { 0.0 };
+        }
+    }
+
+    // Sends the servo positions and one ADC reading per servo to every controller we've heard from
+    fn report(&mut self) {
+        let now = Instant::now();
+        let servos: [u16; SERVO_COUNT] = std::array::from_fn(|servo| self.servos[servo].position.round().clamp(0.0, u16::MAX as f64) as u16);
+        let adc: Vec<u16> = self
+            .servos
+            .iter()
+            .take(self.args.servos)
+            .map(|servo| {
+                // A servo stalled short of its target draws a lot of current, like a real one would
+                let stalled = servo.is_stalled(now) && (servo.target - servo.position).abs() > 0.5;
+                let reading = ADC_IDLE + ADC_PER_SPEED * servo.speed + if stalled { ADC_STALLED } else { 0.0 };
+                (reading + (fastrand::f64() - 0.5) * 2.0 * ADC_NOISE).clamp(0.0, 4095.0) as u16
+            })
+            .collect();
+        for peer in self.peers.clone() {
+            self.send(Message::ServoFeedback { servos }, peer);
+            self.send(Message::SensorReport { adc: adc.clone() }, peer);
+        }
+    }
+
+    // Queues a reply, applying the injected loss, corruption and latency
+    fn send(&mut self, message: Message, to: SocketAddr) {
+        let mut packet = match message.encode(self.sequence) {
+            Ok(packet) => packet,
+            Err(e) => {
+                log::error!("Unable to encode {:?}: {}", message.message_type(), e);
+                return;
+            }
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        if fastrand::f64() < self.args.loss {
+            return;
+        }
+        if fastrand::f64() < self.args.corrupt {
+            let bit = fastrand::usize(..packet.len() * 8);
+            packet[bit / 8] ^= 1 << (bit % 8);
+        }
+        let jitter = if self.args.jitter_ms > 0 { fastrand::u64(..=self.args.jitter_ms) } else { 0 };
+        let due = Instant::now() + Duration::from_millis(self.args.latency_ms + jitter);
+        self.outgoing.push(Outgoing { due, packet, to });
+    }
+
+    fn flush(&mut self, now: Instant) {
+        let (ready, waiting) = std::mem::take(&mut self.outgoing).into_iter().partition(|outgoing| outgoing.due <= now);
+        self.outgoing = waiting;
+        for outgoing in ready.iter() {
+            if let Err(e) = self.socket.send_to(&outgoing.packet, outgoing.to) {
+                log::warn!("Failed to send to {}: {}", outgoing.to, e);
+            }
+        }
+    }
+}
+
+// The records the simulator answers for over mDNS, the same ones the ESP32 advertises
+struct Advertisement {
+    service: String,
+    instance: String,
+    host: String,
+    port: u16,
+    address: Ipv4Addr,
+    txt: Vec<String>,
+}
+
+impl Advertisement {
+    fn new(args: &Args, address: Ipv4Addr) -> Self {
+        let txt = [
+            ("fw", args.firmware.clone()),
+            ("proto", PROTOCOL_VERSION.to_string()),
+            ("servos", args.servos.to_string()),
+            ("msgs", "01,02,03,10,11,12,20,30,31".to_owned()),
+            ("adc", args.servos.to_string()),
+        ];
+        Advertisement {
+            service: SERVICE_TYPE.to_owned(),
+            instance: format!("{}.{}", args.name, SERVICE_TYPE),
+            host: format!("{}.local", args.name),
+            port: args.port,
+            address,
+            txt: txt.iter().map(|(key, value)| format!("{}={}", key, value)).collect(),
+        }
+    }
+
+    // None unless the packet is a query for something advertised here, otherwise whether it asked for a unicast reply
+    fn asked_by(&self, packet: &[u8]) -> Option<bool> {
+        let header = packet.get(..12)?;
+        // Responses from other hosts, including the controller's own answers, aren't questions
+        if header[2] & 0x80 != 0 {
+            return None;
+        }
+        let questions = u16::from_be_bytes([header[4], header[5]]);
+        let mut offset = 12;
+        let mut asked = None;
+        for _ in 0..questions {
+            let (name, end) = read_name(packet, offset)?;
+            let fields = packet.get(end..end + 4)?;
+            let (kind, class) = (u16::from_be_bytes([fields[0], fields[1]]), u16::from_be_bytes([fields[2], fields[3]]));
+            offset = end + 4;
+            let wanted = match kind {
+                TYPE_PTR => name.eq_ignore_ascii_case(&self.service),
+                TYPE_SRV | TYPE_TXT => name.eq_ignore_ascii_case(&self.instance),
+                TYPE_A => name.eq_ignore_ascii_case(&self.host),
+                TYPE_ANY => [&self.service, &self.instance, &self.host].iter().any(|ours| name.eq_ignore_ascii_case(ours)),
+                _ => false,
+            };
+            if wanted {
+                asked = Some(asked.unwrap_or(true) && class & UNICAST_RESPONSE != 0);
+            }
+        }
+        asked
+    }
+
+    // Every record in one response: the PTR the controller browses for as the answer, and the rest as additional records
+    fn response(&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        // ID 0, an authoritative answer, no questions, one answer, three additional records
+        for field in [0, 0x8400, 0, 1, 0, 3] {
+            packet.extend_from_slice(&u16::to_be_bytes(field));
+        }
+        let mut instance = Vec::new();
+        write_name(&mut instance, &self.instance);
+        write_record(&mut packet, &self.service, TYPE_PTR, CLASS_IN, &instance);
+
+        let mut srv = Vec::new();
+        // Priority and weight, then the port
+        for field in [0, 0, self.port] {
+            srv.extend_from_slice(&field.to_be_bytes());
+        }
+        write_name(&mut srv, &self.host);
+        write_record(&mut packet, &self.instance, TYPE_SRV, CLASS_IN | CACHE_FLUSH, &srv);
+
+        let mut txt = Vec::new();
+        for entry in &self.txt {
+            txt.push(entry.len().min(255) as u8);
+            txt.extend_from_slice(&entry.as_bytes()[..entry.len().min(255)]);
+        }
+        write_record(&mut packet, &self.instance, TYPE_TXT, CLASS_IN | CACHE_FLUSH, &txt);
+        write_record(&mut packet, &self.host, TYPE_A, CLASS_IN | CACHE_FLUSH, &self.address.octets());
+        packet
+    }
+}
+
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        packet.push(label.len().min(63) as u8);
+        packet.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+    }
+    packet.push(0);
+}
+
+fn write_record(packet: &mut Vec<u8>, name: &str, kind: u16, class: u16, data: &[u8]) {
+    write_name(packet, name);
+    packet.extend_from_slice(&kind.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet.extend_from_slice(&MDNS_TTL.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+// Reads a possibly compressed name, giving it back dotted without the trailing dot along with where the record carries on
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Each pointer has to go backwards, which stops a malicious packet looping forever
+    let mut limit = offset;
+    loop {
+        let length = *packet.get(offset)? as usize;
+        match length {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            0xc0..=0xff => {
+                let pointer = u16::from_be_bytes([length as u8 & 0x3f, *packet.get(offset + 1)?]) as usize;
+                if pointer >= limit {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                limit = pointer;
+                offset = pointer;
+            }
+            1..=63 => {
+                let label = packet.get(offset + 1..offset + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + length;
+            }
+            _ => return None,
+        }
+    }
+}
+
+// The address the controller should reach the simulator on, the one multicast goes out of
+fn local_address() -> Ipv4Addr {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0));
+    match socket.and_then(|socket| socket.connect((MDNS_GROUP, MDNS_PORT)).and_then(|_| socket.local_addr())) {
+        Ok(SocketAddr::V4(address)) if !address.ip().is_unspecified() => *address.ip(),
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+fn mdns_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Share the port with the controller's browser and any other responder on this machine
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+    socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    // A controller on this machine has to hear the announcements too
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_multicast_ttl_v4(255)?;
+    Ok(socket.into())
+}
+
+// Advertises the simulator the same way the ESP32 advertises itself, so discovery finds it and knows what it can do.
+// Announces at startup and every so often after, and answers queries for it, until the process exits.
+fn advertise(args: &Args) -> std::io::Result<()> {
+    let socket = mdns_socket()?;
+    let advertisement = Advertisement::new(args, local_address());
+    log::info!("Advertising {} at {}:{}", advertisement.instance, advertisement.address, advertisement.port);
+    std::thread::spawn(move || {
+        let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+        let mut buffer = vec![0; 9000];
+        let mut announcements = 0;
+        let mut next_announcement = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next_announcement {
+                if let Err(e) = socket.send_to(&advertisement.response(), group) {
+                    log::warn!("mDNS announcement failed: {}", e);
+                }
+                announcements += 1;
+                next_announcement = now + if announcements < STARTUP_ANNOUNCEMENTS { Duration::from_secs(1) } else { ANNOUNCE_INTERVAL };
+            }
+            let _ = socket.set_read_timeout(Some(next_announcement.saturating_duration_since(now).max(Duration::from_millis(1))));
+            match socket.recv_from(&mut buffer) {
+                Ok((length, from)) => {
+                    let Some(unicast) = advertisement.asked_by(&buffer[..length]) else {
+                        continue;
+                    };
+                    // Queries from anything other than the mDNS port are one-shot lookups that only listen for a direct reply
+                    let to = if unicast || from.port() != MDNS_PORT { from } else { group };
+                    if let Err(e) = socket.send_to(&advertisement.response(), to) {
+                        log::warn!("mDNS reply to {} failed: {}", to, e);
+                    }
+                }
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(e) => log::warn!("mDNS receive failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    if args.servos == 0 || args.servos > SERVO_COUNT {
+        eprintln!("The arm can have between 1 and {} servos", SERVO_COUNT);
+        std::process::exit(2);
+    }
+    for servo in &args.stall {
+        if *servo >= SERVO_COUNT {
+            eprintln!("There is no servo {}, servos are numbered 0 to {}", servo, SERVO_COUNT - 1);
+            std::process::exit(2);
+        }
+    }
+
+    if let Err(e) = advertise(&args) {
+        log::warn!("Not advertising over mDNS: {}", e);
+    }
+
+    let port = args.port;
+    let mut simulator = match Simulator::new(args) {
+        Ok(simulator) => simulator,
+        Err(e) => {
+            eprintln!("Unable to listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Simulated arm listening on port {}", port);
+    simulator.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertisement() -> Advertisement {
+        Advertisement::new(&Args::parse_from(["arm_sim", "--name", "bench-arm", "--port", "4321"]), Ipv4Addr::new(192, 168, 1, 20))
+    }
+
+    fn query(name: &str, kind: u16, class: u16) -> Vec<u8> {
+        let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        write_name(&mut packet, name);
+        packet.extend_from_slice(&kind.to_be_bytes());
+        packet.extend_from_slice(&class.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn answers_queries_for_what_it_advertises() {
+        let advertisement = advertisement();
+        assert_eq!(advertisement.asked_by(&query("_controller._udp.local", TYPE_PTR, CLASS_IN)), Some(false));
+        assert_eq!(advertisement.asked_by(&query("bench-arm._controller._udp.local", TYPE_SRV, CLASS_IN)), Some(false));
+        assert_eq!(advertisement.asked_by(&query("BENCH-ARM.local", TYPE_A, CLASS_IN | UNICAST_RESPONSE)), Some(true));
+        assert_eq!(advertisement.asked_by(&query("_printer._tcp.local", TYPE_PTR, CLASS_IN)), None);
+        assert_eq!(advertisement.asked_by(&query("bench-arm.local", TYPE_PTR, CLASS_IN)), None);
+        // Answers from other hosts aren't questions, even if they name the service
+        let mut response = query("_controller._udp.local", TYPE_PTR, CLASS_IN);
+        response[2] = 0x84;
+        assert_eq!(advertisement.asked_by(&response), None);
+        assert_eq!(advertisement.asked_by(&[0; 5]), None);
+    }
+
+    #[test]
+    fn follows_compressed_names_but_not_loops() {
+        let advertisement = advertisement();
+        // Two questions, the second ending with a pointer into the first
+        let mut packet = query("_printer._tcp.local", TYPE_PTR, CLASS_IN);
+        packet[5] = 2;
+        packet.extend_from_slice(&[11]);
+        packet.extend_from_slice(b"_controller");
+        packet.extend_from_slice(&[4]);
+        packet.extend_from_slice(b"_udp");
+        packet.extend_from_slice(&[0xc0, 12 + 14]);
+        packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        assert_eq!(read_name(&packet, 37), Some(("_controller._udp.local".to_owned(), 56)));
+        assert_eq!(advertisement.asked_by(&packet), Some(false));
+
+        let mut looping = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 12];
+        looping.extend_from_slice(&[0, TYPE_PTR as u8, 0, 1]);
+        assert_eq!(advertisement.asked_by(&looping), None);
+    }
+
+    #[test]
+    fn response_carries_every_record() {
+        let response = advertisement().response();
+        assert_eq!(&response[..12], &[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 3]);
+        let (name, end) = read_name(&response, 12).unwrap();
+        assert_eq!(name, "_controller._udp.local");
+        assert_eq!(&response[end..end + 4], &[0, TYPE_PTR as u8, 0, CLASS_IN as u8]);
+        let (instance, _) = read_name(&response, end + 10).unwrap();
+        assert_eq!(instance, "bench-arm._controller._udp.local");
+
+        let contains = |bytes: &[u8]| response.windows(bytes.len()).any(|window| window == bytes);
+        assert!(contains(&[0, 0, 0, 0, 0x10, 0xe1, 9]), "SRV for port 4321 on bench-arm.local");
+        assert!(contains(b"\x08servos=5") && contains(b"\x05adc=5"));
+        assert!(contains(&[0, 4, 192, 168, 1, 20]));
+    }
+}
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+
+use crate::arm::SERVO_COUNT;
+
+// Converts the servo angles the Arm works in into the values sent on the wire.
+// The angle is first offset and optionally inverted, then passed through the lookup table if it has at least two points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServoCalibration {
+    // Added to the angle, an inverted servo usually wants 180 here so it stays in range
+    pub offset: f64,
+    pub inverted: bool,
+    // Reference points from the angle after offset and inversion to the wire value, kept sorted by angle
+    pub points: Vec<CalibrationPoint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationPoint {
+    pub angle: f64,
+    pub wire: f64,
+}
+
+impl Default for ServoCalibration {
+    fn default() -> Self {
+        ServoCalibration {
+            offset: 0.0,
+            inverted: false,
+            points: Vec::new(),
+        }
+    }
+}
+
+impl ServoCalibration {
+    // Angle after the offset and inversion, which is what the lookup table is keyed on
+    pub fn adjust(&self, angle: f64) -> f64 {
+        self.offset + if self.inverted { -angle } else { angle }
+    }
+
+    pub fn to_wire(&self, angle: f64) -> u16 {
+        let wire = interpolate(&self.points, self.adjust(angle), |point| (point.angle, point.wire));
+        // Anything outside of u16 is sent as the nearest end of the range, like the uncalibrated `as u16` cast did
+        wire.round().clamp(0.0, u16::MAX as f64) as u16
+    }
+
+    // Inverse of to_wire, for turning feedback from the arm back into servo angles.
+    // This assumes the lookup table is monotonic, which any real servo's will be.
+    pub fn to_angle(&self, wire: u16) -> f64 {
+        let mut points = self.points.clone();
+        points.sort_by(|a, b| a.wire.total_cmp(&b.wire));
+        let adjusted = interpolate(&points, wire as f64, |point| (point.wire, point.angle));
+        let angle = adjusted - self.offset;
+        if self.inverted { -angle } else { angle }
+    }
+
+    // Adds a reference point, replacing any existing point at the same angle
+    pub fn record(&mut self, point: CalibrationPoint) {
+        self.points.retain(|existing| (existing.angle - point.angle).abs() > f64::EPSILON);
+        self.points.push(point);
+        self.points.sort_by(|a, b| a.angle.total_cmp(&b.angle));
+    }
+}
+
+// Piecewise linear lookup, clamped to the end points. With fewer than two points the value passes straight through.
+fn interpolate(points: &[CalibrationPoint], value: f64, key: impl Fn(&CalibrationPoint) -> (f64, f64)) -> f64 {
+    if points.len() < 2 {
+        return value;
+    }
+    let (first_x, first_y) = key(&points[0]);
+    let (last_x, last_y) = key(&points[points.len() - 1]);
+    if value <= first_x {
+        return first_y;
+    }
+    if value >= last_x {
+        return last_y;
+    }
+    for pair in points.windows(2) {
+        let (x0, y0) = key(&pair[0]);
+        let (x1, y1) = key(&pair[1]);
+        if value >= x0 && value <= x1 {
+            if x1 - x0 <= f64::EPSILON {
+                return y0;
+            }
+            return y0 + (y1 - y0) * (value - x0) / (x1 - x0);
+        }
+    }
+    value
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    pub servos: [ServoCalibration; SERVO_COUNT],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            servos: std::array::from_fn(|_| ServoCalibration::default()),
+        }
+    }
+}
+
+impl Calibration {
+    // Wire values for a full set of servo angles, in wire order
+    pub fn to_wire(&self, angles: &[f64; SERVO_COUNT]) -> [u16; SERVO_COUNT] {
+        std::array::from_fn(|servo| self.servos[servo].to_wire(angles[servo]))
+    }
+
+    pub fn to_angles(&self, wire: &[u16; SERVO_COUNT]) -> [f64; SERVO_COUNT] {
+        std::array::from_fn(|servo| self.servos[servo].to_angle(wire[servo]))
+    }
+}
+
+// Guided calibration of one servo. The user jogs the raw wire value until the joint physically
+// sits at each reference angle in turn, and the wizard records the wire value for each.
+pub struct CalibrationWizard {
+    pub servo: usize,
+    pub targets: Vec<f64>,
+    pub step: usize,
+    // Raw value currently being sent for the servo under calibration
+    pub wire: u16,
+    pub recorded: Vec<CalibrationPoint>,
+}
+
+impl CalibrationWizard {
+    // Reference angles are spread evenly across the servo's range, starting from the current wire value
+    pub fn new(servo: usize, start: f64, end: f64, reference_points: usize, wire: u16) -> Self {
+        let reference_points = reference_points.max(2);
+        let targets = (0..reference_points)
+            .map(|index| start + (end - start) * index as f64 / (reference_points - 1) as f64)
+            .collect();
+        CalibrationWizard {
+            servo,
+            targets,
+            step: 0,
+            wire,
+            recorded: Vec::new(),
+        }
+    }
+
+    pub fn current_target(&self) -> Option<f64> {
+        self.targets.get(self.step).copied()
+    }
+
+    pub fn jog(&mut self, amount: i32) {
+        self.wire = (self.wire as i32 + amount).clamp(0, u16::MAX as i32) as u16;
+    }
+
+    // Records the current wire value against the current reference angle and moves on
+    pub fn record(&mut self, calibration: &ServoCalibration) {
+        if let Some(target) = self.current_target() {
+            self.recorded.push(CalibrationPoint {
+                angle: calibration.adjust(target),
+                wire: self.wire as f64,
+            });
+            self.step += 1;
+        }
+    }
+
+    pub fn skip(&mut self) {
+        self.step += 1;
+    }
+
+    // Replaces the servo's lookup table with the recorded points
+    pub fn apply(&self, calibration: &mut ServoCalibration) {
+        calibration.points.clear();
+        for point in &self.recorded {
+            calibration.record(*point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Angles from 0 to 180 that don't land on whole degrees, so rounding to the wire shows up
+    fn angles() -> impl Iterator<Item = f64> {
+        (0..=24).map(|step| step as f64 * 7.5 + 0.3).filter(|angle| *angle <= 180.0)
+    }
+
+    fn table() -> Vec<CalibrationPoint> {
+        vec![
+            CalibrationPoint { angle: 0.0, wire: 500.0 },
+            CalibrationPoint { angle: 90.0, wire: 1400.0 },
+            CalibrationPoint { angle: 180.0, wire: 2500.0 },
+        ]
+    }
+
+    // Rounding to a whole wire value can move the angle by up to half a wire step
+    fn assert_round_trip(calibration: &ServoCalibration, tolerance: f64) {
+        for angle in angles() {
+            let back = calibration.to_angle(calibration.to_wire(angle));
+            assert!((back - angle).abs() <= tolerance, "{} came back as {} with {:?}", angle, back, calibration);
+        }
+    }
+
+    #[test]
+    fn offset_round_trips() {
+        let calibration = ServoCalibration { offset: 12.0, ..Default::default() };
+        assert_eq!(calibration.to_wire(90.0), 102);
+        assert_round_trip(&calibration, 0.5);
+    }
+
+    #[test]
+    fn inversion_round_trips() {
+        let calibration = ServoCalibration { offset: 180.0, inverted: true, ..Default::default() };
+        assert_eq!(calibration.to_wire(30.0), 150);
+        assert_round_trip(&calibration, 0.5);
+    }
+
+    #[test]
+    fn lookup_table_round_trips() {
+        // The shallowest segment is 10 wire values per degree
+        let calibration = ServoCalibration { points: table(), ..Default::default() };
+        assert_eq!(calibration.to_wire(45.0), 950);
+        assert_eq!(calibration.to_wire(135.0), 1950);
+        assert_round_trip(&calibration, 0.05);
+
+        let inverted = ServoCalibration { offset: 180.0, inverted: true, points: table() };
+        assert_eq!(inverted.to_wire(0.0), 2500);
+        assert_round_trip(&inverted, 0.05);
+    }
+
+    #[test]
+    fn lookup_table_clamps_to_its_end_points() {
+        let calibration = ServoCalibration { points: table(), ..Default::default() };
+        assert_eq!(calibration.to_wire(-20.0), 500);
+        assert_eq!(calibration.to_wire(200.0), 2500);
+        assert_eq!(calibration.to_angle(100), 0.0);
+        assert_eq!(calibration.to_angle(3000), 180.0);
+    }
+
+    #[test]
+    fn out_of_range_wire_values_saturate() {
+        let below = ServoCalibration { offset: -100.0, ..Default::default() };
+        assert_eq!(below.to_wire(10.0), 0);
+        let above = ServoCalibration { offset: 70_000.0, ..Default::default() };
+        assert_eq!(above.to_wire(10.0), u16::MAX);
+        let table = ServoCalibration { points: vec![CalibrationPoint { angle: 0.0, wire: 0.0 }, CalibrationPoint { angle: 1.0, wire: 100_000.0 }], ..Default::default() };
+        assert_eq!(table.to_wire(0.9), u16::MAX);
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use crate::arm::SERVO_COUNT;
+use crate::protocol::{MessageType, PROTOCOL_VERSION};
+
+// What a device says it can do, from its mDNS TXT record and its Hello. Anything it doesn't say is None,
+// and the controller assumes the full five servo arm.
+//
+// TXT keys:
+//   fw      firmware version, e.g. "1.4.2"
+//   proto   protocol version it speaks
+//   servos  number of servos fitted
+//   msgs    message types it sends or understands, as comma separated hex bytes, e.g. "01,02,03,10,11,12,20,30,31"
+//   adc     number of ADC channels it reports
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub firmware: Option<String>,
+    pub protocol_version: Option<u8>,
+    pub servo_count: Option<usize>,
+    pub message_types: Option<Vec<MessageType>>,
+    pub adc_channels: Option<usize>,
+    // Entries that were there but couldn't be understood, so they can be shown rather than silently ignored
+    pub invalid: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn from_txt(txt: &BTreeMap<String, String>) -> Self {
+        let mut capabilities = Capabilities::default();
+        for (key, value) in txt {
+            let parsed = match key.as_str() {
+                "fw" => {
+                    capabilities.firmware = Some(value.clone());
+                    true
+                }
+                "proto" => value.parse().map(|version| capabilities.protocol_version = Some(version)).is_ok(),
+                "servos" => value.parse().map(|count| capabilities.servo_count = Some(count)).is_ok(),
+                "adc" => value.parse().map(|count| capabilities.adc_channels = Some(count)).is_ok(),
+                "msgs" => parse_message_types(value).map(|types| capabilities.message_types = Some(types)).is_some(),
+                // Unknown keys are left for other tools
+                _ => true,
+            };
+            if !parsed {
+                capabilities.invalid.push(format!("{}={}", key, value));
+            }
+        }
+        capabilities
+    }
+
+    // A Hello comes from the device itself, so it wins over what mDNS said
+    pub fn apply_hello(&mut self, protocol_version: u8, servo_count: u8) {
+        self.protocol_version = Some(protocol_version);
+        self.servo_count = Some(servo_count as usize);
+    }
+
+    // Servos beyond the ones fitted aren't worth showing
+    pub fn has_servo(&self, servo: usize) -> bool {
+        servo < self.servo_count.unwrap_or(SERVO_COUNT)
+    }
+
+    pub fn supports(&self, message_type: MessageType) -> bool {
+        self.message_types.as_ref().is_none_or(|types| types.contains(&message_type))
+    }
+
+    // Anything about the device the user should know before relying on it
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(version) = self.protocol_version {
+            if version != PROTOCOL_VERSION {
+                warnings.push(format!("Device speaks protocol version {}, but this controller speaks version {}", version, PROTOCOL_VERSION));
+            }
+        }
+        if let Some(count) = self.servo_count {
+            if count > SERVO_COUNT {
+                warnings.push(format!("Device has {} servos, only the first {} can be controlled", count, SERVO_COUNT));
+            }
+        }
+        if !self.supports(MessageType::ServoCommand) {
+            warnings.push("Device doesn't accept servo commands".to_owned());
+        } else if !self.supports(MessageType::Ack) {
+            warnings.push("Device doesn't acknowledge commands, so every command will show as failed".to_owned());
+        }
+        if !self.supports(MessageType::EmergencyStop) {
+            warnings.push("Device doesn't support the emergency stop, E-STOP can't stop it".to_owned());
+        }
+        if !self.supports(MessageType::Ping) {
+            warnings.push("Device doesn't answer pings, so the link health can't be monitored".to_owned());
+        }
+        for entry in &self.invalid {
+            warnings.push(format!("Couldn't understand TXT entry {}", entry));
+        }
+        warnings
+    }
+}
+
+fn parse_message_types(value: &str) -> Option<Vec<MessageType>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| u8::from_str_radix(entry.trim_start_matches("0x"), 16).ok().and_then(MessageType::from_byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_MESSAGES: &str = "01,02,03,10,11,12,20,30,31";
+
+    fn txt(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_every_known_key() {
+        let capabilities = Capabilities::from_txt(&txt(&[
+            ("fw", "1.4.2"),
+            ("proto", "1"),
+            ("servos", "4"),
+            ("msgs", "0x10, 11,30"),
+            ("adc", "2"),
+            ("colour", "blue"),
+        ]));
+        assert_eq!(capabilities.firmware.as_deref(), Some("1.4.2"));
+        assert_eq!(capabilities.protocol_version, Some(1));
+        assert_eq!(capabilities.servo_count, Some(4));
+        assert_eq!(capabilities.message_types, Some(vec![MessageType::ServoCommand, MessageType::ServoFeedback, MessageType::Ack]));
+        assert_eq!(capabilities.adc_channels, Some(2));
+        assert!(capabilities.invalid.is_empty());
+        assert!(capabilities.has_servo(3));
+        assert!(!capabilities.has_servo(4));
+    }
+
+    #[test]
+    fn unparseable_entries_are_kept_as_invalid() {
+        let capabilities = Capabilities::from_txt(&txt(&[("proto", "two"), ("msgs", "10,zz"), ("servos", "-1"), ("adc", "")]));
+        assert_eq!(capabilities.protocol_version, None);
+        assert_eq!(capabilities.message_types, None);
+        assert_eq!(capabilities.servo_count, None);
+        assert_eq!(capabilities.adc_channels, None);
+        assert_eq!(capabilities.invalid, ["adc=", "msgs=10,zz", "proto=two", "servos=-1"]);
+        // An unknown message type byte is as bad as one that isn't hex
+        let unknown = Capabilities::from_txt(&txt(&[("msgs", "10,7f")]));
+        assert_eq!(unknown.invalid, ["msgs=10,7f"]);
+    }
+
+    #[test]
+    fn nothing_said_means_the_full_arm() {
+        let capabilities = Capabilities::from_txt(&BTreeMap::new());
+        assert!(capabilities.has_servo(SERVO_COUNT - 1));
+        assert!(capabilities.supports(MessageType::ServoCommand));
+        assert!(capabilities.warnings().is_empty());
+    }
+
+    #[test]
+    fn hello_overrides_the_txt_record() {
+        let mut capabilities = Capabilities::from_txt(&txt(&[("proto", "9"), ("servos", "3")]));
+        capabilities.apply_hello(PROTOCOL_VERSION, 5);
+        assert_eq!((capabilities.protocol_version, capabilities.servo_count), (Some(PROTOCOL_VERSION), Some(5)));
+    }
+
+    #[test]
+    fn warns_about_what_the_device_cant_do() {
+        let full = Capabilities::from_txt(&txt(&[("proto", &PROTOCOL_VERSION.to_string()), ("servos", "5"), ("msgs", ALL_MESSAGES)]));
+        assert!(full.warnings().is_empty(), "{:?}", full.warnings());
+
+        let mismatched = Capabilities::from_txt(&txt(&[("proto", &(PROTOCOL_VERSION + 1).to_string()), ("servos", "7"), ("msgs", ALL_MESSAGES)]));
+        let warnings = mismatched.warnings();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].contains("protocol version"));
+        assert!(warnings[1].contains("7 servos"));
+
+        let no_commands = Capabilities::from_txt(&txt(&[("msgs", "01,11,20")]));
+        assert!(no_commands.warnings().iter().any(|warning| warning.contains("doesn't accept servo commands")));
+        let no_acks = Capabilities::from_txt(&txt(&[("msgs", "01,10,11,20")]));
+        assert!(no_acks.warnings().iter().any(|warning| warning.contains("doesn't acknowledge")));
+        let no_pings = Capabilities::from_txt(&txt(&[("msgs", "01,10,11,12,20,30,31")]));
+        assert!(no_pings.warnings().iter().any(|warning| warning.contains("doesn't answer pings")));
+        let no_estop = Capabilities::from_txt(&txt(&[("msgs", "01,02,03,10,11,20,30,31")]));
+        assert!(no_estop.warnings().iter().any(|warning| warning.contains("emergency stop")));
+
+        let invalid = Capabilities::from_txt(&txt(&[("msgs", ALL_MESSAGES), ("adc", "lots")]));
+        assert_eq!(invalid.warnings(), ["Couldn't understand TXT entry adc=lots"]);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::arm::{IkOptions, ServoLimit, SERVO_COUNT, SERVO_NAMES};
+use crate::calibration::Calibration;
+use crate::delivery::RetryPolicy;
+use crate::transport::DEFAULT_BIND_ADDRESS;
+use crate::kinematics::ChainDescription;
+use crate::mqtt::MqttSettings;
+use crate::ros::RosSettings;
+
+// Bump this whenever the layout of the file changes, and add a step to migrate() that upgrades the previous version
+pub const CONFIG_VERSION: u32 = 2;
+pub const DEFAULT_PROFILE: &str = "Default";
+pub const DEFAULT_SERVICE: &str = "_controller._udp.local";
+
+// Everything the controller remembers between runs, stored as TOML in the platform config directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub version: u32,
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// Settings for one arm on the bench
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub send_to: String,
+    // Local address the controller listens on, so several controllers can share a machine
+    pub bind_address: String,
+    // mDNS service types to browse for, e.g. the arm and the sensor nodes
+    pub services: Vec<String>,
+    // Servo limits in wire order
+    pub servo_limits: [ServoLimit; SERVO_COUNT],
+    pub chain: ChainDescription,
+    pub calibration: Calibration,
+    pub ik: IkOptions,
+    pub retry: RetryPolicy,
+    pub mqtt: MqttSettings,
+    pub ros: RosSettings,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            send_to: "0.0.0.0:1234".to_owned(),
+            bind_address: DEFAULT_BIND_ADDRESS.to_owned(),
+            services: vec![DEFAULT_SERVICE.to_owned()],
+            servo_limits: [ServoLimit { min: 0.0, max: 180.0 }; SERVO_COUNT],
+            chain: ChainDescription::five_servo(1.0, 1.0, 0.0),
+            calibration: Calibration::default(),
+            ik: IkOptions::default(),
+            retry: RetryPolicy::default(),
+            mqtt: MqttSettings::default(),
+            ros: RosSettings::default(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(DEFAULT_PROFILE.to_owned(), Profile::default());
+        Config {
+            version: CONFIG_VERSION,
+            active_profile: DEFAULT_PROFILE.to_owned(),
+            profiles,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|directory| directory.join("controller").join("config.toml"))
+    }
+
+    // Loads the config file, falling back to the defaults if there isn't one or it can't be read
+    pub fn load_or_default() -> Self {
+        match Config::load() {
+            Ok(Some(config)) => config,
+            Ok(None) => Config::default(),
+            Err(e) => {
+                log::warn!("Ignoring config file: {}", e);
+                Config::default()
+            }
+        }
+    }
+
+    pub fn load() -> Result<Option<Self>, ConfigError> {
+        let Some(path) = Config::path() else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)?;
+        Config::parse(&contents).map(Some)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut value: toml::Table = toml::from_str(contents)?;
+        let version = match value.get("version") {
+            Some(toml::Value::Integer(version)) => u32::try_from(*version).map_err(|_| ConfigError::UnsupportedVersion(0))?,
+            Some(_) => return Err(ConfigError::UnsupportedVersion(0)),
+            None => 0,
+        };
+        if version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+        migrate(&mut value, version)?;
+
+        let mut config: Config = value.try_into()?;
+        if config.profiles.is_empty() {
+            config.profiles.insert(DEFAULT_PROFILE.to_owned(), Profile::default());
+        }
+        if !config.profiles.contains_key(&config.active_profile) {
+            config.active_profile = config.profiles.keys().next().cloned().unwrap_or_else(|| DEFAULT_PROFILE.to_owned());
+        }
+        for (name, profile) in &config.profiles {
+            profile.chain.validate().map_err(|e| ConfigError::Invalid(format!("profile {}: {}", name, e)))?;
+            for (servo, limit) in profile.servo_limits.iter().enumerate() {
+                limit.validate().map_err(|e| ConfigError::Invalid(format!("profile {}: {} servo {}", name, SERVO_NAMES[servo], e)))?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self) -> Result<PathBuf, ConfigError> {
+        let path = Config::path().ok_or(ConfigError::NoConfigDirectory)?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(&path, toml::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    pub fn active(&self) -> Profile {
+        self.profiles.get(&self.active_profile).cloned().unwrap_or_default()
+    }
+}
+
+// Upgrades an older config, one version at a time, to CONFIG_VERSION
+fn migrate(value: &mut toml::Table, from: u32) -> Result<(), ConfigError> {
+    let mut version = from;
+    while version < CONFIG_VERSION {
+        match version {
+            // A file without a version is a single profile written by hand, so wrap it up as the default profile
+            0 => {
+                let profile = std::mem::take(value);
+                let mut profiles = toml::Table::new();
+                profiles.insert(DEFAULT_PROFILE.to_owned(), toml::Value::Table(profile));
+                value.insert("active_profile".to_owned(), toml::Value::String(DEFAULT_PROFILE.to_owned()));
+                value.insert("profiles".to_owned(), toml::Value::Table(profiles));
+            }
+            // Profiles could only browse for one service type, now they can browse for several
+            1 => {
+                if let Some(toml::Value::Table(profiles)) = value.get_mut("profiles") {
+                    for (_, profile) in profiles.iter_mut() {
+                        let toml::Value::Table(profile) = profile else {
+                            continue;
+                        };
+                        if let Some(service) = profile.remove("service") {
+                            profile.insert("services".to_owned(), toml::Value::Array(vec![service]));
+                        }
+                    }
+                }
+            }
+            _ => return Err(ConfigError::UnsupportedVersion(version)),
+        }
+        version += 1;
+        value.insert("version".to_owned(), toml::Value::Integer(version as i64));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Write(toml::ser::Error),
+    UnsupportedVersion(u32),
+    Invalid(String),
+    NoConfigDirectory,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Unable to access config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "Unable to parse config file: {}", e),
+            ConfigError::Write(e) => write!(f, "Unable to write config file: {}", e),
+            ConfigError::UnsupportedVersion(version) => write!(f, "Config version {} is not supported, expected at most {}", version, CONFIG_VERSION),
+            ConfigError::Invalid(reason) => write!(f, "Invalid config: {}", reason),
+            ConfigError::NoConfigDirectory => write!(f, "No config directory on this platform"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(e: toml::ser::Error) -> Self {
+        ConfigError::Write(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: &str = "servo_limits = [
+    { min = 10.0, max = 170.0 },
+    { min = 20.0, max = 160.0 },
+    { min = 0.0, max = 180.0 },
+    { min = 0.0, max = 150.0 },
+    { min = 45.0, max = 135.0 },
+]";
+
+    #[test]
+    fn unversioned_file_becomes_the_default_profile() {
+        let contents = format!(
+            "send_to = \"192.168.1.20:1234\"
+bind_address = \"0.0.0.0:4321\"
+service = \"_arm._udp.local\"
+{}
+",
+            LIMITS
+        );
+        let config = Config::parse(&contents).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.active_profile, DEFAULT_PROFILE);
+        assert_eq!(config.profiles.len(), 1);
+        let profile = config.active();
+        assert_eq!(profile.send_to, "192.168.1.20:1234");
+        assert_eq!(profile.bind_address, "0.0.0.0:4321");
+        assert_eq!(profile.services, vec!["_arm._udp.local".to_owned()]);
+        assert_eq!(profile.servo_limits[0], ServoLimit { min: 10.0, max: 170.0 });
+        assert_eq!(profile.servo_limits[4], ServoLimit { min: 45.0, max: 135.0 });
+    }
+
+    #[test]
+    fn version_1_profiles_browse_for_their_one_service() {
+        let contents = format!(
+            "version = 1
+active_profile = \"Lab\"
+
+[profiles.Bench]
+send_to = \"10.0.0.5:1234\"
+service = \"_bench._udp.local\"
+
+[profiles.Lab]
+send_to = \"10.0.0.6:1234\"
+service = \"_lab._udp.local\"
+{}
+",
+            LIMITS
+        );
+        let config = Config::parse(&contents).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.active_profile, "Lab");
+        let bench = &config.profiles["Bench"];
+        assert_eq!(bench.send_to, "10.0.0.5:1234");
+        assert_eq!(bench.services, vec!["_bench._udp.local".to_owned()]);
+        assert_eq!(bench.servo_limits, Profile::default().servo_limits);
+        let lab = &config.profiles["Lab"];
+        assert_eq!(lab.send_to, "10.0.0.6:1234");
+        assert_eq!(lab.services, vec!["_lab._udp.local".to_owned()]);
+        assert_eq!(lab.servo_limits[1], ServoLimit { min: 20.0, max: 160.0 });
+    }
+
+    #[test]
+    fn missing_active_profile_falls_back_to_the_first() {
+        let config = Config::parse("version = 1\nactive_profile = \"Gone\"\n\n[profiles.Bench]\n[profiles.Lab]\n").unwrap();
+        assert_eq!(config.active_profile, "Bench");
+        let empty = Config::parse("version = 1\nactive_profile = \"Gone\"\n\n[profiles]\n").unwrap();
+        assert_eq!(empty.active_profile, DEFAULT_PROFILE);
+        assert_eq!(empty.active(), Profile::default());
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let mut config = Config::default();
+        config.profiles.get_mut(DEFAULT_PROFILE).unwrap().services = vec!["_a._udp.local".to_owned(), "_b._udp.local".to_owned()];
+        let contents = toml::to_string_pretty(&config).unwrap();
+        assert_eq!(Config::parse(&contents).unwrap(), config);
+    }
+
+    #[test]
+    fn reversed_or_non_finite_limits_are_rejected() {
+        let reversed = LIMITS.replace("{ min = 20.0, max = 160.0 }", "{ min = 160.0, max = 20.0 }");
+        let error = Config::parse(&reversed).unwrap_err().to_string();
+        assert!(error.contains("Shoulder servo minimum 160° is above its maximum 20°"), "{}", error);
+        let infinite = LIMITS.replace("{ min = 45.0, max = 135.0 }", "{ min = 45.0, max = inf }");
+        assert!(Config::parse(&infinite).unwrap_err().to_string().contains("Lower servo limits 45° to inf° must both be numbers"));
+        let nan = LIMITS.replace("{ min = 10.0, max = 170.0 }", "{ min = nan, max = 170.0 }");
+        assert!(matches!(Config::parse(&nan), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn newer_or_malformed_versions_are_rejected() {
+        let newer = format!("version = {}\nactive_profile = \"Default\"\n", CONFIG_VERSION + 1);
+        assert!(matches!(Config::parse(&newer), Err(ConfigError::UnsupportedVersion(version)) if version == CONFIG_VERSION + 1));
+        assert!(matches!(Config::parse("version = -1"), Err(ConfigError::UnsupportedVersion(0))));
+        assert!(matches!(Config::parse("version = \"2\""), Err(ConfigError::UnsupportedVersion(0))));
+    }
+}
@@ -1,96 +1,265 @@
 
-use std::borrow::Borrow;
 use crate::plot::{generate_plot};
 use crate::network;
-use crate::arm;
-use std::net::UdpSocket;
+use crate::arm::{self, ServoLimit};
+use crate::kinematics::ChainDescription;
+use crate::calibration::{Calibration, CalibrationWizard};
+use crate::config::{Config, ConfigError, Profile};
+use crate::protocol::{Message, MessageType, PacketStats};
+use crate::delivery::{Delivery, RetryPolicy};
+use crate::telemetry::Telemetry;
+use crate::capabilities::Capabilities;
+use crate::health::{LinkHealth, LinkStatus, HISTORY_SECONDS};
+use crate::estop::EmergencyStop;
+use crate::recording::{Playback, Trajectory, MAX_SPEED, MIN_SPEED, SAMPLE_INTERVAL};
+use crate::sequencer::{Easing, Interpolation, Keyframe, Plan, Pose, Sequence};
+use crate::api::{ApiCommand, ApiError, ApiLink, ApiReply, ServoAngles, Solver};
+use crate::stream::{EventData, StreamEvent};
+use crate::ros;
+use crate::transport::{Transport, TransportError, TransportEvent};
+use std::fs;
+use std::net::SocketAddr;
 use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Local;
 use eframe::egui;
+use axum::http::StatusCode;
+use serde_json::json;
+use egui_plot::{Legend, Line, Plot, PlotPoints};
 use eframe::egui::{Ui, Separator, ComboBox, Slider, Sense, vec2, WidgetType, WidgetInfo, DragValue, Response, pos2, lerp, Widget, Image, ColorImage, TextureOptions};
-use plotters::prelude::*;
-
-use plotters::drawing::IntoDrawingArea;
-use plotters::element::Rectangle;
 use crate::models::{SharedState, Mode};
-const MOVE_SCALE: f32 = 0.01;
-const SCROLL_SCALE: f32 = 0.001;
+// Highest servo limit that can be set in Settings
+const MAX_SERVO_LIMIT: f64 = 270.0;
 
 pub struct Controller {
     ip_addr_string: String,
     is_ip_addr: bool,
     send_to: String,
-    udp_socket: UdpSocket,
-    servo_top_range: RangeInclusive<f64>,
-    servo_shoulder_range: RangeInclusive<f64>,
-    servo_upper_range: RangeInclusive<f64>,
-    servo_elbow_range: RangeInclusive<f64>,
-    servo_lower_range: RangeInclusive<f64>,
+    transport: Transport,
+    bind_address: String,
+    bind_error: Option<String>,
+    selected_device: Option<String>,
+    // Protocol version and servo count from the last Hello
+    hello: Option<(u8, u8)>,
+    health: LinkHealth,
+    estop: EmergencyStop,
+    recording: Option<Trajectory>,
+    trajectories: Vec<Trajectory>,
+    selected_trajectory: usize,
+    trajectory_name: String,
+    trajectory_path: String,
+    trajectory_message: Option<String>,
+    playback: Option<Playback>,
+    sequence: Sequence,
+    sequence_path: String,
+    sequence_message: Option<String>,
+    sequence_run: Option<(Plan, Playback)>,
+    // Requests from the HTTP API and events for its stream, if it was started
+    api: Option<ApiLink>,
+    services_text: String,
+    // Ordered as in arm::SERVO_NAMES
+    servo_limits: [ServoLimit; arm::SERVO_COUNT],
     send_vec: Vec<u8>,
-    receive_vec: Vec<u8>,
-    received_values: Vec<f64>,
+    transport_error: Option<String>,
+    send_sequence: u16,
+    receive_stats: PacketStats,
+    delivery: Delivery,
+    feedback: Option<[u16; arm::SERVO_COUNT]>,
+    adc_values: Vec<u16>,
+    receive_error: Option<String>,
+    last_sender: Option<SocketAddr>,
+    telemetry: Telemetry,
+    telemetry_window: f64,
     mode: Mode,
     send: bool,
-    flag: bool,
+    // The pose has changed since it was last sent, Auto Send sends it on the next frame
+    pose_dirty: bool,
     shared_state: Arc<Mutex<SharedState>>,
     arm: arm::Arm,
     target_i: f64,
     target_j: f64,
     target_k: f64,
+    ik_message: Option<String>,
+    ik_options: arm::IkOptions,
+    chain_path: String,
+    chain_message: Option<String>,
+    calibration: Calibration,
+    calibration_servo: usize,
+    calibration_reference_points: usize,
+    calibration_wizard: Option<CalibrationWizard>,
+    calibration_message: Option<String>,
+    config: Config,
+    new_profile_name: String,
+    config_message: Option<String>,
     plot_yaw: f64,
     plot_scale: f64,
 }
 
 impl Controller {
-    pub fn new(shared_state: Arc<Mutex<SharedState>>) -> Self {
-        let udp_socket = UdpSocket::bind("0.0.0.0:8080").expect("Failed to bind socket");
-        udp_socket.set_nonblocking(true).expect("Failed to set nonblocking");
-
-
-        Controller {
+    // bind_override is listened on instead of the profile's bind address, without changing the profile
+    pub fn new(
+        shared_state: Arc<Mutex<SharedState>>,
+        config: Config,
+        transport: Transport,
+        bind_override: Option<String>,
+        api: Option<ApiLink>,
+    ) -> Self {
+        let profile = config.active();
+        let mut controller = Controller {
             ip_addr_string: "0.0.0.0:1234".to_owned(),
             is_ip_addr: true,
             send_to: "0.0.0.0:1234".to_owned(),
-            udp_socket,
-            servo_top_range: 0.0..=180.0,
-            servo_shoulder_range: 0.0..=180.0,
-            servo_upper_range: 0.0..=180.0,
-            servo_elbow_range: 0.0..=180.0,
-            servo_lower_range: 0.0..=180.0,
+            transport,
+            bind_address: String::new(),
+            bind_error: None,
+            selected_device: None,
+            hello: None,
+            health: LinkHealth::new(),
+            estop: EmergencyStop::new(),
+            recording: None,
+            trajectories: Vec::new(),
+            selected_trajectory: 0,
+            trajectory_name: String::new(),
+            trajectory_path: "trajectory.json".to_owned(),
+            trajectory_message: None,
+            playback: None,
+            sequence: Sequence { name: "Sequence".to_owned(), ..Sequence::default() },
+            sequence_path: "sequence.json".to_owned(),
+            sequence_message: None,
+            sequence_run: None,
+            api,
+            services_text: String::new(),
+            servo_limits: [ServoLimit { min: 0.0, max: 180.0 }; arm::SERVO_COUNT],
             send_vec: Vec::new(),
-            receive_vec: vec![0; 11],
-            received_values: vec![0.0; 5],
+            transport_error: None,
+            send_sequence: 0,
+            receive_stats: PacketStats::default(),
+            delivery: Delivery::new(RetryPolicy::default()),
+            feedback: None,
+            adc_values: Vec::new(),
+            receive_error: None,
+            last_sender: None,
+            telemetry: Telemetry::new(),
+            telemetry_window: 30.0,
             mode: Mode::Stopped,
             send: true,
-            flag: true,
+            pose_dirty: true,
             shared_state,
             arm: arm::Arm::new(1.0, 1.0),
             target_i: 1.0,
             target_j: 1.0,
             target_k: 1.0,
+            ik_message: None,
+            ik_options: arm::IkOptions::default(),
+            chain_path: "arm.toml".to_owned(),
+            chain_message: None,
+            calibration: Calibration::default(),
+            calibration_servo: 0,
+            calibration_reference_points: 5,
+            calibration_wizard: None,
+            calibration_message: None,
+            config,
+            new_profile_name: String::new(),
+            config_message: None,
             plot_yaw: 0.5,
             plot_scale: 0.55,
+        };
+        controller.apply_profile(profile);
+        let bind_address = bind_override.unwrap_or_else(|| controller.bind_address.clone());
+        controller.bind(&bind_address);
+        controller
+    }
+
+    // Listens on a new address, keeping the error for the UI rather than giving up if it's unavailable
+    fn bind(&mut self, address: &str) {
+        self.bind_error = match self.transport.bind(address) {
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("{}", e);
+                Some(e.to_string())
+            }
+        };
+    }
+
+    // Replaces the current settings with the ones stored in a profile
+    fn apply_profile(&mut self, profile: Profile) {
+        self.ip_addr_string = profile.send_to.clone();
+        self.is_ip_addr = network::Network::check_ip_string(&self.ip_addr_string);
+        self.send_to = profile.send_to;
+        // The first profile is applied before anything is bound, and Controller::new binds once it knows about any override
+        self.bind_address = profile.bind_address;
+        if self.transport.address().is_some() && self.transport.address() != Some(self.bind_address.as_str()) {
+            let address = self.bind_address.clone();
+            self.bind(&address);
         }
+        self.servo_limits = profile.servo_limits;
+        self.arm.set_chain(profile.chain);
+        self.calibration = profile.calibration;
+        self.ik_options = profile.ik;
+        self.delivery.policy = profile.retry;
+        self.services_text = profile.services.join(", ");
+        self.shared_state.lock().unwrap().set_services(profile.services);
+        self.pose_dirty = true;
+    }
+
+    // The current settings as a profile, ready to be saved
+    fn current_profile(&self) -> Profile {
+        Profile {
+            send_to: self.send_to.clone(),
+            bind_address: self.bind_address.clone(),
+            services: self.shared_state.lock().unwrap().services(),
+            servo_limits: self.servo_limits,
+            chain: self.arm.chain().clone(),
+            calibration: self.calibration.clone(),
+            ik: self.ik_options,
+            retry: self.delivery.policy,
+            // Only set in the config file, so kept as they were
+            mqtt: self.config.active().mqtt,
+            ros: self.config.active().ros,
+        }
+    }
+
+    // Stores the current settings in the active profile and writes the config file
+    pub fn save_config(&mut self) -> Result<PathBuf, ConfigError> {
+        let profile = self.current_profile();
+        self.config.profiles.insert(self.config.active_profile.clone(), profile);
+        self.config.save()
     }
 
     pub fn render_ui(&mut self, ui: &mut Ui) {
         // Heading
         ui.heading("Limb Controller");
+        self.render_emergency_stop(ui);
 
         // IP Address input and validation
         ui.horizontal(|ui| {
             let name_label = ui.label("IP Address: ");
             ui.text_edit_singleline(&mut self.ip_addr_string).labelled_by(name_label.id);
-            Controller::mdns_button(ui, &mut self.ip_addr_string, &self.shared_state);
             self.is_ip_addr = network::Network::check_ip_string(&self.ip_addr_string);
             ui.label(if self.is_ip_addr { "Valid" } else { "Invalid" });
-            if self.is_ip_addr {
-                if ui.button("Apply").clicked() {
-                    self.send_to = self.ip_addr_string.clone();
-                }
+            if self.is_ip_addr && ui.button("Apply").clicked() {
+                self.send_to = self.ip_addr_string.clone();
             }
+            ui.add(Separator::default());
+            self.render_link_health(ui);
         });
 
+        self.render_device_list(ui);
+
+        if let Some(error) = &self.bind_error {
+            let mut retry = false;
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::RED, error);
+                retry = ui.button("Retry").clicked();
+            });
+            if retry {
+                let address = self.transport.address().unwrap_or(&self.bind_address).to_owned();
+                self.bind(&address);
+            }
+        }
+
         // Mode selection
         ui.add(Separator::default());
         ComboBox::from_label("Choose Mode")
@@ -98,21 +267,45 @@ impl Controller {
             .show_ui(ui, |ui| {
                 ui.selectable_value(&mut self.mode, Mode::Sending, "Sending");
                 ui.selectable_value(&mut self.mode, Mode::Receiving, "Receiving");
+                ui.selectable_value(&mut self.mode, Mode::Sequencer, "Sequencer");
                 ui.selectable_value(&mut self.mode, Mode::Stopped, "Stopped");
                 ui.selectable_value(&mut self.mode, Mode::Settings, "Settings");
             });
 
+        // Acks and retries need handling whatever mode we're in, except Receiving which must only ever listen
+        if self.mode == Mode::Receiving {
+            self.receive_data();
+        } else {
+            self.service_delivery(ui);
+        }
+        self.service_emergency_stop(ui);
+        self.handle_api_requests();
+
+        // Playback only advances while it's on screen, so pause it rather than have it jump ahead on return
+        if self.mode != Mode::Sending {
+            if let Some(playback) = self.playback.as_mut() {
+                playback.paused = true;
+            }
+        }
+        if self.mode != Mode::Sequencer {
+            if let Some((_, playback)) = self.sequence_run.as_mut() {
+                playback.paused = true;
+            }
+        }
+
         // Mode-specific UI
         match self.mode {
             Mode::Sending => {
                 self.render_sending_mode_ui(ui);
-                self.render_receive_ui();
-                self.render_arm_status_ui(ui);
+                ui.add_enabled_ui(!self.estop.is_engaged(), |ui| self.render_arm_status_ui(ui));
                 self.render_plot(ui);
             }
             Mode::Receiving => {
-                ui.label("Stopped");
+                self.render_receiving_mode_ui(ui);
             },
+            Mode::Sequencer => {
+                self.render_sequencer_ui(ui);
+            }
             Mode::Stopped => {
                 ui.label("Stopped");
             }
@@ -124,22 +317,53 @@ impl Controller {
 
     fn render_sending_mode_ui(&mut self, ui: &mut Ui) {
         ui.label(format!("Sending Data to {}", &self.send_to));
-        if ui.button("Reset").clicked() {
-            *self.arm.servo_a_horiz() = (self.servo_top_range.end() + self.servo_top_range.start()) / 2.0;
-            *self.arm.servo_a_vert() = (self.servo_shoulder_range.end() + self.servo_shoulder_range.start()) / 2.0;
-            *self.arm.servo_b_horiz() = (self.servo_upper_range.end() + self.servo_upper_range.start()) / 2.0;
-            *self.arm.servo_b_vert() = *self.servo_elbow_range.start();
-            *self.arm.servo_c_horiz() = (self.servo_lower_range.end() + self.servo_lower_range.start()) / 2.0;
-            self.flag = true;
-        }
-        // Servo control sliders
-        Controller::render_servo_control(ui, &self.servo_top_range, self.arm.servo_a_horiz(), "Top Servo", &mut self.flag);
-        Controller::render_servo_control(ui, &self.servo_shoulder_range, self.arm.servo_a_vert(), "Shoulder Servo", &mut self.flag);
-        Controller::render_servo_control(ui, &self.servo_upper_range, self.arm.servo_b_horiz(), "Upper Servo", &mut self.flag);
-        Controller::render_servo_control(ui, &self.servo_elbow_range, self.arm.servo_b_vert(), "Elbow Servo", &mut self.flag);
-        Controller::render_servo_control(ui, &self.servo_lower_range, self.arm.servo_c_horiz(), "Lower Servo", &mut self.flag);
+        let capabilities = self.capabilities();
+        Controller::render_capability_warnings(ui, &capabilities);
+        // Everything that can move the arm is locked while the emergency stop is engaged, and the sliders while playing back
+        let playing = self.playback.as_ref().is_some_and(|playback| !playback.paused);
+        ui.add_enabled_ui(!self.estop.is_engaged() && !playing, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Reset").clicked() {
+                    let [top, shoulder, upper, elbow, lower] = self.servo_limits;
+                    *self.arm.servo_a_horiz() = (top.max + top.min) / 2.0;
+                    *self.arm.servo_a_vert() = (shoulder.max + shoulder.min) / 2.0;
+                    *self.arm.servo_b_horiz() = (upper.max + upper.min) / 2.0;
+                    *self.arm.servo_b_vert() = elbow.min;
+                    *self.arm.servo_c_horiz() = (lower.max + lower.min) / 2.0;
+                    self.pose_dirty = true;
+                }
+                if ui.button("Home").clicked() {
+                    let mut home = self.arm.home_servo_angles();
+                    for (angle, limit) in home.iter_mut().zip(self.servo_limits.iter()) {
+                        *angle = limit.clamp(*angle);
+                    }
+                    self.arm.set_servo_angles(&home);
+                    self.pose_dirty = true;
+                }
+            });
+            // Servo control sliders, in wire order, for the servos the arm actually has
+            if capabilities.has_servo(0) {
+                Controller::render_servo_control(ui, &self.servo_limits[0], self.arm.servo_a_horiz(), "Top Servo", &mut self.pose_dirty);
+            }
+            if capabilities.has_servo(1) {
+                Controller::render_servo_control(ui, &self.servo_limits[1], self.arm.servo_a_vert(), "Shoulder Servo", &mut self.pose_dirty);
+            }
+            if capabilities.has_servo(2) {
+                Controller::render_servo_control(ui, &self.servo_limits[2], self.arm.servo_b_horiz(), "Upper Servo", &mut self.pose_dirty);
+            }
+            if capabilities.has_servo(3) {
+                Controller::render_servo_control(ui, &self.servo_limits[3], self.arm.servo_b_vert(), "Elbow Servo", &mut self.pose_dirty);
+            }
+            if capabilities.has_servo(4) {
+                Controller::render_servo_control(ui, &self.servo_limits[4], self.arm.servo_c_horiz(), "Lower Servo", &mut self.pose_dirty);
+            }
+        });
+        ui.add_enabled_ui(!self.estop.is_engaged(), |ui| self.render_recording_ui(ui));
         self.arm.update();
         ui.label(format!("Packet {:?}", &self.send_vec));
+        if let Some(error) = &self.transport_error {
+            ui.label(format!("Network error: {}", error));
+        }
 
         ui.horizontal(|ui| {
             ui.add(Controller::toggle(&mut self.send));
@@ -147,36 +371,45 @@ impl Controller {
             ui.label(if self.send { "Auto Send" } else { "Manual Send" });
         });
 
-        if !self.send {
+        if self.estop.is_engaged() {
+            ui.colored_label(egui::Color32::RED, "Emergency stop engaged, re-arm to send again");
+        } else if !self.send {
             if ui.button("Send").clicked() {
-                self.send_data().expect("Failed to send data");
+                if let Err(e) = self.send_data() {
+                    self.transport_error = Some(e.to_string());
+                }
             }
-        } else if self.flag {
-            self.send_data().expect("Failed to send data");
+        } else if self.health.is_dead(Instant::now()) {
+            // Nothing is streamed to an arm that isn't answering, the latest pose goes out once it's back
+            ui.colored_label(egui::Color32::RED, "Link is down, automatic sending is paused");
+        } else if self.pose_dirty {
+            // The pose changed, delivery takes care of getting it to the arm from here.
+            // A failed send isn't retried every frame, the next change tries again.
+            if let Err(e) = self.send_data() {
+                self.transport_error = Some(e.to_string());
+            }
+            self.pose_dirty = false;
         }
 
-        // Receive data
-        match self.udp_socket.recv_from(&mut self.receive_vec) {
-            Ok(_) => {}
-            Err(_) => {}
-        };
-        for (i, chunk) in self.receive_vec.chunks_exact(2).enumerate() { // Divides the received data into 2 byte chunks, and iterates over them
-            self.received_values[i] = chunk.try_into()
-                .map(|bytes: [u8; 2]| u16::from_be_bytes(bytes) as f64) // Convert bytes to u16, then to f64
-                .unwrap_or(-1.0); // Default to -1.0 if conversion fails, indicating a problem converting from bytes to u16
-            if self.received_values == vec![
-                *self.arm.servo_a_horiz(),
-                *self.arm.servo_a_vert(),
-                *self.arm.servo_b_horiz(),
-                *self.arm.servo_b_vert()
-            ] {
-                self.flag = false;
-            }
+        self.render_delivery_ui(ui);
+        ui.label(format!("Received {:?}", &self.feedback));
+        if let Some(feedback) = &self.feedback {
+            ui.label(format!("Received angles {:.1?}", self.calibration.to_angles(feedback)));
+        }
+        if !self.adc_values.is_empty() {
+            ui.label(format!("ADC {:?}", &self.adc_values));
+        }
+        let stats = &self.receive_stats;
+        ui.label(format!(
+            "Packets received: {}, dropped: {}, reordered: {}, corrupted: {}",
+            stats.received, stats.dropped, stats.reordered, stats.corrupted
+        ));
+        if let Some(error) = &self.receive_error {
+            ui.label(format!("Last bad packet: {}", error));
         }
-        ui.label(format!("Received {:?}, flag set to: {}", &self.received_values, &self.flag));
     }
 
-    fn render_servo_control(ui: &mut Ui, range: &RangeInclusive<f64>, angle: &mut f64, label: &str, flag: &mut bool) {
+    fn render_servo_control(ui: &mut Ui, limit: &ServoLimit, angle: &mut f64, label: &str, pose_dirty: &mut bool) {
         ui.horizontal(|ui| {
             // Label for the servo
             ui.label(format!("{} Position:", label));
@@ -185,17 +418,17 @@ impl Controller {
             Controller::flag_setting_slider(
                 ui,
                 angle,
-                range.clone(),
+                limit.range(),
                 "°",
-                flag,
+                pose_dirty,
             );
             if ui.button("-").clicked() {
-                *angle = (*angle - 1.0).max(*range.start());
-                *flag = true;
+                *angle = limit.clamp(*angle - 1.0);
+                *pose_dirty = true;
             }
             if ui.button("+").clicked() {
-                *angle = (*angle + 1.0).min(*range.end());
-                *flag = true;
+                *angle = limit.clamp(*angle + 1.0);
+                *pose_dirty = true;
             }
         });
         ui.end_row(); // End the current row and prepare for the next
@@ -206,11 +439,11 @@ impl Controller {
         value: &mut f64,
         range: RangeInclusive<f64>,
         suffix: &str,
-        flag: &mut bool,
+        pose_dirty: &mut bool,
     ) {
         let slider_response = ui.add(Slider::new(value, range).suffix(suffix));
         if slider_response.changed() {
-            *flag = true;
+            *pose_dirty = true;
         }
     }
 
@@ -239,6 +472,376 @@ impl Controller {
         response
     }
 
+    // Records the pose into named trajectories, and plays them back by moving the sliders
+    fn render_recording_ui(&mut self, ui: &mut Ui) {
+        // Recording picks up the pose whatever moved it, the sliders, IK or Home
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record(self.arm.servo_angles(), Local::now());
+            ui.ctx().request_repaint_after(SAMPLE_INTERVAL);
+        }
+
+        ui.add(Separator::default());
+        ui.label("Recording:");
+        ui.horizontal(|ui| {
+            let name_label = ui.label("Name:");
+            ui.text_edit_singleline(&mut self.trajectory_name).labelled_by(name_label.id);
+            if let Some(mut recording) = self.recording.take() {
+                if ui.button("Stop Recording").clicked() {
+                    recording.finish(Local::now());
+                    self.trajectories.push(recording);
+                    self.selected_trajectory = self.trajectories.len() - 1;
+                } else {
+                    self.recording = Some(recording);
+                }
+            } else if ui.add_enabled(self.playback.is_none(), egui::Button::new("Record")).clicked() {
+                let name = match self.trajectory_name.trim() {
+                    "" => format!("Recording {}", self.trajectories.len() + 1),
+                    name => name.to_owned(),
+                };
+                self.recording = Some(Trajectory::new(&name));
+            }
+        });
+        if let Some(recording) = &self.recording {
+            ui.colored_label(egui::Color32::RED, format!("Recording {}: {} samples, {:.1} s", recording.name, recording.samples.len(), recording.duration()));
+        }
+        ui.horizontal(|ui| {
+            let path_label = ui.label("Trajectory File:");
+            ui.text_edit_singleline(&mut self.trajectory_path).labelled_by(path_label.id);
+            let idle = self.playback.is_none() && self.recording.is_none();
+            if ui.add_enabled(idle, egui::Button::new("Load")).clicked() {
+                self.trajectory_message = Some(match Trajectory::load(Path::new(&self.trajectory_path)) {
+                    Ok(trajectory) => {
+                        let message = format!("Loaded {}", trajectory.name);
+                        self.trajectories.push(trajectory);
+                        self.selected_trajectory = self.trajectories.len() - 1;
+                        message
+                    }
+                    Err(e) => e.to_string(),
+                });
+            }
+            if ui.add_enabled(!self.trajectories.is_empty(), egui::Button::new("Save")).clicked() {
+                self.trajectory_message = Some(match self.trajectories[self.selected_trajectory].save(Path::new(&self.trajectory_path)) {
+                    Ok(()) => format!("Saved {}", self.trajectory_path),
+                    Err(e) => e.to_string(),
+                });
+            }
+        });
+        if let Some(message) = &self.trajectory_message {
+            ui.label(message);
+        }
+        if self.trajectories.is_empty() {
+            return;
+        }
+
+        let mut delete = false;
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(self.playback.is_none(), |ui| {
+                ComboBox::from_label("Trajectory")
+                    .selected_text(&self.trajectories[self.selected_trajectory].name)
+                    .show_ui(ui, |ui| {
+                        for (index, trajectory) in self.trajectories.iter().enumerate() {
+                            let started = trajectory.started_at().map(|at| at.format("%H:%M:%S").to_string()).unwrap_or_default();
+                            let label = format!("{} ({}, {:.1} s)", trajectory.name, started, trajectory.duration());
+                            ui.selectable_value(&mut self.selected_trajectory, index, label);
+                        }
+                    });
+            });
+            let now = Instant::now();
+            match self.playback.as_mut() {
+                Some(playback) => {
+                    let duration = self.trajectories[self.selected_trajectory].duration();
+                    if playback.paused {
+                        if ui.button("Play").clicked() {
+                            playback.resume(duration, now);
+                        }
+                    } else if ui.button("Pause").clicked() {
+                        playback.paused = true;
+                    }
+                    if ui.button("Stop").clicked() {
+                        self.playback = None;
+                    }
+                }
+                None => {
+                    if ui.add_enabled(self.recording.is_none(), egui::Button::new("Play")).clicked() {
+                        self.playback = Some(Playback::new(1.0, false, now));
+                    }
+                    delete = ui.button("Delete").clicked();
+                }
+            }
+        });
+        if delete {
+            self.trajectories.remove(self.selected_trajectory);
+            self.selected_trajectory = self.selected_trajectory.min(self.trajectories.len().saturating_sub(1));
+            return;
+        }
+
+        if let Some(playback) = self.playback.as_mut() {
+            let duration = self.trajectories[self.selected_trajectory].duration();
+            ui.horizontal(|ui| {
+                ui.add(Slider::new(&mut playback.speed, MIN_SPEED..=MAX_SPEED).logarithmic(true).text("Speed"));
+                ui.checkbox(&mut playback.looping, "Loop");
+            });
+            // Dragging the position scrubs through the trajectory, moving the arm with it
+            ui.add(Slider::new(&mut playback.position, 0.0..=duration).suffix(" s").text("Position"));
+            if !self.send {
+                ui.label("Playback only reaches the arm with Auto Send on");
+            }
+        }
+        self.advance_playback(ui);
+    }
+
+    // Moves the sliders to where playback has got to. Auto send streams them to the arm like any other change,
+    // and the usual limits apply.
+    fn advance_playback(&mut self, ui: &mut Ui) {
+        let limits = self.servo_limits;
+        let Some(playback) = self.playback.as_mut() else {
+            return;
+        };
+        let trajectory = &self.trajectories[self.selected_trajectory];
+        playback.tick(trajectory.duration(), Instant::now());
+        if !playback.paused {
+            ui.ctx().request_repaint();
+        }
+        let Some(angles) = trajectory.angles_at(playback.position) else {
+            return;
+        };
+        let angles: [f64; arm::SERVO_COUNT] = std::array::from_fn(|servo| limits[servo].clamp(angles[servo]));
+        if angles != self.arm.servo_angles() {
+            self.arm.set_servo_angles(&angles);
+            self.pose_dirty = true;
+        }
+    }
+
+    // Keyframe editor and timeline. Playing a sequence moves the arm and streams every change through send_data.
+    fn render_sequencer_ui(&mut self, ui: &mut Ui) {
+        ui.heading("Sequencer");
+        ui.horizontal(|ui| {
+            let path_label = ui.label("Sequence File (.json):");
+            ui.text_edit_singleline(&mut self.sequence_path).labelled_by(path_label.id);
+            if ui.button("Load").clicked() {
+                self.sequence_message = Some(match Sequence::load(Path::new(&self.sequence_path)) {
+                    Ok(sequence) => {
+                        self.sequence = sequence;
+                        self.sequence_run = None;
+                        format!("Loaded {}", self.sequence_path)
+                    }
+                    Err(e) => e.to_string(),
+                });
+            }
+            if ui.button("Save").clicked() {
+                self.sequence_message = Some(match self.sequence.save(Path::new(&self.sequence_path)) {
+                    Ok(()) => format!("Saved {}", self.sequence_path),
+                    Err(e) => e.to_string(),
+                });
+            }
+        });
+        if let Some(message) = &self.sequence_message {
+            ui.label(message);
+        }
+
+        self.render_timeline(ui);
+        if !self.send {
+            ui.label("Sequences only reach the arm with Auto Send on");
+        }
+        ui.add_enabled_ui(!self.estop.is_engaged(), |ui| self.render_sequence_controls(ui));
+        // Keyframes can't change under a running plan
+        ui.add_enabled_ui(self.sequence_run.is_none(), |ui| self.render_keyframe_editor(ui));
+        self.advance_sequence(ui);
+    }
+
+    fn render_sequence_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let now = Instant::now();
+            match self.sequence_run.as_mut() {
+                Some((plan, playback)) => {
+                    if playback.paused {
+                        if ui.button("Play").clicked() {
+                            playback.resume(plan.duration(), now);
+                        }
+                    } else if ui.button("Pause").clicked() {
+                        playback.paused = true;
+                    }
+                    if ui.button("Stop").clicked() {
+                        self.sequence_run = None;
+                    }
+                }
+                None => {
+                    if ui.button("Play").clicked() {
+                        // Planning from the current pose means the first keyframe is approached from wherever the arm is
+                        match self.sequence.plan(&self.arm, self.arm.servo_angles(), &self.servo_limits, &self.ik_options) {
+                            Ok(plan) => {
+                                self.sequence_run = Some((plan, Playback::new(1.0, false, now)));
+                                self.sequence_message = None;
+                            }
+                            Err(e) => self.sequence_message = Some(e.to_string()),
+                        }
+                    }
+                }
+            }
+            if let Some((_, playback)) = self.sequence_run.as_mut() {
+                ui.add(Slider::new(&mut playback.speed, MIN_SPEED..=MAX_SPEED).logarithmic(true).text("Speed"));
+                ui.checkbox(&mut playback.looping, "Loop");
+            }
+        });
+        if self.health.is_dead(Instant::now()) && self.sequence_run.is_some() {
+            ui.colored_label(egui::Color32::RED, "Link is down, the sequence isn't reaching the arm");
+        }
+    }
+
+    // Keyframes laid out along the sequence's duration, with the playhead. Clicking or dragging on it scrubs a running sequence.
+    fn render_timeline(&mut self, ui: &mut Ui) {
+        let (duration, times, position): (f64, Vec<f64>, Option<f64>) = match &self.sequence_run {
+            Some((plan, playback)) => (plan.duration(), plan.keyframe_times().collect(), Some(playback.position)),
+            None => {
+                let times = self
+                    .sequence
+                    .keyframes
+                    .iter()
+                    .scan(0.0, |time, keyframe| {
+                        *time += keyframe.duration.max(0.0);
+                        Some(*time)
+                    })
+                    .collect();
+                (self.sequence.duration(), times, None)
+            }
+        };
+        ui.label(format!("{} keyframes, {:.2} s", times.len(), duration));
+        let (rect, response) = ui.allocate_exact_size(vec2(ui.available_width(), 28.0), Sense::click_and_drag());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 4.0, ui.visuals().extreme_bg_color);
+        let x_at = |time: f64| {
+            let fraction = if duration > 0.0 { (time / duration) as f32 } else { 0.0 };
+            lerp(rect.left()..=rect.right(), fraction.clamp(0.0, 1.0))
+        };
+        let text_colour = ui.visuals().text_color();
+        for (index, time) in times.iter().enumerate() {
+            let x = x_at(*time);
+            painter.line_segment([pos2(x, rect.top()), pos2(x, rect.bottom())], (1.0, text_colour));
+            painter.text(pos2(x - 3.0, rect.top() + 2.0), egui::Align2::RIGHT_TOP, (index + 1).to_string(), egui::FontId::monospace(10.0), text_colour);
+        }
+        if let Some(position) = position {
+            let x = x_at(position);
+            painter.line_segment([pos2(x, rect.top()), pos2(x, rect.bottom())], (2.0, egui::Color32::RED));
+        }
+        // Scrubbing moves the arm like playing does, so it's locked out while stopped
+        if self.estop.is_engaged() {
+            return;
+        }
+        if let (Some((_, playback)), Some(pointer)) = (self.sequence_run.as_mut(), response.interact_pointer_pos()) {
+            let fraction = ((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0) as f64;
+            playback.position = fraction * duration;
+        }
+    }
+
+    fn render_keyframe_editor(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let name_label = ui.label("Name:");
+            ui.text_edit_singleline(&mut self.sequence.name).labelled_by(name_label.id);
+            ComboBox::from_label("Interpolation")
+                .selected_text(self.sequence.interpolation.to_string())
+                .show_ui(ui, |ui| {
+                    for interpolation in [Interpolation::Joint, Interpolation::Cartesian] {
+                        ui.selectable_value(&mut self.sequence.interpolation, interpolation, interpolation.to_string());
+                    }
+                });
+        });
+
+        let count = self.sequence.keyframes.len();
+        let mut moved: Option<(usize, usize)> = None;
+        let mut removed: Option<usize> = None;
+        for (index, keyframe) in self.sequence.keyframes.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}.", index + 1));
+                match &mut keyframe.pose {
+                    Pose::Joints { angles } => {
+                        for (angle, name) in angles.iter_mut().zip(arm::SERVO_NAMES) {
+                            ui.label(format!("{}:", name));
+                            ui.add(DragValue::new(angle).speed(0.5).max_decimals(1).suffix("°"));
+                        }
+                    }
+                    Pose::Cartesian { i, j, k } => {
+                        for (value, name) in [i, j, k].into_iter().zip(["i", "j", "k"]) {
+                            ui.label(format!("{}:", name));
+                            ui.add(DragValue::new(value).speed(0.01).max_decimals(3).suffix(" m"));
+                        }
+                    }
+                }
+                ui.add(Separator::default());
+                ui.label("Over:");
+                ui.add(DragValue::new(&mut keyframe.duration).speed(0.05).max_decimals(2).suffix(" s").clamp_range(0.0..=600.0));
+                ComboBox::from_id_source(("keyframe_easing", index))
+                    .selected_text(keyframe.easing.to_string())
+                    .show_ui(ui, |ui| {
+                        for easing in Easing::ALL {
+                            ui.selectable_value(&mut keyframe.easing, easing, easing.to_string());
+                        }
+                    });
+                if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                    moved = Some((index, index - 1));
+                }
+                if ui.add_enabled(index + 1 < count, egui::Button::new("Down")).clicked() {
+                    moved = Some((index, index + 1));
+                }
+                if ui.button("Delete").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some((from, to)) = moved {
+            self.sequence.keyframes.swap(from, to);
+        }
+        if let Some(index) = removed {
+            self.sequence.keyframes.remove(index);
+        }
+
+        // New keyframes are taken from the arm as it is now, set it up in Sending mode first
+        ui.horizontal(|ui| {
+            if ui.button("Add Current Angles").clicked() {
+                let pose = Pose::Joints { angles: self.arm.servo_angles() };
+                self.sequence.keyframes.push(Keyframe { pose, duration: 1.0, easing: Easing::default() });
+            }
+            if ui.button("Add Current Position").clicked() {
+                let (i, j, k) = self.arm.get_ijk();
+                self.sequence.keyframes.push(Keyframe { pose: Pose::Cartesian { i, j, k }, duration: 1.0, easing: Easing::default() });
+            }
+            if ui.button("Clear").clicked() {
+                self.sequence.keyframes.clear();
+            }
+        });
+    }
+
+    // Moves the arm to where the sequence has got to and sends it with Auto Send on, unless the link is down.
+    // Otherwise the pose is left for Sending mode to send.
+    fn advance_sequence(&mut self, ui: &mut Ui) {
+        let Some((plan, playback)) = self.sequence_run.as_mut() else {
+            return;
+        };
+        playback.tick(plan.duration(), Instant::now());
+        if !playback.paused {
+            ui.ctx().request_repaint();
+        }
+        let angles = match plan.pose_at(playback.position, self.arm.servo_angles()) {
+            Ok(angles) => angles,
+            Err(e) => {
+                self.sequence_message = Some(e.to_string());
+                self.sequence_run = None;
+                return;
+            }
+        };
+        if angles == self.arm.servo_angles() {
+            return;
+        }
+        self.arm.set_servo_angles(&angles);
+        self.arm.update();
+        self.pose_dirty = true;
+        if self.send && !self.health.is_dead(Instant::now()) {
+            if let Err(e) = self.send_data() {
+                self.transport_error = Some(e.to_string());
+            }
+            self.pose_dirty = false;
+        }
+    }
+
     // A wrapper that allows the more idiomatic usage pattern: `ui.add(toggle(&mut my_bool))`
     /// iOS-style toggle switch.
     ///
@@ -250,36 +853,52 @@ impl Controller {
         move |ui: &mut Ui| Controller::toggle_ui(ui, on)
     }
 
-    fn send_data(&mut self) -> Result<(), std::io::Error> {
-        // Clear the previous data
-        self.send_vec.clear();
+    fn send_data(&mut self) -> Result<(), TransportError> {
+        // Servo angles go through the calibration to get the values the arm expects
+        let wire = self.calibration.to_wire(&self.arm.servo_angles());
+        self.send_wire(&wire)
+    }
 
-        // Add the control signal type
-        self.send_vec.push(0);
+    // Sends already calibrated wire values, in wire order, as a new command
+    fn send_wire(&mut self, wire: &[u16; arm::SERVO_COUNT]) -> Result<(), TransportError> {
+        // The UI is locked while stopped, but nothing gets to move the arm whichever way it asked
+        if self.estop.is_engaged() {
+            return Ok(());
+        }
+        let id = self.delivery.start(*wire, Instant::now());
+        self.transmit(id, wire)
+    }
 
-        // Helper function to convert f64 to u16 and append it to the vector
-        let append_f64_as_u16 = |vec: &mut Vec<u8>, value: &mut f64| {
-            // Ensure the value is within the valid range for u16
-            if *value >= 0.0 && *value <= u16::MAX as f64 {
-                // Safe to unwrap because we've already checked the range
-                let bytes = (*value as u16).to_be_bytes();
-                vec.extend_from_slice(&bytes);
-            } else {
-                vec.extend_from_slice(&0u16.to_be_bytes());
-            }
-        };
+    // Sends one attempt at a command
+    fn transmit(&mut self, id: u16, wire: &[u16; arm::SERVO_COUNT]) -> Result<(), TransportError> {
+        self.send_vec = self.send_message(&Message::ServoCommand { id, servos: *wire })?;
+        Ok(())
+    }
 
-        // Append servo values to send_vec
-        append_f64_as_u16(&mut self.send_vec, self.arm.servo_a_horiz());
-        append_f64_as_u16(&mut self.send_vec, self.arm.servo_a_vert());
-        append_f64_as_u16(&mut self.send_vec, self.arm.servo_b_horiz());
-        append_f64_as_u16(&mut self.send_vec, self.arm.servo_b_vert());
-        append_f64_as_u16(&mut self.send_vec, self.arm.servo_c_horiz());
+    // Encodes a message with the next sequence number and returns the packet that was sent
+    fn send_message(&mut self, message: &Message) -> Result<Vec<u8>, TransportError> {
+        let sequence = self.send_sequence;
+        let packet = message.encode(sequence)?;
+        self.send_sequence = self.send_sequence.wrapping_add(1);
 
-        // Send the data
-        self.udp_socket.send_to(&self.send_vec, &self.send_to)?;
+        // Hand the data to the transport task, which does the actual sending
+        self.transport.send(&self.send_to, packet.clone())?;
+        match *message {
+            Message::ServoCommand { id, servos } => {
+                let angles = self.calibration.to_angles(&servos);
+                self.publish(EventData::ServoCommand { sequence, id, angles, wire: servos });
+            }
+            Message::EmergencyStop { id } => self.publish(EventData::EmergencyStop { sequence, id }),
+            _ => {}
+        }
+        Ok(packet)
+    }
 
-        Ok(())
+    // Pushes an event to the API's stream, if there is one
+    fn publish(&self, data: EventData) {
+        if let Some(api) = &self.api {
+            api.publish(StreamEvent::now(data));
+        }
     }
 
     fn render_arm_status_ui(&mut self, ui: &mut Ui) {
@@ -296,6 +915,13 @@ impl Controller {
             ui.label("k:");
             ui.add(DragValue::new(&mut k).speed(0.0).max_decimals(2));
         });
+        let (roll, pitch, yaw) = self.arm.poses().tool.rotation.euler_angles();
+        ui.label(format!(
+            "Tool roll: {:.1}°, pitch: {:.1}°, yaw: {:.1}°",
+            roll.to_degrees(),
+            pitch.to_degrees(),
+            yaw.to_degrees()
+        ));
 
         ui.horizontal(|ui| {
             ui.label("target i:");
@@ -307,125 +933,906 @@ impl Controller {
             ui.label("target k:");
             ui.add(DragValue::new(&mut self.target_k).speed(0.1).max_decimals(2));
         });
-        if ui.button("Apply").clicked() {
-            //self.arm.calculate_inverse_kinematics(self.target_i, self.target_j, self.target_k, 0.0001);
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                self.apply_target();
+            }
+            if ui.button("Apply (Numeric)").clicked() {
+                self.apply_target_numeric();
+            }
+        });
+        if let Some(message) = &self.ik_message {
+            ui.label(message);
         }
         // Controller::plot_arm(ui, 64.0);
     }
 
+    // Moves the arm to the target i/j/k, picking the in-limit IK solution closest to the current pose.
+    // Returns false, leaving the reason in ik_message, if there's no such solution.
+    fn apply_target(&mut self) -> bool {
+        let shoulder = *self.arm.servo_a_vert();
+        let upper = *self.arm.servo_b_horiz();
+        let elbow = *self.arm.servo_b_vert();
+        let result = self.arm
+            .angle_from_point(self.target_i, self.target_j, self.target_k)
+            .and_then(|solutions| {
+                solutions
+                    .into_iter()
+                    .filter(|solution| solution.within_limits(&self.servo_limits[1], &self.servo_limits[2], &self.servo_limits[3]))
+                    .min_by(|a, b| a.distance_from(shoulder, upper, elbow).total_cmp(&b.distance_from(shoulder, upper, elbow)))
+                    .ok_or(arm::IkError::OutsideLimits)
+            });
+
+        match result {
+            Ok(solution) => {
+                self.arm.apply_solution(&solution);
+                self.pose_dirty = true;
+                self.ik_message = None;
+                true
+            }
+            Err(e) => {
+                self.ik_message = Some(e.to_string());
+                false
+            }
+        }
+    }
+
+    // Moves the arm towards the target i/j/k with the damped least squares solver, keeping every servo within its limits
+    fn apply_target_numeric(&mut self) -> bool {
+        let report = match self.arm.solve_numeric(self.target_i, self.target_j, self.target_k, &self.servo_limits, &self.ik_options) {
+            Ok(report) => report,
+            Err(e) => {
+                self.ik_message = Some(e.to_string());
+                return false;
+            }
+        };
+        let converged = report.converged();
+        if converged {
+            self.arm.set_servo_angles(&report.angles);
+            self.pose_dirty = true;
+        }
+        self.ik_message = Some(report.to_string());
+        converged
+    }
+
     fn render_settings(&mut self, ui: &mut Ui) {
         ui.heading("Settings");
+        self.render_profile_settings(ui);
         ui.label("Arm Lengths:");
+        ui.horizontal(|ui| match self.arm.settable_arm_lengths() {
+            Some((upper_length, lower_length, tool_length)) => {
+                ui.label("Upper Length:");
+                ui.add(DragValue::new(upper_length).speed(0.05).max_decimals(3).suffix(" m").clamp_range(0.0..=100.0));
+                ui.add(Separator::default());
+                ui.label("Lower Length:");
+                ui.add(DragValue::new(lower_length).speed(0.05).max_decimals(3).suffix(" m").clamp_range(0.0..=100.0));
+                ui.add(Separator::default());
+                ui.label("Tool Length:");
+                ui.add(DragValue::new(tool_length).speed(0.01).max_decimals(3).suffix(" m").clamp_range(0.0..=100.0));
+            }
+            None => {
+                ui.label("Link lengths come from the kinematic chain file");
+            }
+        });
+        self.render_chain_settings(ui);
+        ui.add_enabled_ui(!self.estop.is_engaged(), |ui| self.render_calibration_settings(ui));
+        ui.label("\nArm Angle Upper Limits:");
         ui.horizontal(|ui| {
-            ui.label("Upper Length:");
-            ui.add(DragValue::new(self.arm.settable_arm_lengths().0).speed(0.05).max_decimals(3).suffix(" m").clamp_range(0.0..=100.0));
             ui.add(Separator::default());
-            ui.label("Lower Length:");
-            ui.add(DragValue::new(self.arm.settable_arm_lengths().1).speed(0.05).max_decimals(3).suffix(" m").clamp_range(0.0..=100.0));
-        });
-        let mut top_limit = *self.servo_top_range.end();
-        let mut shoulder_limit = *self.servo_shoulder_range.end();
-        let mut upper_limit = *self.servo_upper_range.end();
-        let mut elbow_limit = *self.servo_elbow_range.end();
-        let mut lower_limit = *self.servo_lower_range.end();
-        let mut top_limit_lower = *self.servo_top_range.start();
-        let mut shoulder_limit_lower = *self.servo_shoulder_range.start();
-        let mut upper_limit_lower = *self.servo_upper_range.start();
-        let mut elbow_limit_lower = *self.servo_elbow_range.start();
-        let mut lower_limit_lower = *self.servo_lower_range.start();
-        ui.label("\nArm Angle Upper Limits:");
+            for (name, limit) in arm::SERVO_NAMES.iter().zip(self.servo_limits.iter_mut()) {
+                ui.label(format!("Servo {}:", name));
+                // Each end can only be dragged as far as the other, and anything that still isn't a limit is dropped
+                let mut max = limit.max;
+                if ui.add(DragValue::new(&mut max).speed(1).suffix("°").clamp_range(limit.min..=limit.max.max(MAX_SERVO_LIMIT))).changed() {
+                    if let Ok(changed) = ServoLimit::new(limit.min, max) {
+                        *limit = changed;
+                    }
+                }
+            }
+        });
+        ui.add(Separator::default());
+        ui.label("Servo Top Upper Limit:");
         ui.horizontal(|ui| {
-            // Temporary variables to hold the current upper limits
-
             ui.add(Separator::default());
-            ui.label("Servo Top:");
-            if ui.add(DragValue::new(&mut top_limit).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_top_range = top_limit_lower..=top_limit;
+            for (name, limit) in arm::SERVO_NAMES.iter().zip(self.servo_limits.iter_mut()) {
+                ui.label(format!("Servo {}:", name));
+                let mut min = limit.min;
+                if ui.add(DragValue::new(&mut min).speed(1).suffix("°").clamp_range(limit.min.min(0.0)..=limit.max)).changed() {
+                    if let Ok(changed) = ServoLimit::new(min, limit.max) {
+                        *limit = changed;
+                    }
+                }
+            }
+        });
+        ui.add(Separator::default());
+        ui.label("Numeric IK Solver:");
+        ui.horizontal(|ui| {
+            ui.label("Tolerance:");
+            ui.add(DragValue::new(&mut self.ik_options.tolerance).speed(0.0001).max_decimals(4).suffix(" m").clamp_range(0.00001..=0.1));
+            ui.label("Max Iterations:");
+            ui.add(DragValue::new(&mut self.ik_options.max_iterations).speed(1).clamp_range(1..=5000));
+            ui.label("Damping:");
+            ui.add(DragValue::new(&mut self.ik_options.damping).speed(0.005).max_decimals(3).clamp_range(0.001..=1.0));
+            ui.label("Max Step:");
+            ui.add(DragValue::new(&mut self.ik_options.max_step).speed(0.5).suffix("°").clamp_range(0.1..=90.0));
+        });
+        ui.add(Separator::default());
+        ui.horizontal(|ui| {
+            let bind_label = ui.label("Listen On:");
+            ui.text_edit_singleline(&mut self.bind_address).labelled_by(bind_label.id);
+            let bound = self.transport.local_address();
+            if ui.button("Apply").clicked() {
+                let address = self.bind_address.clone();
+                self.bind(&address);
+            }
+            ui.label(match bound {
+                Some(address) => format!("Listening on {}", address),
+                None => "Not listening".to_owned(),
+            });
+        });
+        ui.add(Separator::default());
+        ui.label("Command Delivery:");
+        ui.horizontal(|ui| {
+            let policy = &mut self.delivery.policy;
+            ui.label("Ack Timeout:");
+            ui.add(DragValue::new(&mut policy.initial_timeout_ms).speed(5).suffix(" ms").clamp_range(10..=5000));
+            ui.label("Backoff:");
+            ui.add(DragValue::new(&mut policy.backoff).speed(0.1).max_decimals(2).suffix("x").clamp_range(1.0..=10.0));
+            ui.label("Max Timeout:");
+            ui.add(DragValue::new(&mut policy.max_timeout_ms).speed(10).suffix(" ms").clamp_range(10..=30000));
+            ui.label("Max Attempts:");
+            ui.add(DragValue::new(&mut policy.max_attempts).speed(1).clamp_range(1..=50));
+        });
+        ui.add(Separator::default());
+        ui.horizontal(|ui| {
+            let mdns_label = ui.label("mDNS Service Types:");
+            ui.text_edit_singleline(&mut self.services_text).labelled_by(mdns_label.id)
+                .on_hover_text("Comma separated, e.g. _controller._udp.local, _sensor._udp.local");
+            if ui.button("Apply").clicked() {
+                let services = self.services_text.split(',').map(str::to_owned).collect();
+                let mut shared_state_lock = self.shared_state.lock().unwrap();
+                shared_state_lock.set_services(services);
+                self.services_text = shared_state_lock.services().join(", ");
             }
-            ui.label("Servo Shoulder:");
-            if ui.add(DragValue::new(&mut shoulder_limit).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_shoulder_range = shoulder_limit_lower..=shoulder_limit;
+        });
+        ui.label(format!("Browsing for: {}", self.shared_state.lock().unwrap().services().join(", ")));
+    }
+
+    fn render_profile_settings(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let mut selected = self.config.active_profile.clone();
+            ComboBox::from_label("Profile")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in self.config.profiles.keys() {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+            if selected != self.config.active_profile {
+                // Keep any changes to the profile we are leaving
+                let current = self.current_profile();
+                self.config.profiles.insert(self.config.active_profile.clone(), current);
+                self.config.active_profile = selected;
+                self.apply_profile(self.config.active());
+                self.config_message = None;
             }
-            ui.label("Servo Upper:");
-            if ui.add(DragValue::new(&mut upper_limit).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_upper_range = upper_limit_lower..=upper_limit;
+
+            if ui.button("Save Settings").clicked() {
+                self.config_message = Some(match self.save_config() {
+                    Ok(path) => format!("Saved to {}", path.display()),
+                    Err(e) => e.to_string(),
+                });
             }
-            ui.label("Servo Elbow:");
-            if ui.add(DragValue::new(&mut elbow_limit).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_elbow_range = elbow_limit_lower..=elbow_limit;
+            if self.config.profiles.len() > 1 && ui.button("Delete Profile").clicked() {
+                self.config.profiles.remove(&self.config.active_profile);
+                self.config.active_profile = self.config.profiles.keys().next().cloned().unwrap_or_default();
+                self.apply_profile(self.config.active());
+                self.config_message = None;
             }
-            ui.label("Servo Lower:");
-            if ui.add(DragValue::new(&mut lower_limit).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_lower_range = lower_limit_lower..=lower_limit;
+        });
+        ui.horizontal(|ui| {
+            let name_label = ui.label("New Profile:");
+            ui.text_edit_singleline(&mut self.new_profile_name).labelled_by(name_label.id);
+            let name = self.new_profile_name.trim().to_owned();
+            if ui.add_enabled(!name.is_empty() && !self.config.profiles.contains_key(&name), egui::Button::new("Save As")).clicked() {
+                // The new profile starts as a copy of the current settings
+                let current = self.current_profile();
+                self.config.profiles.insert(self.config.active_profile.clone(), current.clone());
+                self.config.profiles.insert(name.clone(), current);
+                self.config.active_profile = name;
+                self.new_profile_name.clear();
+                self.config_message = None;
             }
         });
+        if let Some(message) = &self.config_message {
+            ui.label(message);
+        }
         ui.add(Separator::default());
-        ui.label("Servo Top Upper Limit:");
+    }
+
+    fn render_calibration_settings(&mut self, ui: &mut Ui) {
+        ui.add(Separator::default());
+        ui.label("Servo Calibration:");
+        let limits = self.servo_limits;
+
+        // Only one servo can be calibrated at a time, so the selection is locked while the wizard runs
+        ui.add_enabled_ui(self.calibration_wizard.is_none(), |ui| {
+            ComboBox::from_label("Servo")
+                .selected_text(arm::SERVO_NAMES[self.calibration_servo])
+                .show_ui(ui, |ui| {
+                    for (servo, name) in arm::SERVO_NAMES.iter().enumerate() {
+                        ui.selectable_value(&mut self.calibration_servo, servo, *name);
+                    }
+                });
+        });
+
+        let servo = self.calibration_servo;
+        let calibration = &mut self.calibration.servos[servo];
         ui.horizontal(|ui| {
-            // Temporary variables to hold the current upper limits
-            ui.add(Separator::default());
-            ui.label("Servo Top:");
-            if ui.add(DragValue::new(&mut top_limit_lower).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_top_range = top_limit_lower..=top_limit;
+            ui.label("Offset:");
+            ui.add(DragValue::new(&mut calibration.offset).speed(0.5).suffix("°").clamp_range(-360.0..=360.0));
+            ui.checkbox(&mut calibration.inverted, "Inverted");
+            let preview = calibration.to_wire(*self.arm.servo_angles().get(servo).unwrap_or(&0.0));
+            ui.label(format!("Currently sending: {}", preview));
+        });
+
+        let mut remove = None;
+        for (index, point) in calibration.points.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{:.1}° -> {:.0}", point.angle, point.wire));
+                if ui.button("Remove").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            calibration.points.remove(index);
+        }
+        if calibration.points.len() < 2 {
+            ui.label("No lookup table, angles are sent as they are after the offset");
+        }
+
+        let mut jog_to = None;
+        match &mut self.calibration_wizard {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.label("Reference Points:");
+                    ui.add(DragValue::new(&mut self.calibration_reference_points).speed(1).clamp_range(2..=19));
+                    if ui.button("Start Calibration Wizard").clicked() {
+                        let limit = limits[servo];
+                        let wire = calibration.to_wire(self.arm.servo_angles()[servo]);
+                        self.calibration_wizard = Some(CalibrationWizard::new(servo, limit.min, limit.max, self.calibration_reference_points, wire));
+                        jog_to = Some(wire);
+                    }
+                    if ui.button("Clear Table").clicked() {
+                        calibration.points.clear();
+                    }
+                });
+            }
+            Some(wizard) => {
+                let mut finished = false;
+                let mut cancelled = false;
+                match wizard.current_target() {
+                    Some(target) => {
+                        ui.label(format!(
+                            "Step {} of {}: jog the {} servo until the joint sits at {:.1}°, then record it",
+                            wizard.step + 1,
+                            wizard.targets.len(),
+                            arm::SERVO_NAMES[wizard.servo],
+                            target
+                        ));
+                        ui.horizontal(|ui| {
+                            for amount in [-10, -1, 1, 10] {
+                                if ui.button(format!("{:+}", amount)).clicked() {
+                                    wizard.jog(amount);
+                                    jog_to = Some(wizard.wire);
+                                }
+                            }
+                            ui.label(format!("Wire value: {}", wizard.wire));
+                            if ui.button("Record").clicked() {
+                                wizard.record(calibration);
+                            }
+                            if ui.button("Skip").clicked() {
+                                wizard.skip();
+                            }
+                        });
+                    }
+                    None => {
+                        ui.label(format!("Recorded {} reference points", wizard.recorded.len()));
+                        if ui.button("Finish").clicked() {
+                            wizard.apply(calibration);
+                            finished = true;
+                        }
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+                if finished || cancelled {
+                    self.calibration_wizard = None;
+                    // Put the servo back where the Arm thinks it is
+                    self.pose_dirty = true;
+                    if let Err(e) = self.send_data() {
+                        self.calibration_message = Some(format!("Failed to send: {}", e));
+                    }
+                }
             }
-            ui.label("Servo Shoulder:");
-            if ui.add(DragValue::new(&mut shoulder_limit_lower).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_shoulder_range = shoulder_limit_lower..=shoulder_limit;
+        }
+
+        // Jogging sends the raw value for the servo under calibration and the calibrated values for the rest
+        if let Some(wire) = jog_to {
+            let mut values = self.calibration.to_wire(&self.arm.servo_angles());
+            values[servo] = wire;
+            self.calibration_message = match self.send_wire(&values) {
+                Ok(()) => None,
+                Err(e) => Some(format!("Failed to send: {}", e)),
+            };
+        }
+        if let Some(message) = &self.calibration_message {
+            ui.label(message);
+        }
+    }
+
+    fn render_chain_settings(&mut self, ui: &mut Ui) {
+        ui.add(Separator::default());
+        ui.label(format!("Kinematic Chain: {} ({} joints)", self.arm.chain().name, self.arm.chain().joints.len()));
+        ui.horizontal(|ui| {
+            let path_label = ui.label("Chain File (.toml or .json):");
+            ui.text_edit_singleline(&mut self.chain_path).labelled_by(path_label.id);
+            if ui.button("Load").clicked() {
+                self.chain_message = Some(match ChainDescription::load(Path::new(&self.chain_path)) {
+                    Ok(chain) => {
+                        self.arm.set_chain(chain);
+                        self.pose_dirty = true;
+                        format!("Loaded {}", self.chain_path)
+                    }
+                    Err(e) => e.to_string(),
+                });
             }
-            ui.label("Servo Upper:");
-            if ui.add(DragValue::new(&mut upper_limit_lower).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_upper_range = upper_limit_lower..=upper_limit;
+            if ui.button("Save").clicked() {
+                self.chain_message = Some(match self.arm.chain().save(Path::new(&self.chain_path)) {
+                    Ok(()) => format!("Saved {}", self.chain_path),
+                    Err(e) => e.to_string(),
+                });
             }
-            ui.label("Servo Elbow:");
-            if ui.add(DragValue::new(&mut elbow_limit_lower).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_elbow_range = elbow_limit_lower..=elbow_limit;
+            if ui.button("Export URDF").clicked() {
+                let path = Path::new(&self.chain_path).with_extension("urdf");
+                self.chain_message = Some(match fs::write(&path, ros::urdf(self.arm.chain(), &self.servo_limits)) {
+                    Ok(()) => format!("Exported {}", path.display()),
+                    Err(e) => format!("Unable to write {}: {}", path.display(), e),
+                });
             }
-            ui.label("Servo Lower:");
-            if ui.add(DragValue::new(&mut lower_limit_lower).speed(1).suffix("°").clamp_range(0..=270)).changed() {
-                self.servo_lower_range = lower_limit_lower..=lower_limit;
+            if ui.button("Five Servo Default").clicked() {
+                self.arm.set_chain(ChainDescription::five_servo(1.0, 1.0, 0.0));
+                self.pose_dirty = true;
+                self.chain_message = None;
             }
         });
-        ui.add(Separator::default());
-        let mdns_label = ui.label("mDNS Service Address: (NON-FUNCTIONAL SETTING)");
-        let mut shared_state_lock = self.shared_state.lock().unwrap();
-        let mut mdns_address = shared_state_lock.service.clone();
-        ui.text_edit_singleline(&mut mdns_address).labelled_by(mdns_label.id);
-        if self.ip_addr_string != mdns_address{
-            shared_state_lock.set_service(mdns_address)
+        if let Some(message) = &self.chain_message {
+            ui.label(message);
         }
     }
 
-    fn render_receive_ui(&mut self) {
-        // Receive data
-        match self.udp_socket.recv_from(&mut self.receive_vec) {
-            Ok(_) => {}
-            Err(_) => {}
-        };
-        for (i, chunk) in self.receive_vec.chunks_exact(2).enumerate() {
-            let adc_value = u16::from_be_bytes(chunk.try_into().unwrap());
-            // Process these !TODO
+    // What the device we're sending to can do, from its TXT record if it was picked from the device list, and from its Hello
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = self
+            .selected_device
+            .as_deref()
+            .and_then(|instance| self.shared_state.lock().unwrap().devices.get(instance).map(|device| device.capabilities.clone()))
+            .unwrap_or_default();
+        if let Some((protocol_version, servo_count)) = self.hello {
+            capabilities.apply_hello(protocol_version, servo_count);
         }
+        capabilities
+    }
 
+    fn render_capability_warnings(ui: &mut Ui, capabilities: &Capabilities) {
+        if let Some(firmware) = &capabilities.firmware {
+            ui.label(format!("Firmware {}", firmware));
+        }
+        for warning in capabilities.warnings() {
+            ui.colored_label(egui::Color32::YELLOW, warning);
+        }
     }
 
-    fn mdns_button(ui: &mut Ui, sock: &mut String, shared_state: &Arc<Mutex<SharedState>>) {
-        // Acquire the lock and immediately scope it to limit its duration
-        let first_ip_option = {
-            let shared_state_lock = shared_state.lock().unwrap();
-            shared_state_lock.discovered_ips.first().cloned() // Clone the first IP if it exists
-        };
+    // Devices found over mDNS. Picking one points the controller at it, and keeps following it if its address changes.
+    fn render_device_list(&mut self, ui: &mut Ui) {
+        let now = Instant::now();
+        let mut state = self.shared_state.lock().unwrap();
+        state.devices.expire(now);
+
+        if let Some(device) = self.selected_device.as_deref().and_then(|instance| state.devices.get(instance)) {
+            let addresses = state.devices.addresses(device, now);
+            if device.is_online(now) && !addresses.is_empty() && !addresses.iter().any(|address| address.to_string() == self.send_to) {
+                self.send_to = addresses[0].to_string();
+                self.ip_addr_string = self.send_to.clone();
+            }
+        }
+
+        let online = state.devices.devices().filter(|device| device.is_online(now)).count();
+        let mut selected = None;
+        egui::CollapsingHeader::new(format!("Devices ({} online)", online)).id_source("devices").show(ui, |ui| {
+            if state.devices.devices().next().is_none() {
+                ui.label("No devices found yet");
+                return;
+            }
+            egui::Grid::new("device_list").striped(true).show(ui, |ui| {
+                for device in state.devices.devices() {
+                    let is_online = device.is_online(now);
+                    let addresses = state.devices.addresses(device, now);
+                    let (rect, indicator) = ui.allocate_exact_size(vec2(12.0, 12.0), Sense::hover());
+                    ui.painter().circle_filled(rect.center(), 4.0, if is_online { egui::Color32::GREEN } else { egui::Color32::GRAY });
+                    indicator.on_hover_text(if is_online { "Online" } else { "Offline" });
+                    let is_selected = self.selected_device.as_deref() == Some(device.instance.as_str());
+                    let details = format!(
+                        "{}\nHost: {}\nFirmware: {}\nFirst seen {:.0} s ago\n{}",
+                        device.instance,
+                        device.hostname.as_deref().unwrap_or("unknown"),
+                        device.capabilities.firmware.as_deref().unwrap_or("unknown"),
+                        now.duration_since(device.first_seen).as_secs_f64(),
+                        device.txt.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("\n"),
+                    );
+                    if ui.selectable_label(is_selected, device.name()).on_hover_text(details).clicked() {
+                        selected = Some((device.instance.clone(), addresses.first().copied()));
+                    }
+                    ui.label(if addresses.is_empty() {
+                        "No address yet".to_owned()
+                    } else {
+                        addresses.iter().map(|address| address.to_string()).collect::<Vec<_>>().join(", ")
+                    });
+                    ui.label(format!("Seen {:.0} s ago", now.duration_since(device.last_seen).as_secs_f64()));
+                    ui.end_row();
+                }
+            });
+        });
+        drop(state);
+
+        if let Some((instance, address)) = selected {
+            self.selected_device = Some(instance);
+            // The Hello we have came from whatever we were talking to before
+            self.hello = None;
+            if let Some(address) = address {
+                self.send_to = address.to_string();
+                self.ip_addr_string = self.send_to.clone();
+            }
+        }
+    }
+
+    // Handles anything the arm has sent, pings it, and resends the latest command if its ack is overdue
+    fn service_delivery(&mut self, ui: &mut Ui) {
+        self.receive_data();
+        let now = Instant::now();
+
+        self.health.set_target(&self.send_to);
+        if self.capabilities().supports(MessageType::Ping) {
+            if let Some(id) = self.health.ping_due(now) {
+                if let Err(e) = self.send_message(&Message::Ping { id }) {
+                    log::warn!("Failed to send ping: {}", e);
+                }
+            }
+            ui.ctx().request_repaint_after(self.health.next_ping_in(now));
+        }
+
+        // Retrying into a dead link only uses up the attempts, so hold off until it comes back
+        if !self.health.is_dead(now) {
+            if let Some(command) = self.delivery.retry(now) {
+                let (id, servos) = (command.id, command.servos);
+                if let Err(e) = self.transmit(id, &servos) {
+                    log::warn!("Failed to resend command {}: {}", id, e);
+                }
+            }
+        }
+        // Nothing else wakes the UI up, so make sure we're back in time for the next retry
+        if let Some(wait) = self.delivery.next_retry_in(now) {
+            ui.ctx().request_repaint_after(wait);
+        }
+    }
+
+    // Answers whatever the HTTP API has asked for since the last frame
+    fn handle_api_requests(&mut self) {
+        while let Some(request) = self.api.as_mut().and_then(|api| api.requests.try_recv().ok()) {
+            let reply = self.api_reply(request.command);
+            // The client may have given up waiting, which is nothing to worry about
+            let _ = request.reply.send(reply);
+        }
+    }
 
-        // Use the first IP option outside the lock scope
-        match first_ip_option {
-            Some(mdns_ip) => {
-                if ui.button("mDNS").clicked() {
-                    *sock = mdns_ip.to_string();
+    fn api_reply(&mut self, command: ApiCommand) -> ApiReply {
+        match command {
+            ApiCommand::GetServos => Ok(self.servos_json()),
+            ApiCommand::SetServos(angles) => self.api_set_servos(angles),
+            ApiCommand::MoveTo(target) => {
+                self.check_api_can_move()?;
+                (self.target_i, self.target_j, self.target_k) = (target.i, target.j, target.k);
+                let moved = match target.solver {
+                    Solver::Numeric => self.apply_target_numeric(),
+                    Solver::ClosedForm => self.apply_target(),
+                };
+                if !moved {
+                    return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, self.ik_message.clone().unwrap_or_default()));
                 }
+                self.api_send()
+            }
+            ApiCommand::GetTelemetry => Ok(self.telemetry_json()),
+            ApiCommand::GetDevices => Ok(self.devices_json()),
+            ApiCommand::EmergencyStop => {
+                self.trigger_emergency_stop();
+                let id = self.estop.engaged().map(|stop| stop.id);
+                Ok(json!({ "engaged": true, "id": id }))
+            }
+            ApiCommand::GetChain => Ok(json!(self.arm.chain())),
+        }
+    }
+
+    fn api_set_servos(&mut self, angles: ServoAngles) -> ApiReply {
+        self.check_api_can_move()?;
+        let mut servo_angles = self.arm.servo_angles();
+        for (servo, angle) in angles.in_wire_order().into_iter().enumerate() {
+            let Some(angle) = angle else {
+                continue;
+            };
+            if !angle.is_finite() {
+                return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{} angle isn't a number", arm::SERVO_NAMES[servo])));
+            }
+            servo_angles[servo] = self.servo_limits[servo].clamp(angle);
+        }
+        self.arm.set_servo_angles(&servo_angles);
+        self.api_send()
+    }
+
+    // The API may only move the arm when the UI could
+    fn check_api_can_move(&self) -> Result<(), ApiError> {
+        if self.estop.is_engaged() {
+            return Err(ApiError::new(StatusCode::CONFLICT, "Emergency stop engaged, re-arm from the controller first"));
+        }
+        if self.mode != Mode::Sending {
+            return Err(ApiError::new(StatusCode::CONFLICT, format!("The controller is in {:?} mode, switch to Sending first", self.mode)));
+        }
+        if self.playback.as_ref().is_some_and(|playback| !playback.paused) || self.sequence_run.as_ref().is_some_and(|(_, playback)| !playback.paused) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "Playback is running"));
+        }
+        // Like Auto Send, nothing is sent to an arm that isn't answering, and the pose is left alone so the reply is the truth
+        if self.health.is_dead(Instant::now()) {
+            return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Link is down, the arm isn't answering"));
+        }
+        Ok(())
+    }
+
+    fn api_send(&mut self) -> ApiReply {
+        self.arm.update();
+        self.send_data().map_err(|e| ApiError::new(StatusCode::BAD_GATEWAY, e.to_string()))?;
+        self.pose_dirty = false;
+        Ok(self.servos_json())
+    }
+
+    fn servos_json(&self) -> serde_json::Value {
+        let angles: serde_json::Map<String, serde_json::Value> =
+            arm::SERVO_NAMES.iter().zip(self.arm.servo_angles()).map(|(name, angle)| (name.to_lowercase(), json!(angle))).collect();
+        let limits: serde_json::Map<String, serde_json::Value> = arm::SERVO_NAMES
+            .iter()
+            .zip(self.servo_limits)
+            .map(|(name, limit)| (name.to_lowercase(), json!([limit.min, limit.max])))
+            .collect();
+        let (i, j, k) = self.arm.get_ijk();
+        let command = self.delivery.latest().map(|command| {
+            json!({ "id": command.id, "state": command.state.to_string(), "attempts": command.attempts })
+        });
+        json!({ "angles": angles, "limits": limits, "position": { "i": i, "j": j, "k": k }, "command": command })
+    }
+
+    fn telemetry_json(&self) -> serde_json::Value {
+        let feedback = self.feedback.map(|feedback| {
+            arm::SERVO_NAMES
+                .iter()
+                .zip(self.calibration.to_angles(&feedback))
+                .map(|(name, angle)| (name.to_lowercase(), json!(angle)))
+                .collect::<serde_json::Map<String, serde_json::Value>>()
+        });
+        let stats = &self.receive_stats;
+        let health = self.health.stats(Instant::now());
+        let milliseconds = |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.0);
+        json!({
+            "feedback": feedback,
+            "adc": self.adc_values,
+            "seconds_since_last_received": self.telemetry.seconds_since_last_received(),
+            "packets": { "received": stats.received, "dropped": stats.dropped, "reordered": stats.reordered, "corrupted": stats.corrupted },
+            "link": {
+                "status": health.status.to_string(),
+                "round_trip_ms": milliseconds(health.round_trip),
+                "jitter_ms": milliseconds(health.jitter),
+                "loss": health.loss,
+            },
+            "estop_engaged": self.estop.is_engaged(),
+        })
+    }
+
+    fn devices_json(&self) -> serde_json::Value {
+        let state = self.shared_state.lock().unwrap();
+        let now = Instant::now();
+        let devices: Vec<serde_json::Value> = state
+            .devices
+            .devices()
+            .map(|device| {
+                let addresses: Vec<String> = state.devices.addresses(device, now).iter().map(|address| address.to_string()).collect();
+                let capabilities = &device.capabilities;
+                json!({
+                    "name": device.name(),
+                    "instance": device.instance,
+                    "online": device.is_online(now),
+                    "addresses": addresses,
+                    "firmware": capabilities.firmware,
+                    "protocol_version": capabilities.protocol_version,
+                    "servo_count": capabilities.servo_count,
+                    "adc_channels": capabilities.adc_channels,
+                    "warnings": capabilities.warnings(),
+                    "selected": self.selected_device.as_deref() == Some(device.instance.as_str()),
+                })
+            })
+            .collect();
+        json!(devices)
+    }
+
+    // Stops the arm from any mode. This is the one thing that is sent even in Receiving mode.
+    fn trigger_emergency_stop(&mut self) {
+        self.delivery.cancel();
+        self.pose_dirty = false;
+        if let Some(playback) = self.playback.as_mut() {
+            playback.paused = true;
+        }
+        if let Some((_, playback)) = self.sequence_run.as_mut() {
+            playback.paused = true;
+        }
+        let id = self.estop.trigger(Instant::now());
+        if let Err(e) = self.send_message(&Message::EmergencyStop { id }) {
+            self.transport_error = Some(format!("Failed to send emergency stop: {}", e));
+        }
+    }
+
+    // Keeps sending the stop until the arm acks it. Unlike commands this ignores the link health, there's no giving up on a stop.
+    fn service_emergency_stop(&mut self, ui: &mut Ui) {
+        let now = Instant::now();
+        if let Some(id) = self.estop.resend_due(now) {
+            if let Err(e) = self.send_message(&Message::EmergencyStop { id }) {
+                self.transport_error = Some(format!("Failed to send emergency stop: {}", e));
             }
-            None => {}
         }
+        if let Some(wait) = self.estop.next_resend_in(now) {
+            ui.ctx().request_repaint_after(wait);
+        }
+    }
+
+    // The E-STOP button, or while the stop is engaged, how it's going and the way to re-arm
+    fn render_emergency_stop(&mut self, ui: &mut Ui) {
+        if ui.input(|input| input.key_pressed(egui::Key::Escape)) {
+            self.trigger_emergency_stop();
+        }
+        let mut rearm = false;
+        ui.horizontal(|ui| {
+            let button = egui::Button::new(egui::RichText::new("E-STOP").size(24.0).strong().color(egui::Color32::WHITE))
+                .fill(egui::Color32::from_rgb(200, 0, 0))
+                .min_size(vec2(140.0, 48.0));
+            if ui.add(button).on_hover_text("Stop and relax every servo (Esc)").clicked() {
+                self.trigger_emergency_stop();
+            }
+            let Some(stop) = self.estop.engaged() else {
+                return;
+            };
+            ui.vertical(|ui| {
+                let triggered_at = stop.triggered_at.format("%H:%M:%S");
+                match stop.acked {
+                    Some(round_trip) => ui.colored_label(egui::Color32::RED, format!("Stopped at {}, acked in {} ms", triggered_at, round_trip.as_millis())),
+                    None => ui.colored_label(egui::Color32::RED, format!("Stopping since {}, no ack after {} attempt(s)", triggered_at, stop.attempts)),
+                };
+                rearm = if stop.acked.is_some() {
+                    ui.button("Re-arm").clicked()
+                } else {
+                    ui.button("Re-arm without ack").on_hover_text("The arm hasn't confirmed that it stopped").clicked()
+                };
+            });
+        });
+        if rearm {
+            // Nothing goes out until the pose is changed again
+            self.estop.rearm();
+            self.pose_dirty = false;
+        }
+        if self.estop.log().next().is_some() {
+            egui::CollapsingHeader::new("Emergency Stop Log").show(ui, |ui| {
+                for (at, message) in self.estop.log() {
+                    ui.label(format!("{}  {}", at.format("%Y-%m-%d %H:%M:%S%.3f"), message));
+                }
+            });
+        }
+    }
+
+    // Status light and round trip graph for the arm at send_to
+    fn render_link_health(&self, ui: &mut Ui) {
+        let stats = self.health.stats(Instant::now());
+        let colour = match stats.status {
+            LinkStatus::Unknown => egui::Color32::GRAY,
+            LinkStatus::Alive => egui::Color32::GREEN,
+            LinkStatus::Degraded => egui::Color32::YELLOW,
+            LinkStatus::Dead => egui::Color32::RED,
+        };
+        let milliseconds = |duration: Option<Duration>| duration.map_or("-".to_owned(), |duration| format!("{:.1} ms", duration.as_secs_f64() * 1000.0));
+        let (rect, light) = ui.allocate_exact_size(vec2(12.0, 12.0), Sense::hover());
+        ui.painter().circle_filled(rect.center(), 5.0, colour);
+        light.on_hover_text(format!(
+            "Link {}\nRound trip: {}\nJitter: {}\nLoss: {:.0}%",
+            stats.status,
+            milliseconds(stats.round_trip),
+            milliseconds(stats.jitter),
+            stats.loss * 100.0
+        ));
+        ui.label(milliseconds(stats.round_trip));
+
+        let now = self.health.now();
+        let points: PlotPoints = self.health.history.iter().map(|[time, round_trip]| [time - now, *round_trip]).collect();
+        Plot::new("link_health")
+            .width(150.0)
+            .height(30.0)
+            .show_axes([false, false])
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .include_x(-HISTORY_SECONDS)
+            .include_x(0.0)
+            .include_y(0.0)
+            .show(ui, |plot_ui| plot_ui.line(Line::new(points)));
+    }
+
+    fn render_delivery_ui(&self, ui: &mut Ui) {
+        match self.delivery.latest() {
+            Some(command) => ui.label(format!("Command {} {} after {} attempt(s)", command.id, command.state, command.attempts)),
+            None => ui.label("No commands sent"),
+        };
+        egui::CollapsingHeader::new("Recent Commands").show(ui, |ui| {
+            for command in self.delivery.history() {
+                ui.label(format!("{}: {:?} {}, {} attempt(s)", command.id, command.servos, command.state, command.attempts));
+            }
+        });
+    }
+
+    // Passive dashboard of whatever the arm reports. Nothing in here may send.
+    fn render_receiving_mode_ui(&mut self, ui: &mut Ui) {
+        match self.transport.local_address() {
+            Some(address) => ui.label(format!("Listening on {}", address)),
+            None => ui.label("Not listening"),
+        };
+        ui.label(match (self.telemetry.seconds_since_last_received(), self.last_sender) {
+            (Some(seconds), Some(sender)) => format!("Last report from {} {:.1} s ago", sender, seconds),
+            (Some(seconds), None) => format!("Last report {:.1} s ago", seconds),
+            (None, _) => "Nothing received yet".to_owned(),
+        });
+        let stats = &self.receive_stats;
+        ui.label(format!(
+            "Packets received: {}, dropped: {}, reordered: {}, corrupted: {}",
+            stats.received, stats.dropped, stats.reordered, stats.corrupted
+        ));
+        let capabilities = self.capabilities();
+        Controller::render_capability_warnings(ui, &capabilities);
+        // Only show what the device has, when it has said
+        let servo_count = capabilities.servo_count.unwrap_or(arm::SERVO_COUNT);
+        let adc_channels = capabilities.adc_channels.unwrap_or(usize::MAX);
+
+        // Live readouts
+        egui::Grid::new("telemetry_readouts").striped(true).show(ui, |ui| {
+            for (name, series) in arm::SERVO_NAMES.iter().zip(&self.telemetry.servos).take(servo_count) {
+                ui.label(*name);
+                ui.label(match Telemetry::latest(series) {
+                    Some(angle) => format!("{:.1}°", angle),
+                    None => "-".to_owned(),
+                });
+                ui.end_row();
+            }
+            for (channel, series) in self.telemetry.adc.iter().enumerate().take(adc_channels) {
+                ui.label(format!("ADC {}", channel));
+                ui.label(Telemetry::latest(series).map(|value| format!("{:.0}", value)).unwrap_or_default());
+                ui.end_row();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Window:");
+            ui.add(Slider::new(&mut self.telemetry_window, 5.0..=crate::telemetry::MAX_HISTORY_SECONDS).suffix(" s"));
+            if ui.button("Clear").clicked() {
+                self.telemetry.clear();
+            }
+        });
+
+        // Scrolling charts, the right hand edge is always now
+        let now = self.telemetry.now();
+        let since = now - self.telemetry_window;
+        let recent = |series: &crate::telemetry::Series| -> PlotPoints {
+            series.iter().filter(|[time, _]| *time >= since).map(|[time, value]| [time - now, *value]).collect()
+        };
+        ui.label("Servo Angles");
+        Plot::new("telemetry_servos")
+            .legend(Legend::default())
+            .height(200.0)
+            .include_x(-self.telemetry_window)
+            .include_x(0.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show(ui, |plot_ui| {
+                for (name, series) in arm::SERVO_NAMES.iter().zip(&self.telemetry.servos).take(servo_count) {
+                    plot_ui.line(Line::new(recent(series)).name(*name));
+                }
+            });
+        ui.label("ADC");
+        Plot::new("telemetry_adc")
+            .legend(Legend::default())
+            .height(200.0)
+            .include_x(-self.telemetry_window)
+            .include_x(0.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show(ui, |plot_ui| {
+                for (channel, series) in self.telemetry.adc.iter().enumerate().take(adc_channels) {
+                    plot_ui.line(Line::new(recent(series)).name(format!("ADC {}", channel)));
+                }
+            });
+
+        // Keep the charts scrolling even when nothing else is happening
+        ui.ctx().request_repaint_after(Duration::from_millis(50));
     }
 
+    // Handles everything the transport task has received since the last frame
+    fn receive_data(&mut self) {
+        while let Some(event) = self.transport.try_recv() {
+            let (at, packet) = match event {
+                TransportEvent::Received { from, at, packet } => {
+                    self.last_sender = Some(from);
+                    (at, packet)
+                }
+                TransportEvent::SendFailed { to, error } => {
+                    self.transport_error = Some(format!("Failed to send to {}: {}", to, error));
+                    continue;
+                }
+            };
+            let packet = match packet {
+                Ok(packet) => packet,
+                Err(e) => {
+                    self.receive_stats.corrupted();
+                    self.receive_error = Some(e.to_string());
+                    continue;
+                }
+            };
+            // The arm starts counting again from zero when it restarts, which it announces with a Hello
+            if let Message::Hello { protocol_version, servo_count, .. } = packet.message {
+                self.receive_stats.restart();
+                self.hello = Some((protocol_version, servo_count));
+            }
+            // Anything older than what we already have is stale, so drop it
+            if !self.receive_stats.accept(packet.sequence) {
+                continue;
+            }
+            match packet.message {
+                Message::Ack { message_type, id } if message_type == MessageType::ServoCommand as u8 => {
+                    self.delivery.acknowledge(id, at);
+                }
+                Message::Ack { message_type, id } if message_type == MessageType::EmergencyStop as u8 => {
+                    self.estop.acknowledge(id, at);
+                }
+                Message::Pong { id } => self.health.pong(id, at),
+                Message::ServoFeedback { servos } => {
+                    let angles = self.calibration.to_angles(&servos);
+                    self.telemetry.record_servos(&angles, at);
+                    self.publish(EventData::Feedback { angles, wire: servos });
+                    self.feedback = Some(servos);
+                }
+                Message::SensorReport { adc } => {
+                    self.telemetry.record_adc(&adc, at);
+                    self.publish(EventData::Adc { values: adc.clone() });
+                    self.adc_values = adc;
+                }
+                Message::Error { code, message } => {
+                    self.receive_error = Some(format!("Arm reported error {}: {}", code, message));
+                }
+                _ => {}
+            }
+        }
+    }
 
     fn render_plot(&mut self, ui: &mut Ui) {
         let w = 640;
@@ -437,12 +1844,12 @@ impl Controller {
         // Do the above but handle with a match
         match image_data {
             Ok(_) => {
-                let image = ColorImage::from_rgb([w, h], &mut buf);
+                let image = ColorImage::from_rgb([w, h], &buf);
 
                 // Thanks to https://github.com/emilk/egui/discussions/3431
                 // you must keep the handle, if the handle is destroyed so the texture will be destroyed as well
                 let handle = ui.ctx().load_texture("Arm Positions", image.clone(), TextureOptions::default());
-                let sized_image = egui::load::SizedTexture::new(handle.id(), vec2(*&image.size[0] as f32, *&image.size[1] as f32));
+                let sized_image = egui::load::SizedTexture::new(handle.id(), vec2(image.size[0] as f32, image.size[1] as f32));
                 let image = Image::from_texture(sized_image);
                 ui.add(image);
                 ui.add(Slider::new(&mut self.plot_yaw, 0.0..=5.0).text("Yaw"));
@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::arm::SERVO_COUNT;
+
+// How many of the most recent commands are kept around for the UI
+pub const HISTORY_LENGTH: usize = 10;
+
+// How long to wait for an ack before sending a command again. The wait is multiplied by
+// the backoff after every attempt, up to the maximum, and the command fails after max_attempts sends.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub initial_timeout_ms: u64,
+    pub backoff: f64,
+    pub max_timeout_ms: u64,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_timeout_ms: 100,
+            backoff: 2.0,
+            max_timeout_ms: 1000,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn timeout_after(&self, attempts: u32) -> Duration {
+        let timeout = self.initial_timeout_ms as f64 * self.backoff.max(1.0).powi(attempts.saturating_sub(1) as i32);
+        Duration::from_millis(timeout.min(self.max_timeout_ms as f64) as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandState {
+    // Sent, waiting for the arm to ack it
+    Pending,
+    // The arm acked it, round trip measured from the first send
+    Acked { round_trip: Duration },
+    // No ack after every attempt
+    Failed,
+    // A newer command was sent before this one was acked, so it is no longer worth retrying
+    Superseded,
+    // Given up on by the emergency stop
+    Cancelled,
+}
+
+impl fmt::Display for CommandState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandState::Pending => write!(f, "pending"),
+            CommandState::Acked { round_trip } => write!(f, "acked in {} ms", round_trip.as_millis()),
+            CommandState::Failed => write!(f, "failed"),
+            CommandState::Superseded => write!(f, "superseded"),
+            CommandState::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub id: u16,
+    // Wire values, in wire order
+    pub servos: [u16; SERVO_COUNT],
+    pub state: CommandState,
+    pub attempts: u32,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+// Tracks servo commands until the arm acks them, and decides when to send them again.
+// Only the newest command is ever retried, an older pose is never worth going back to.
+pub struct Delivery {
+    pub policy: RetryPolicy,
+    next_id: u16,
+    history: VecDeque<Command>,
+}
+
+impl Delivery {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Delivery {
+            policy,
+            next_id: 0,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    // Starts tracking a new command and returns the ID to send it with
+    pub fn start(&mut self, servos: [u16; SERVO_COUNT], now: Instant) -> u16 {
+        for command in self.history.iter_mut() {
+            if command.state == CommandState::Pending {
+                command.state = CommandState::Superseded;
+            }
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(Command {
+            id,
+            servos,
+            state: CommandState::Pending,
+            attempts: 1,
+            first_sent: now,
+            last_sent: now,
+        });
+        id
+    }
+
+    // Marks a command as acked. Returns false for IDs we aren't waiting on, such as a duplicate ack.
+    pub fn acknowledge(&mut self, id: u16, now: Instant) -> bool {
+        match self.history.iter_mut().find(|command| command.id == id) {
+            Some(command) if command.state == CommandState::Pending || command.state == CommandState::Superseded => {
+                command.state = CommandState::Acked { round_trip: now - command.first_sent };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Stops retrying everything still waiting on an ack
+    pub fn cancel(&mut self) {
+        for command in self.history.iter_mut() {
+            if command.state == CommandState::Pending {
+                command.state = CommandState::Cancelled;
+            }
+        }
+    }
+
+    // Returns the command that has waited too long for its ack and should be sent again, if any.
+    // Once a command has used all of its attempts it is marked as failed instead.
+    pub fn retry(&mut self, now: Instant) -> Option<&Command> {
+        let policy = self.policy;
+        let command = self.history.back_mut()?;
+        if command.state != CommandState::Pending || now < command.last_sent + policy.timeout_after(command.attempts) {
+            return None;
+        }
+        if command.attempts >= policy.max_attempts {
+            command.state = CommandState::Failed;
+            return None;
+        }
+        command.attempts += 1;
+        command.last_sent = now;
+        Some(command)
+    }
+
+    // How long until retry() will next have something to do, for scheduling a repaint
+    pub fn next_retry_in(&self, now: Instant) -> Option<Duration> {
+        let command = self.history.back()?;
+        if command.state != CommandState::Pending {
+            return None;
+        }
+        Some((command.last_sent + self.policy.timeout_after(command.attempts)).saturating_duration_since(now))
+    }
+
+    pub fn latest(&self) -> Option<&Command> {
+        self.history.back()
+    }
+
+    // Newest first
+    pub fn history(&self) -> impl Iterator<Item = &Command> {
+        self.history.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVOS: [u16; SERVO_COUNT] = [95, 40, 90, 0, 90];
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn timeout_backs_off_up_to_the_maximum() {
+        let policy = RetryPolicy::default();
+        let timeouts: Vec<u64> = (1..=6).map(|attempts| policy.timeout_after(attempts).as_millis() as u64).collect();
+        assert_eq!(timeouts, [100, 200, 400, 800, 1000, 1000]);
+        // A backoff below 1 would shrink the wait, so it's treated as no backoff
+        let flat = RetryPolicy { backoff: 0.5, ..RetryPolicy::default() };
+        assert_eq!(flat.timeout_after(4), ms(100));
+    }
+
+    #[test]
+    fn retries_until_attempts_run_out() {
+        let start = Instant::now();
+        let mut delivery = Delivery::new(RetryPolicy::default());
+        let id = delivery.start(SERVOS, start);
+        assert_eq!(delivery.next_retry_in(start), Some(ms(100)));
+        assert!(delivery.retry(start + ms(99)).is_none());
+
+        // Each retry waits for the backed off timeout from the previous send
+        let mut now = start;
+        for (attempts, wait) in [(2, 100), (3, 200), (4, 400), (5, 800)] {
+            now += ms(wait);
+            let command = delivery.retry(now).expect("Retry should be due");
+            assert_eq!((command.id, command.attempts, command.servos), (id, attempts, SERVOS));
+        }
+        assert_eq!(delivery.next_retry_in(now), Some(ms(1000)));
+        assert!(delivery.retry(now + ms(999)).is_none());
+        assert!(delivery.retry(now + ms(1000)).is_none());
+        assert_eq!(delivery.latest().unwrap().state, CommandState::Failed);
+        assert_eq!(delivery.next_retry_in(now + ms(1000)), None);
+    }
+
+    #[test]
+    fn ack_stops_retries_and_measures_from_the_first_send() {
+        let start = Instant::now();
+        let mut delivery = Delivery::new(RetryPolicy::default());
+        let id = delivery.start(SERVOS, start);
+        assert!(delivery.retry(start + ms(100)).is_some());
+        assert!(delivery.acknowledge(id, start + ms(130)));
+        assert_eq!(delivery.latest().unwrap().state, CommandState::Acked { round_trip: ms(130) });
+        assert_eq!(delivery.next_retry_in(start + ms(130)), None);
+        assert!(delivery.retry(start + ms(10_000)).is_none());
+        // Duplicate acks, and acks for IDs never sent, are ignored
+        assert!(!delivery.acknowledge(id, start + ms(150)));
+        assert!(!delivery.acknowledge(id.wrapping_add(1), start + ms(150)));
+    }
+
+    #[test]
+    fn newer_commands_supersede_older_ones() {
+        let start = Instant::now();
+        let mut delivery = Delivery::new(RetryPolicy::default());
+        let first = delivery.start(SERVOS, start);
+        let second = delivery.start([90; SERVO_COUNT], start + ms(10));
+        assert_ne!(first, second);
+        let states: Vec<CommandState> = delivery.history().map(|command| command.state).collect();
+        assert_eq!(states, [CommandState::Pending, CommandState::Superseded]);
+        // Only the newest command is retried
+        assert_eq!(delivery.retry(start + ms(110)).map(|command| command.id), Some(second));
+        // A late ack for the superseded command still records its round trip
+        assert!(delivery.acknowledge(first, start + ms(120)));
+        assert_eq!(delivery.history().nth(1).unwrap().state, CommandState::Acked { round_trip: ms(120) });
+    }
+
+    #[test]
+    fn history_keeps_the_newest_commands() {
+        let start = Instant::now();
+        let mut delivery = Delivery::new(RetryPolicy::default());
+        let ids: Vec<u16> = (0..HISTORY_LENGTH + 3).map(|_| delivery.start(SERVOS, start)).collect();
+        let kept: Vec<u16> = delivery.history().map(|command| command.id).collect();
+        assert_eq!(kept.len(), HISTORY_LENGTH);
+        assert_eq!(kept[0], *ids.last().unwrap());
+        assert!(!kept.contains(&ids[2]));
+    }
+
+    #[test]
+    fn cancel_gives_up_on_pending_commands() {
+        let start = Instant::now();
+        let mut delivery = Delivery::new(RetryPolicy::default());
+        let acked = delivery.start(SERVOS, start);
+        assert!(delivery.acknowledge(acked, start + ms(20)));
+        let pending = delivery.start(SERVOS, start + ms(30));
+        delivery.cancel();
+        let states: Vec<CommandState> = delivery.history().map(|command| command.state).collect();
+        assert_eq!(states, [CommandState::Cancelled, CommandState::Acked { round_trip: ms(20) }]);
+        assert!(delivery.retry(start + ms(10_000)).is_none());
+        assert_eq!(delivery.next_retry_in(start + ms(30)), None);
+        assert!(!delivery.acknowledge(pending, start + ms(40)));
+    }
+}
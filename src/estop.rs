@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+// How often an unacked stop is sent again. A stop never runs out of attempts, it is sent until the arm acks it.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(50);
+// How many events are kept for the UI
+pub const LOG_LENGTH: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct Stop {
+    pub id: u16,
+    pub triggered_at: DateTime<Local>,
+    pub attempts: u32,
+    // Round trip from the first send, once the arm has acked it
+    pub acked: Option<Duration>,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+// The emergency stop. Once triggered it stays engaged, and the controller sends nothing that could move the arm,
+// until it is explicitly re-armed.
+pub struct EmergencyStop {
+    next_id: u16,
+    engaged: Option<Stop>,
+    log: VecDeque<(DateTime<Local>, String)>,
+}
+
+impl EmergencyStop {
+    pub fn new() -> Self {
+        EmergencyStop {
+            next_id: 0,
+            engaged: None,
+            log: VecDeque::with_capacity(LOG_LENGTH),
+        }
+    }
+
+    // Engages the stop and returns the ID to send it with. Triggering it again while engaged sends the same stop again straight away.
+    pub fn trigger(&mut self, now: Instant) -> u16 {
+        if let Some(stop) = self.engaged.as_mut() {
+            stop.attempts += 1;
+            stop.last_sent = now;
+            return stop.id;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.engaged = Some(Stop {
+            id,
+            triggered_at: Local::now(),
+            attempts: 1,
+            acked: None,
+            first_sent: now,
+            last_sent: now,
+        });
+        self.record(format!("Emergency stop {} triggered", id));
+        id
+    }
+
+    // Returns false for acks of a stop we aren't waiting on
+    pub fn acknowledge(&mut self, id: u16, now: Instant) -> bool {
+        let Some(stop) = self.engaged.as_mut().filter(|stop| stop.id == id && stop.acked.is_none()) else {
+            return false;
+        };
+        let round_trip = now.saturating_duration_since(stop.first_sent);
+        stop.acked = Some(round_trip);
+        let message = format!("Emergency stop {} acked after {} attempt(s) in {} ms", id, stop.attempts, round_trip.as_millis());
+        self.record(message);
+        true
+    }
+
+    // Returns the ID of the stop to send again, if it is engaged, unacked and due
+    pub fn resend_due(&mut self, now: Instant) -> Option<u16> {
+        let stop = self.engaged.as_mut().filter(|stop| stop.acked.is_none())?;
+        if now < stop.last_sent + RESEND_INTERVAL {
+            return None;
+        }
+        stop.attempts += 1;
+        stop.last_sent = now;
+        Some(stop.id)
+    }
+
+    // How long until resend_due() will next have something to do, for scheduling a repaint
+    pub fn next_resend_in(&self, now: Instant) -> Option<Duration> {
+        let stop = self.engaged.as_ref().filter(|stop| stop.acked.is_none())?;
+        Some((stop.last_sent + RESEND_INTERVAL).saturating_duration_since(now))
+    }
+
+    pub fn rearm(&mut self) {
+        if let Some(stop) = self.engaged.take() {
+            let message = match stop.acked {
+                Some(_) => format!("Re-armed after emergency stop {}", stop.id),
+                None => format!("Re-armed after emergency stop {} without an ack", stop.id),
+            };
+            self.record(message);
+        }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.is_some()
+    }
+
+    pub fn engaged(&self) -> Option<&Stop> {
+        self.engaged.as_ref()
+    }
+
+    // Newest first
+    pub fn log(&self) -> impl Iterator<Item = &(DateTime<Local>, String)> {
+        self.log.iter().rev()
+    }
+
+    fn record(&mut self, message: String) {
+        let at = Local::now();
+        log::warn!("{} at {}", message, at.format("%Y-%m-%d %H:%M:%S%.3f"));
+        if self.log.len() == LOG_LENGTH {
+            self.log.pop_front();
+        }
+        self.log.push_back((at, message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_is_resent_until_acked() {
+        let start = Instant::now();
+        let mut estop = EmergencyStop::new();
+        let id = estop.trigger(start);
+        assert!(estop.is_engaged());
+        assert_eq!(estop.next_resend_in(start), Some(RESEND_INTERVAL));
+        assert_eq!(estop.resend_due(start + RESEND_INTERVAL / 2), None);
+        // It never runs out of attempts
+        let mut now = start;
+        for _ in 0..100 {
+            now += RESEND_INTERVAL;
+            assert_eq!(estop.resend_due(now), Some(id));
+        }
+        assert_eq!(estop.engaged().unwrap().attempts, 101);
+
+        assert!(!estop.acknowledge(id.wrapping_add(1), now));
+        assert!(estop.acknowledge(id, now + Duration::from_millis(5)));
+        assert_eq!(estop.engaged().unwrap().acked, Some(now + Duration::from_millis(5) - start));
+        assert_eq!(estop.resend_due(now + RESEND_INTERVAL * 10), None);
+        assert_eq!(estop.next_resend_in(now), None);
+        assert!(!estop.acknowledge(id, now + Duration::from_millis(6)));
+    }
+
+    #[test]
+    fn triggering_again_resends_the_same_stop() {
+        let start = Instant::now();
+        let mut estop = EmergencyStop::new();
+        let id = estop.trigger(start);
+        assert_eq!(estop.trigger(start + Duration::from_millis(10)), id);
+        assert_eq!(estop.engaged().unwrap().attempts, 2);
+        // The repeat counts as a send, so the next resend waits a full interval from it
+        assert_eq!(estop.resend_due(start + RESEND_INTERVAL), None);
+        assert_eq!(estop.resend_due(start + Duration::from_millis(10) + RESEND_INTERVAL), Some(id));
+    }
+
+    #[test]
+    fn stays_engaged_after_the_ack_until_rearmed() {
+        let start = Instant::now();
+        let mut estop = EmergencyStop::new();
+        let first = estop.trigger(start);
+        assert!(estop.acknowledge(first, start + Duration::from_millis(20)));
+        assert!(estop.is_engaged());
+        estop.rearm();
+        assert!(!estop.is_engaged());
+        assert_eq!(estop.resend_due(start + RESEND_INTERVAL), None);
+
+        // A new stop gets a new ID, and re-arming without an ack is logged as such
+        let second = estop.trigger(start + Duration::from_secs(1));
+        assert_ne!(first, second);
+        estop.rearm();
+        let log: Vec<&str> = estop.log().map(|(_, message)| message.as_str()).collect();
+        assert_eq!(log.len(), 5);
+        assert!(log[0].contains("without an ack"), "{:?}", log);
+        assert!(log[2].starts_with("Re-armed") && !log[2].contains("without"), "{:?}", log);
+        assert!(log[3].contains("acked after 1 attempt(s) in 20 ms"), "{:?}", log);
+    }
+
+    #[test]
+    fn log_keeps_the_newest_events() {
+        let start = Instant::now();
+        let mut estop = EmergencyStop::new();
+        for _ in 0..LOG_LENGTH {
+            estop.trigger(start);
+            estop.rearm();
+        }
+        assert_eq!(estop.log().count(), LOG_LENGTH);
+        let newest = estop.log().next().unwrap();
+        assert!(newest.1.contains(&format!("emergency stop {}", LOG_LENGTH - 1)), "{}", newest.1);
+    }
+}
@@ -1,7 +1,10 @@
-use eframe::egui::{self, CentralPanel, Context};
+use eframe::egui::{CentralPanel, Context};
 use std::sync::{Arc, Mutex};
 use crate::controller::Controller;
 use crate::models::SharedState;
+use crate::config::Config;
+use crate::transport::Transport;
+use crate::api::ApiLink;
 
 
 pub struct Gui {
@@ -9,9 +12,15 @@ pub struct Gui {
 }
 
 impl Gui {
-    pub fn  new(shared_state: Arc<Mutex<SharedState>>) -> Self {
+    pub fn  new(
+        shared_state: Arc<Mutex<SharedState>>,
+        config: Config,
+        transport: Transport,
+        bind_override: Option<String>,
+        api: Option<ApiLink>,
+    ) -> Self {
 
-        let controller = Controller::new(shared_state);
+        let controller = Controller::new(shared_state, config, transport, bind_override, api);
 
         Gui {
             controller,
@@ -27,7 +36,14 @@ impl Gui {
 }
 
 impl eframe::App for Gui {
-    fn update(&mut self, ctx: &Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         self.update(ctx);
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Keep the settings for next time
+        if let Err(e) = self.controller.save_config() {
+            log::error!("Failed to save settings: {}", e);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,419 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::{Args, Subcommand};
+use tokio::runtime::Runtime;
+
+use crate::arm::{self, Arm, SERVO_COUNT};
+use crate::config::Profile;
+use crate::delivery::{CommandState, Delivery};
+use crate::models::SharedState;
+use crate::network::Network;
+use crate::protocol::{Message, MessageType, PROTOCOL_VERSION};
+use crate::recording::{RecordingError, Trajectory};
+use crate::ros;
+use crate::sequencer::{Sequence, SequenceError};
+use crate::transport::{Transport, TransportError, TransportEvent};
+
+// Headless runs send from a port of their own by default, so they don't clash with a GUI on the same machine
+pub const DEFAULT_HEADLESS_BIND_ADDRESS: &str = "0.0.0.0:0";
+// How often a sequence is sampled and sent while playing
+const PLAY_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Browse for arms over mDNS and list what was found
+    Discover {
+        /// How long to browse for, in seconds
+        #[arg(long, default_value_t = 3.0)]
+        seconds: f64,
+    },
+    /// Move the servos to the given angles in degrees, waiting for the arm to ack. Servos not given go to home.
+    Send {
+        #[command(flatten)]
+        target: Target,
+        #[arg(long)]
+        top: Option<f64>,
+        #[arg(long)]
+        shoulder: Option<f64>,
+        #[arg(long)]
+        upper: Option<f64>,
+        #[arg(long)]
+        elbow: Option<f64>,
+        #[arg(long)]
+        lower: Option<f64>,
+    },
+    /// Move every servo to the home pose, waiting for the arm to ack
+    Home {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Play a sequence saved from Sequencer mode, starting from the home pose, or a trajectory saved from a recording
+    Play {
+        /// Sequence or trajectory JSON file
+        path: PathBuf,
+        #[command(flatten)]
+        target: Target,
+        /// Playback speed, 2 plays twice as fast
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// Print everything the arm reports
+    Monitor {
+        #[command(flatten)]
+        target: Target,
+        /// Stop after this many seconds, otherwise run until interrupted
+        #[arg(long)]
+        seconds: Option<f64>,
+    },
+    /// Describe the active profile's chain and servo limits as a URDF, for RViz
+    Urdf {
+        /// File to write, otherwise it's printed
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct Target {
+    /// Address of the arm, e.g. 192.168.1.20:1234. Defaults to the active profile's.
+    #[arg(long)]
+    to: Option<String>,
+}
+
+// Runs one command to completion and returns the process exit code
+pub fn run(command: Command, profile: Profile, bind: Option<String>) -> i32 {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let result = match command {
+        Command::Discover { seconds } => runtime.block_on(discover(&profile, seconds)),
+        Command::Urdf { output } => urdf(&profile, output),
+        command => {
+            let mut session = match Session::new(&runtime, profile, bind.as_deref().unwrap_or(DEFAULT_HEADLESS_BIND_ADDRESS)) {
+                Ok(session) => session,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+            };
+            runtime.block_on(session.run(command))
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+async fn discover(profile: &Profile, seconds: f64) -> Result<(), HeadlessError> {
+    let shared_state = Arc::new(Mutex::new(SharedState::new(profile.services.clone())));
+    let discovery = tokio::spawn(Network::discover_devices(shared_state.clone()));
+    tokio::time::sleep(Duration::from_secs_f64(seconds.max(0.0))).await;
+    discovery.abort();
+
+    let state = shared_state.lock().unwrap();
+    let now = Instant::now();
+    let mut found = 0;
+    for device in state.devices.devices().filter(|device| device.is_online(now)) {
+        found += 1;
+        let addresses: Vec<String> = state.devices.addresses(device, now).iter().map(|address| address.to_string()).collect();
+        println!("{}\t{}", device.name(), addresses.join(" "));
+        let capabilities = &device.capabilities;
+        if let Some(firmware) = &capabilities.firmware {
+            println!("\tfirmware {}", firmware);
+        }
+        if let Some(version) = capabilities.protocol_version {
+            println!("\tprotocol {}", version);
+        }
+        if let Some(count) = capabilities.servo_count {
+            println!("\t{} servos", count);
+        }
+        for warning in capabilities.warnings() {
+            println!("\twarning: {}", warning);
+        }
+    }
+    if found == 0 {
+        return Err(HeadlessError::NothingFound);
+    }
+    Ok(())
+}
+
+fn urdf(profile: &Profile, output: Option<PathBuf>) -> Result<(), HeadlessError> {
+    let urdf = ros::urdf(&profile.chain, &profile.servo_limits);
+    match output {
+        Some(path) => fs::write(&path, urdf).map_err(|e| HeadlessError::Write { path, error: e }),
+        None => {
+            print!("{}", urdf);
+            Ok(())
+        }
+    }
+}
+
+// Sequences and trajectories are both JSON, trajectories are the ones with samples
+fn is_trajectory(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .is_some_and(|value| value.get("samples").is_some())
+}
+
+// One bound socket and the profile's view of the arm
+struct Session {
+    transport: Transport,
+    profile: Profile,
+    arm: Arm,
+    delivery: Delivery,
+    sequence: u16,
+}
+
+impl Session {
+    fn new(runtime: &Runtime, profile: Profile, bind: &str) -> Result<Self, HeadlessError> {
+        let mut transport = Transport::new(runtime.handle().clone(), None);
+        transport.bind(bind)?;
+        Ok(Session {
+            transport,
+            arm: Arm::from_chain(profile.chain.clone()),
+            delivery: Delivery::new(profile.retry),
+            profile,
+            sequence: 0,
+        })
+    }
+
+    async fn run(&mut self, command: Command) -> Result<(), HeadlessError> {
+        match command {
+            Command::Send { target, top, shoulder, upper, elbow, lower } => {
+                let to = self.target(target);
+                let mut angles = self.arm.home_servo_angles();
+                for (angle, given) in angles.iter_mut().zip([top, shoulder, upper, elbow, lower]) {
+                    if let Some(given) = given {
+                        *angle = given;
+                    }
+                }
+                self.command(&to, angles).await
+            }
+            Command::Home { target } => {
+                let to = self.target(target);
+                self.command(&to, self.arm.home_servo_angles()).await
+            }
+            Command::Play { path, target, speed } => {
+                let to = self.target(target);
+                if is_trajectory(&path) {
+                    self.play_trajectory(&to, &Trajectory::load(&path)?, speed).await
+                } else {
+                    self.play_sequence(&to, &Sequence::load(&path)?, speed).await
+                }
+            }
+            Command::Monitor { target, seconds } => {
+                let to = self.target(target);
+                self.monitor(&to, seconds).await
+            }
+            Command::Discover { .. } | Command::Urdf { .. } => unreachable!("Discovery and URDFs don't need a session"),
+        }
+    }
+
+    fn target(&self, target: Target) -> String {
+        target.to.unwrap_or_else(|| self.profile.send_to.clone())
+    }
+
+    // Clamps to the profile's servo limits, saying so rather than silently sending something else
+    fn limit(&self, angles: [f64; SERVO_COUNT]) -> [f64; SERVO_COUNT] {
+        std::array::from_fn(|servo| {
+            let limit = self.profile.servo_limits[servo];
+            let clamped = limit.clamp(angles[servo]);
+            if clamped != angles[servo] {
+                eprintln!("{} servo limited to {:.1}°, {:.1}° is outside {:.1}° to {:.1}°", arm::SERVO_NAMES[servo], clamped, angles[servo], limit.min, limit.max);
+            }
+            clamped
+        })
+    }
+
+    fn send(&mut self, to: &str, message: &Message) -> Result<(), HeadlessError> {
+        let packet = message.encode(self.sequence).map_err(TransportError::from)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.transport.send(to, packet)?;
+        Ok(())
+    }
+
+    // Starts a new command without waiting for its ack
+    fn start(&mut self, to: &str, angles: [f64; SERVO_COUNT]) -> Result<(), HeadlessError> {
+        let servos = self.profile.calibration.to_wire(&self.limit(angles));
+        let id = self.delivery.start(servos, Instant::now());
+        self.send(to, &Message::ServoCommand { id, servos })
+    }
+
+    // Sends a pose and retries it under the profile's retry policy until the arm acks it
+    async fn command(&mut self, to: &str, angles: [f64; SERVO_COUNT]) -> Result<(), HeadlessError> {
+        self.start(to, angles)?;
+        self.wait_for_ack(to).await
+    }
+
+    async fn wait_for_ack(&mut self, to: &str) -> Result<(), HeadlessError> {
+        loop {
+            let Some(command) = self.delivery.latest() else {
+                return Ok(());
+            };
+            match command.state {
+                CommandState::Acked { round_trip } => {
+                    println!("Command {} acked in {} ms", command.id, round_trip.as_millis());
+                    return Ok(());
+                }
+                CommandState::Pending => {}
+                _ => return Err(HeadlessError::NoAck { attempts: command.attempts }),
+            }
+            let wait = self.delivery.next_retry_in(Instant::now()).unwrap_or_default();
+            if let Ok(event) = tokio::time::timeout(wait, self.transport.recv()).await {
+                self.handle(event.ok_or(TransportError::Closed)?);
+                continue;
+            }
+            if let Some(command) = self.delivery.retry(Instant::now()) {
+                let (id, servos) = (command.id, command.servos);
+                self.send(to, &Message::ServoCommand { id, servos })?;
+            }
+        }
+    }
+
+    // Streams the sequence at a fixed rate, superseding each pose with the next, then waits for the last one to be acked
+    async fn play_sequence(&mut self, to: &str, sequence: &Sequence, speed: f64) -> Result<(), HeadlessError> {
+        let mut plan = sequence.plan(&self.arm, self.arm.home_servo_angles(), &self.profile.servo_limits, &self.profile.ik)?;
+        let duration = plan.duration();
+        self.play(to, &sequence.name, duration, speed, |time, current| Ok(plan.pose_at(time, current)?)).await
+    }
+
+    async fn play_trajectory(&mut self, to: &str, trajectory: &Trajectory, speed: f64) -> Result<(), HeadlessError> {
+        let angles_at = |time, _| trajectory.angles_at(time).ok_or(HeadlessError::Recording(RecordingError::Empty));
+        self.play(to, &trajectory.name, trajectory.duration(), speed, angles_at).await
+    }
+
+    // Sends the pose at each tick until the end, then waits for the last one to be acked.
+    // pose_at gets the time into the motion and the last pose sent.
+    async fn play(
+        &mut self,
+        to: &str,
+        name: &str,
+        duration: f64,
+        speed: f64,
+        mut pose_at: impl FnMut(f64, [f64; SERVO_COUNT]) -> Result<[f64; SERVO_COUNT], HeadlessError>,
+    ) -> Result<(), HeadlessError> {
+        // A speed of 0 would never get to the end
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(HeadlessError::InvalidSpeed(speed));
+        }
+        println!("Playing {}, {:.2} s", name, duration / speed);
+        let started = Instant::now();
+        let mut ticker = tokio::time::interval(PLAY_INTERVAL);
+        let mut angles = self.arm.home_servo_angles();
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let time = started.elapsed().as_secs_f64() * speed;
+                    angles = pose_at(time.min(duration), angles)?;
+                    self.start(to, angles)?;
+                    if time >= duration {
+                        break;
+                    }
+                }
+                event = self.transport.recv() => self.handle(event.ok_or(TransportError::Closed)?),
+            }
+        }
+        self.wait_for_ack(to).await
+    }
+
+    // Says Hello so the arm starts reporting to us, then prints whatever comes back
+    async fn monitor(&mut self, to: &str, seconds: Option<f64>) -> Result<(), HeadlessError> {
+        self.send(to, &Message::Hello { protocol_version: PROTOCOL_VERSION, servo_count: SERVO_COUNT as u8, name: "controller".to_owned() })?;
+        let deadline = seconds.map(|seconds| tokio::time::Instant::now() + Duration::from_secs_f64(seconds.max(0.0)));
+        loop {
+            let event = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, self.transport.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => return Ok(()),
+                },
+                None => self.transport.recv().await,
+            };
+            let event = event.ok_or(TransportError::Closed)?;
+            if let TransportEvent::Received { from, packet, .. } = &event {
+                let timestamp = chrono::Local::now().format("%H:%M:%S%.3f");
+                let line = match packet {
+                    Ok(packet) => match &packet.message {
+                        Message::ServoFeedback { servos } => {
+                            format!("{} {} #{} feedback {:.1?}", timestamp, from, packet.sequence, self.profile.calibration.to_angles(servos))
+                        }
+                        message => format!("{} {} #{} {:?}", timestamp, from, packet.sequence, message),
+                    },
+                    Err(e) => format!("{} {} bad packet: {}", timestamp, from, e),
+                };
+                // Whatever we're piped into has stopped reading, e.g. head, which is as good as being interrupted
+                if writeln!(std::io::stdout(), "{}", line).is_err() {
+                    return Ok(());
+                }
+            }
+            self.handle(event);
+        }
+    }
+
+    fn handle(&mut self, event: TransportEvent) {
+        match event {
+            TransportEvent::Received { at, packet: Ok(packet), .. } => match packet.message {
+                Message::Ack { message_type, id } if message_type == MessageType::ServoCommand as u8 => {
+                    self.delivery.acknowledge(id, at);
+                }
+                Message::Error { code, message } => eprintln!("Arm reported error {}: {}", code, message),
+                _ => {}
+            },
+            TransportEvent::Received { packet: Err(e), from, .. } => log::warn!("Bad packet from {}: {}", from, e),
+            TransportEvent::SendFailed { to, error } => eprintln!("Failed to send to {}: {}", to, error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Transport(TransportError),
+    Sequence(SequenceError),
+    Recording(RecordingError),
+    NoAck { attempts: u32 },
+    NothingFound,
+    InvalidSpeed(f64),
+    Write { path: PathBuf, error: std::io::Error },
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Transport(e) => write!(f, "{}", e),
+            HeadlessError::Sequence(e) => write!(f, "{}", e),
+            HeadlessError::Recording(e) => write!(f, "{}", e),
+            HeadlessError::NoAck { attempts } => write!(f, "The arm didn't ack the command after {} attempt(s)", attempts),
+            HeadlessError::NothingFound => write!(f, "No devices found"),
+            HeadlessError::InvalidSpeed(speed) => write!(f, "Speed must be a positive number, not {}", speed),
+            HeadlessError::Write { path, error } => write!(f, "Unable to write {}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Error for HeadlessError {}
+
+impl From<TransportError> for HeadlessError {
+    fn from(e: TransportError) -> Self {
+        HeadlessError::Transport(e)
+    }
+}
+
+impl From<SequenceError> for HeadlessError {
+    fn from(e: SequenceError) -> Self {
+        HeadlessError::Sequence(e)
+    }
+}
+
+impl From<RecordingError> for HeadlessError {
+    fn from(e: RecordingError) -> Self {
+        HeadlessError::Recording(e)
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// How often the arm is pinged
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+// A ping without a pong after this long counts as lost
+pub const PING_TIMEOUT: Duration = Duration::from_millis(1000);
+// With no pong for this long the link is declared dead
+pub const DEAD_AFTER: Duration = Duration::from_millis(2500);
+// Pings the statistics are worked out over
+pub const WINDOW: usize = 20;
+// How much round trip history is kept for the graph
+pub const HISTORY_SECONDS: f64 = 60.0;
+// Above this much loss, or this round trip, the link counts as degraded
+const DEGRADED_LOSS: f64 = 0.2;
+const DEGRADED_RTT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    // Not pinging, or nothing has been heard yet
+    Unknown,
+    Alive,
+    Degraded,
+    Dead,
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkStatus::Unknown => write!(f, "Unknown"),
+            LinkStatus::Alive => write!(f, "Alive"),
+            LinkStatus::Degraded => write!(f, "Degraded"),
+            LinkStatus::Dead => write!(f, "Dead"),
+        }
+    }
+}
+
+struct Ping {
+    id: u16,
+    sent: Instant,
+    round_trip: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthStats {
+    pub status: LinkStatus,
+    pub round_trip: Option<Duration>,
+    // Mean difference between consecutive round trips
+    pub jitter: Option<Duration>,
+    // Fraction of the pings in the window that were lost
+    pub loss: f64,
+}
+
+// Heartbeat for the link to one arm. Pings go out at a fixed rate and the pongs give the round trip.
+pub struct LinkHealth {
+    target: String,
+    start: Instant,
+    next_id: u16,
+    pings: VecDeque<Ping>,
+    // When pinging started, or restarted after a pause
+    pinging_since: Option<Instant>,
+    last_pong: Option<Instant>,
+    // (seconds since start, round trip in milliseconds) for the graph
+    pub history: VecDeque<[f64; 2]>,
+}
+
+impl LinkHealth {
+    pub fn new() -> Self {
+        LinkHealth {
+            target: String::new(),
+            start: Instant::now(),
+            next_id: 0,
+            pings: VecDeque::with_capacity(WINDOW),
+            pinging_since: None,
+            last_pong: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    // Starts over whenever the address being monitored changes
+    pub fn set_target(&mut self, target: &str) {
+        if self.target != target {
+            *self = LinkHealth::new();
+            self.target = target.to_owned();
+        }
+    }
+
+    // Returns the ID of a ping to send, if one is due
+    pub fn ping_due(&mut self, now: Instant) -> Option<u16> {
+        let last_sent = self.pings.back().map(|ping| ping.sent);
+        if last_sent.is_some_and(|sent| now < sent + PING_INTERVAL) {
+            return None;
+        }
+        // Coming back after a pause, so the silence while paused doesn't count against the link
+        if !self.is_pinging(now) {
+            self.pinging_since = Some(now);
+            self.pings.clear();
+            self.last_pong = None;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if self.pings.len() == WINDOW {
+            self.pings.pop_front();
+        }
+        self.pings.push_back(Ping { id, sent: now, round_trip: None });
+        Some(id)
+    }
+
+    pub fn next_ping_in(&self, now: Instant) -> Duration {
+        self.pings.back().map_or(Duration::ZERO, |ping| (ping.sent + PING_INTERVAL).saturating_duration_since(now))
+    }
+
+    pub fn pong(&mut self, id: u16, now: Instant) {
+        let Some(ping) = self.pings.iter_mut().find(|ping| ping.id == id && ping.round_trip.is_none()) else {
+            return;
+        };
+        let round_trip = now.saturating_duration_since(ping.sent);
+        ping.round_trip = Some(round_trip);
+        self.last_pong = Some(now);
+
+        let time = now.saturating_duration_since(self.start).as_secs_f64();
+        self.history.push_back([time, round_trip.as_secs_f64() * 1000.0]);
+        while self.history.front().is_some_and(|[oldest, _]| time - oldest > HISTORY_SECONDS) {
+            self.history.pop_front();
+        }
+    }
+
+    // Seconds since the health started, the x axis of the history
+    pub fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    pub fn stats(&self, now: Instant) -> HealthStats {
+        let round_trips: Vec<Duration> = self.pings.iter().filter_map(|ping| ping.round_trip).collect();
+        // Pings still inside their timeout might yet be answered, so they don't count either way
+        let lost = self.pings.iter().filter(|ping| ping.round_trip.is_none() && now >= ping.sent + PING_TIMEOUT).count();
+        let decided = round_trips.len() + lost;
+        let loss = if decided > 0 { lost as f64 / decided as f64 } else { 0.0 };
+        let round_trip = (!round_trips.is_empty()).then(|| round_trips.iter().sum::<Duration>() / round_trips.len() as u32);
+        let jitter = (round_trips.len() > 1).then(|| {
+            let total: Duration = round_trips.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+            total / (round_trips.len() - 1) as u32
+        });
+
+        let status = match self.pinging_since {
+            Some(since) if self.is_pinging(now) => {
+                let silent_since = self.last_pong.map_or(since, |pong| pong.max(since));
+                if now.saturating_duration_since(silent_since) > DEAD_AFTER {
+                    LinkStatus::Dead
+                } else if self.last_pong.is_none() {
+                    LinkStatus::Unknown
+                } else if loss > DEGRADED_LOSS || round_trip.is_some_and(|round_trip| round_trip > DEGRADED_RTT) {
+                    LinkStatus::Degraded
+                } else {
+                    LinkStatus::Alive
+                }
+            }
+            _ => LinkStatus::Unknown,
+        };
+        HealthStats { status, round_trip, jitter, loss }
+    }
+
+    pub fn is_dead(&self, now: Instant) -> bool {
+        self.stats(now).status == LinkStatus::Dead
+    }
+
+    // Pinging counts as paused if no ping has gone out for a couple of intervals, e.g. in Receiving mode
+    fn is_pinging(&self, now: Instant) -> bool {
+        self.pings.back().is_some_and(|ping| now < ping.sent + 2 * PING_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Pings on schedule from start, answering each after the given round trip, or not at all for None.
+    // Returns when the last ping went out.
+    fn run(health: &mut LinkHealth, start: Instant, round_trips: &[Option<u64>]) -> Instant {
+        let mut now = start;
+        for (index, round_trip) in round_trips.iter().enumerate() {
+            now = start + PING_INTERVAL * index as u32;
+            let id = health.ping_due(now).expect("Ping should be due");
+            if let Some(round_trip) = round_trip {
+                health.pong(id, now + ms(*round_trip));
+            }
+        }
+        now
+    }
+
+    #[test]
+    fn pings_go_out_at_the_interval() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        assert_eq!(health.next_ping_in(start), Duration::ZERO);
+        let first = health.ping_due(start).unwrap();
+        assert_eq!(health.ping_due(start + PING_INTERVAL - ms(1)), None);
+        assert_eq!(health.next_ping_in(start + ms(100)), PING_INTERVAL - ms(100));
+        assert_eq!(health.ping_due(start + PING_INTERVAL), Some(first.wrapping_add(1)));
+    }
+
+    #[test]
+    fn round_trip_and_jitter_come_from_the_pongs() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        let last = run(&mut health, start, &[Some(10), Some(30), Some(20)]);
+        let stats = health.stats(last + ms(40));
+        assert_eq!(stats.round_trip, Some(ms(20)));
+        // |10 - 30| and |30 - 20|
+        assert_eq!(stats.jitter, Some(ms(15)));
+        assert_eq!(stats.loss, 0.0);
+        assert_eq!(stats.status, LinkStatus::Alive);
+        assert_eq!(health.history.len(), 3);
+        // A pong for a ping we never sent, or a second pong for the same ping, changes nothing
+        health.pong(1000, last + ms(50));
+        health.pong(0, last + ms(50));
+        assert_eq!(health.stats(last + ms(50)).round_trip, Some(ms(20)));
+    }
+
+    #[test]
+    fn pings_only_count_as_lost_after_the_timeout() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        let last = run(&mut health, start, &[Some(10), None, Some(10), None]);
+        // The second ping is past its timeout, the fourth isn't yet
+        let stats = health.stats(last + ms(100));
+        assert!((stats.loss - 1.0 / 3.0).abs() < 1e-9, "{:?}", stats);
+        assert_eq!(stats.status, LinkStatus::Degraded);
+        let stats = health.stats(last + PING_TIMEOUT);
+        assert_eq!(stats.loss, 0.5);
+    }
+
+    #[test]
+    fn slow_round_trips_are_degraded() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        let last = run(&mut health, start, &[Some(250), Some(250)]);
+        assert_eq!(health.stats(last + ms(300)).status, LinkStatus::Degraded);
+    }
+
+    #[test]
+    fn silence_while_pinging_is_dead() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        assert_eq!(health.stats(start).status, LinkStatus::Unknown);
+        let mut round_trips = vec![Some(10)];
+        round_trips.extend([None; 6]);
+        let last = run(&mut health, start, &round_trips);
+        let pong = start + ms(10);
+        assert_eq!(last, start + PING_INTERVAL * 6);
+        assert!(!health.is_dead(pong + DEAD_AFTER));
+        assert!(health.is_dead(pong + DEAD_AFTER + ms(1)));
+
+        // A link that never answered is dead once it has been pinged for long enough
+        let mut silent = LinkHealth::new();
+        let last = run(&mut silent, start, &[None; 6]);
+        assert_eq!(silent.stats(start + DEAD_AFTER).status, LinkStatus::Unknown);
+        assert!(silent.is_dead(last + ms(1)));
+    }
+
+    #[test]
+    fn pausing_pings_forgets_the_silence() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        let last = run(&mut health, start, &[Some(10), None, None]);
+        // With no pings for a while the link is unknown rather than dead
+        let resumed = last + PING_INTERVAL * 10;
+        assert_eq!(health.stats(resumed).status, LinkStatus::Unknown);
+        health.ping_due(resumed).unwrap();
+        let stats = health.stats(resumed + ms(1));
+        assert_eq!((stats.status, stats.loss), (LinkStatus::Unknown, 0.0));
+        assert!(!health.is_dead(resumed + DEAD_AFTER));
+    }
+
+    #[test]
+    fn changing_target_starts_over() {
+        let start = Instant::now();
+        let mut health = LinkHealth::new();
+        health.set_target("10.0.0.5:1234");
+        let last = run(&mut health, start, &[Some(10), Some(10)]);
+        health.set_target("10.0.0.5:1234");
+        assert_eq!(health.stats(last + ms(20)).status, LinkStatus::Alive);
+        health.set_target("10.0.0.6:1234");
+        assert_eq!(health.stats(last + ms(20)).status, LinkStatus::Unknown);
+        assert!(health.history.is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::arm::{HOME_SERVO_ANGLES, SERVO_COUNT};
+
+// Describes an arm as a chain of revolute joints, each driven by one of the servos.
+// Frames are chained from the shoulder at the origin, and the tool is offset from the last joint's frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainDescription {
+    pub name: String,
+    pub joints: Vec<JointDescription>,
+    // Tool tip offset in the last joint's frame, in metres
+    #[serde(default)]
+    pub tool: [f64; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointDescription {
+    pub name: String,
+    // Index of the servo driving this joint, in wire order
+    pub servo: usize,
+    pub frame: JointFrame,
+    #[serde(default)]
+    pub mapping: ServoMapping,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JointFrame {
+    // Move by origin in the previous frame, then rotate by the joint angle about axis
+    Axis { axis: [f64; 3], origin: [f64; 3] },
+    // Standard Denavit-Hartenberg parameters, lengths in metres and angles in degrees.
+    // The joint angle is added to theta.
+    Dh { a: f64, alpha: f64, d: f64, theta: f64 },
+}
+
+// How a servo angle turns into a joint angle:
+// joint = direction * gear_ratio * (servo - offset)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServoMapping {
+    // Servo angle, in degrees, at which the joint is at 0
+    pub offset: f64,
+    // 1 if the joint turns the same way as the servo, -1 if it is reversed
+    pub direction: f64,
+    // Joint degrees per servo degree
+    pub gear_ratio: f64,
+}
+
+impl Default for ServoMapping {
+    fn default() -> Self {
+        ServoMapping {
+            offset: 0.0,
+            direction: 1.0,
+            gear_ratio: 1.0,
+        }
+    }
+}
+
+impl ServoMapping {
+    pub fn to_joint(self, servo: f64) -> f64 {
+        self.direction * self.gear_ratio * (servo - self.offset)
+    }
+
+    pub fn to_servo(self, joint: f64) -> f64 {
+        self.offset + joint / (self.direction * self.gear_ratio)
+    }
+}
+
+// Pose of every joint frame along the chain, relative to the origin
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainPoses {
+    // One pose per joint, in the same order as ChainDescription::joints
+    pub joints: Vec<na::Isometry3<f64>>,
+    pub tool: na::Isometry3<f64>,
+}
+
+impl JointFrame {
+    // Transform from the previous joint's frame to this one, for a joint angle in degrees
+    fn transform(&self, angle: f64) -> na::Isometry3<f64> {
+        let (before, axis, after) = self.split();
+        before * na::UnitQuaternion::from_axis_angle(&axis, angle.to_radians()) * after
+    }
+
+    // The fixed transform before the joint, the axis it turns about, and the fixed transform after it
+    pub fn split(&self) -> (na::Isometry3<f64>, na::Unit<na::Vector3<f64>>, na::Isometry3<f64>) {
+        match self {
+            JointFrame::Axis { axis, origin } => (
+                na::Isometry3::translation(origin[0], origin[1], origin[2]),
+                na::Unit::new_normalize(na::Vector3::from(*axis)),
+                na::Isometry3::identity(),
+            ),
+            JointFrame::Dh { a, alpha, d, theta } => {
+                // Rot_z(theta + angle) * Trans_z(d) * Trans_x(a) * Rot_x(alpha), and Rot_z(angle) can go first as it commutes with Rot_z(theta)
+                let about_z = na::Isometry3::from_parts(
+                    na::Translation3::new(0.0, 0.0, *d),
+                    na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), theta.to_radians()),
+                );
+                let about_x = na::Isometry3::from_parts(
+                    na::Translation3::new(*a, 0.0, 0.0),
+                    na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), alpha.to_radians()),
+                );
+                (na::Isometry3::identity(), na::Vector3::z_axis(), about_z * about_x)
+            }
+        }
+    }
+}
+
+impl ChainDescription {
+    // The five servo arm: the top servo rotates the shoulder mount about i, the shoulder tilts about j,
+    // the upper arm rotates about its own length, the elbow bends about j, and the lower arm rotates about its own length.
+    // The upper and lower rotations turn clockwise, so they are reversed.
+    pub fn five_servo(upper_length: f64, lower_length: f64, tool_length: f64) -> Self {
+        let joint = |name: &str, servo: usize, axis: [f64; 3], origin: [f64; 3], direction: f64| JointDescription {
+            name: name.to_owned(),
+            servo,
+            frame: JointFrame::Axis { axis, origin },
+            mapping: ServoMapping {
+                offset: HOME_SERVO_ANGLES[servo],
+                direction,
+                gear_ratio: 1.0,
+            },
+        };
+
+        ChainDescription {
+            name: "Five Servo Arm".to_owned(),
+            joints: vec![
+                joint("Top", 0, [1.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1.0),
+                joint("Shoulder", 1, [0.0, 1.0, 0.0], [0.0, 0.0, 0.0], 1.0),
+                joint("Upper", 2, [0.0, 0.0, 1.0], [0.0, 0.0, 0.0], -1.0),
+                joint("Elbow", 3, [0.0, 1.0, 0.0], [0.0, 0.0, upper_length], 1.0),
+                joint("Lower", 4, [0.0, 0.0, 1.0], [0.0, 0.0, lower_length], -1.0),
+            ],
+            tool: [0.0, 0.0, tool_length],
+        }
+    }
+
+    // Loads a description from a .toml or .json file
+    pub fn load(path: &Path) -> Result<Self, ChainError> {
+        let contents = fs::read_to_string(path)?;
+        let chain: ChainDescription = match Self::format(path)? {
+            ChainFormat::Toml => toml::from_str(&contents)?,
+            ChainFormat::Json => serde_json::from_str(&contents)?,
+        };
+        chain.validate()?;
+        Ok(chain)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ChainError> {
+        let contents = match Self::format(path)? {
+            ChainFormat::Toml => toml::to_string_pretty(self)?,
+            ChainFormat::Json => serde_json::to_string_pretty(self)?,
+        };
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn format(path: &Path) -> Result<ChainFormat, ChainError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(ChainFormat::Toml),
+            Some("json") => Ok(ChainFormat::Json),
+            _ => Err(ChainError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ChainError> {
+        if self.joints.is_empty() {
+            return Err(ChainError::Invalid("the chain has no joints".to_owned()));
+        }
+        let mut used = [false; SERVO_COUNT];
+        for joint in &self.joints {
+            if joint.servo >= SERVO_COUNT {
+                return Err(ChainError::Invalid(format!("joint {} uses servo {}, but there are only {} servos", joint.name, joint.servo, SERVO_COUNT)));
+            }
+            if used[joint.servo] {
+                return Err(ChainError::Invalid(format!("servo {} drives more than one joint", joint.servo)));
+            }
+            used[joint.servo] = true;
+            if joint.mapping.direction.abs() != 1.0 {
+                return Err(ChainError::Invalid(format!("joint {} direction must be 1 or -1", joint.name)));
+            }
+            if joint.mapping.gear_ratio == 0.0 || !joint.mapping.gear_ratio.is_finite() {
+                return Err(ChainError::Invalid(format!("joint {} has an invalid gear ratio", joint.name)));
+            }
+            if let JointFrame::Axis { axis, .. } = joint.frame {
+                if na::Vector3::from(axis).norm() == 0.0 {
+                    return Err(ChainError::Invalid(format!("joint {} has a zero length axis", joint.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Forward kinematics for servo angles in degrees, in wire order
+    pub fn forward(&self, servo_angles: &[f64; SERVO_COUNT]) -> ChainPoses {
+        let mut pose = na::Isometry3::identity();
+        let mut joints = Vec::with_capacity(self.joints.len());
+        for joint in &self.joints {
+            let angle = joint.mapping.to_joint(servo_angles[joint.servo]);
+            pose *= joint.frame.transform(angle);
+            joints.push(pose);
+        }
+        let tool = pose * na::Translation3::new(self.tool[0], self.tool[1], self.tool[2]);
+        ChainPoses { joints, tool }
+    }
+
+    // Servo angles that put every joint at 0. Servos not in the chain stay in the middle of their travel.
+    pub fn home_servo_angles(&self) -> [f64; SERVO_COUNT] {
+        let mut angles = [90.0; SERVO_COUNT];
+        for joint in &self.joints {
+            angles[joint.servo] = joint.mapping.offset;
+        }
+        angles
+    }
+
+    // The upper, lower and tool lengths if this chain has the five servo layout, which the closed form IK relies on
+    pub fn five_servo_lengths(&self) -> Option<(f64, f64, f64)> {
+        let template = ChainDescription::five_servo(0.0, 0.0, 0.0);
+        if self.joints.len() != template.joints.len() || self.tool[0] != 0.0 || self.tool[1] != 0.0 {
+            return None;
+        }
+        for (joint, expected) in self.joints.iter().zip(template.joints.iter()) {
+            let (JointFrame::Axis { axis, origin }, JointFrame::Axis { axis: expected_axis, .. }) = (&joint.frame, &expected.frame) else {
+                return None;
+            };
+            let axis = na::Vector3::from(*axis).normalize();
+            if joint.servo != expected.servo || (axis - na::Vector3::from(*expected_axis)).norm() > 1e-9 || origin[0] != 0.0 || origin[1] != 0.0 {
+                return None;
+            }
+        }
+        // Only the elbow and lower rotation are offset along their axis, by the upper and lower lengths
+        let origin_z = |index: usize| match self.joints[index].frame {
+            JointFrame::Axis { origin, .. } => origin[2],
+            JointFrame::Dh { .. } => 0.0,
+        };
+        if origin_z(0) != 0.0 || origin_z(1) != 0.0 || origin_z(2) != 0.0 {
+            return None;
+        }
+        Some((origin_z(3), origin_z(4), self.tool[2]))
+    }
+
+    // Mutable upper, lower and tool lengths, for chains with the five servo layout
+    pub fn five_servo_lengths_mut(&mut self) -> Option<(&mut f64, &mut f64, &mut f64)> {
+        self.five_servo_lengths()?;
+        match self.joints.as_mut_slice() {
+            [_, _, _, elbow, lower] => match (&mut elbow.frame, &mut lower.frame) {
+                (JointFrame::Axis { origin: elbow_origin, .. }, JointFrame::Axis { origin: lower_origin, .. }) => {
+                    Some((&mut elbow_origin[2], &mut lower_origin[2], &mut self.tool[2]))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The joint driven by a servo, if it drives one
+    pub fn joint(&self, servo: usize) -> Option<&JointDescription> {
+        self.joints.iter().find(|joint| joint.servo == servo)
+    }
+}
+
+enum ChainFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum ChainError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    Json(serde_json::Error),
+    UnknownFormat(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::Io(e) => write!(f, "Unable to access chain file: {}", e),
+            ChainError::Toml(e) => write!(f, "Unable to parse chain TOML: {}", e),
+            ChainError::TomlWrite(e) => write!(f, "Unable to write chain TOML: {}", e),
+            ChainError::Json(e) => write!(f, "Unable to parse chain JSON: {}", e),
+            ChainError::UnknownFormat(path) => write!(f, "{} is not a .toml or .json file", path),
+            ChainError::Invalid(reason) => write!(f, "Invalid chain: {}", reason),
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+impl From<std::io::Error> for ChainError {
+    fn from(e: std::io::Error) -> Self {
+        ChainError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ChainError {
+    fn from(e: toml::de::Error) -> Self {
+        ChainError::Toml(e)
+    }
+}
+
+impl From<toml::ser::Error> for ChainError {
+    fn from(e: toml::ser::Error) -> Self {
+        ChainError::TomlWrite(e)
+    }
+}
+
+impl From<serde_json::Error> for ChainError {
+    fn from(e: serde_json::Error) -> Self {
+        ChainError::Json(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn assert_position(pose: &na::Isometry3<f64>, expected: [f64; 3]) {
+        let position = pose.translation.vector;
+        assert!((position - na::Vector3::from(expected)).norm() < TOLERANCE, "{:?} isn't {:?}", position, expected);
+    }
+
+    // Joint angles from home, turned into servo angles for the five servo arm
+    fn from_home(joints: [f64; SERVO_COUNT]) -> [f64; SERVO_COUNT] {
+        let chain = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        std::array::from_fn(|servo| chain.joint(servo).unwrap().mapping.to_servo(joints[servo]))
+    }
+
+    fn dh(name: &str, servo: usize, a: f64, alpha: f64, d: f64) -> JointDescription {
+        JointDescription {
+            name: name.to_owned(),
+            servo,
+            frame: JointFrame::Dh { a, alpha, d, theta: 0.0 },
+            mapping: ServoMapping::default(),
+        }
+    }
+
+    fn invalid(chain: &ChainDescription) -> String {
+        match chain.validate() {
+            Err(ChainError::Invalid(reason)) => reason,
+            other => panic!("{} should be invalid, got {:?}", chain.name, other),
+        }
+    }
+
+    #[test]
+    fn five_servo_home_points_straight_up() {
+        let chain = ChainDescription::five_servo(1.0, 0.8, 0.1);
+        assert_eq!(chain.home_servo_angles(), HOME_SERVO_ANGLES);
+        let poses = chain.forward(&HOME_SERVO_ANGLES);
+        assert_position(&poses.joints[3], [0.0, 0.0, 1.0]);
+        assert_position(&poses.joints[4], [0.0, 0.0, 1.8]);
+        assert_position(&poses.tool, [0.0, 0.0, 1.9]);
+        assert!(poses.tool.rotation.angle() < TOLERANCE);
+    }
+
+    #[test]
+    fn five_servo_joints_turn_the_way_the_servos_do() {
+        let chain = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        // The shoulder tilts the arm towards i
+        let tilted = chain.forward(&from_home([0.0, 30.0, 0.0, 0.0, 0.0]));
+        assert_position(&tilted.tool, [1.8 * 0.5, 0.0, 1.8 * 3f64.sqrt() / 2.0]);
+        // The top servo swings the shoulder mount about i, towards -j
+        let swung = chain.forward(&from_home([90.0, 30.0, 0.0, 0.0, 0.0]));
+        assert_position(&swung.tool, [0.9, -1.8 * 3f64.sqrt() / 2.0, 0.0]);
+        // Bending the elbow only moves the lower arm, and the upper rotation turns the bend clockwise
+        let bent = chain.forward(&from_home([0.0, 0.0, 0.0, 90.0, 0.0]));
+        assert_position(&bent.joints[3], [0.0, 0.0, 1.0]);
+        assert_position(&bent.tool, [0.8, 0.0, 1.0]);
+        let mut turned = from_home([0.0, 0.0, 0.0, 90.0, 0.0]);
+        turned[2] += 90.0;
+        assert_position(&chain.forward(&turned).tool, [0.0, -0.8, 1.0]);
+        // The lower rotation turns the tool about its own length without moving it
+        let rolled = chain.forward(&from_home([0.0, 0.0, 0.0, 90.0, 45.0]));
+        assert_position(&rolled.tool, [0.8, 0.0, 1.0]);
+        assert!((rolled.tool.rotation.angle() - bent.tool.rotation.angle()).abs() > 0.1);
+    }
+
+    #[test]
+    fn servo_mapping_round_trips() {
+        let mapping = ServoMapping { offset: 90.0, direction: -1.0, gear_ratio: 2.0 };
+        assert_eq!(mapping.to_joint(100.0), -20.0);
+        assert_eq!(mapping.to_servo(-20.0), 100.0);
+        assert_eq!(ServoMapping::default().to_joint(42.0), 42.0);
+    }
+
+    #[test]
+    fn dh_joints_follow_the_dh_convention() {
+        let chain = ChainDescription {
+            name: "Planar".to_owned(),
+            joints: vec![dh("First", 0, 1.0, 0.0, 0.0), dh("Second", 1, 0.5, 0.0, 0.0)],
+            tool: [0.0; 3],
+        };
+        chain.validate().unwrap();
+        assert_eq!(chain.five_servo_lengths(), None);
+        assert_position(&chain.forward(&[0.0; SERVO_COUNT]).tool, [1.5, 0.0, 0.0]);
+        assert_position(&chain.forward(&[90.0, 0.0, 0.0, 0.0, 0.0]).tool, [0.0, 1.5, 0.0]);
+        assert_position(&chain.forward(&[90.0, -90.0, 0.0, 0.0, 0.0]).tool, [0.5, 1.0, 0.0]);
+
+        // d moves along z before the joint's x, and alpha twists the next z about x
+        let twisted = ChainDescription {
+            name: "Twisted".to_owned(),
+            joints: vec![dh("Only", 0, 0.0, 90.0, 0.3)],
+            tool: [0.0, 0.0, 0.2],
+        };
+        assert_position(&twisted.forward(&[0.0; SERVO_COUNT]).tool, [0.0, -0.2, 0.3]);
+    }
+
+    #[test]
+    fn five_servo_lengths_only_come_from_the_five_servo_layout() {
+        let mut chain = ChainDescription::five_servo(1.0, 0.8, 0.1);
+        assert_eq!(chain.five_servo_lengths(), Some((1.0, 0.8, 0.1)));
+        let (upper, _, tool) = chain.five_servo_lengths_mut().unwrap();
+        (*upper, *tool) = (1.2, 0.0);
+        assert_eq!(chain.five_servo_lengths(), Some((1.2, 0.8, 0.0)));
+
+        chain.joints[1].frame = JointFrame::Axis { axis: [1.0, 0.0, 0.0], origin: [0.0; 3] };
+        assert_eq!(chain.five_servo_lengths(), None);
+        assert!(chain.five_servo_lengths_mut().is_none());
+    }
+
+    #[test]
+    fn validate_rejects_bad_chains() {
+        ChainDescription::five_servo(1.0, 0.8, 0.0).validate().unwrap();
+
+        let mut empty = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        empty.joints.clear();
+        assert!(invalid(&empty).contains("no joints"));
+
+        let mut missing_servo = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        missing_servo.joints[4].servo = SERVO_COUNT;
+        assert!(invalid(&missing_servo).contains("only 5 servos"));
+
+        let mut shared_servo = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        shared_servo.joints[4].servo = 3;
+        assert!(invalid(&shared_servo).contains("more than one joint"));
+
+        let mut direction = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        direction.joints[0].mapping.direction = 0.5;
+        assert!(invalid(&direction).contains("direction"));
+
+        for gear_ratio in [0.0, f64::NAN, f64::INFINITY] {
+            let mut geared = ChainDescription::five_servo(1.0, 0.8, 0.0);
+            geared.joints[2].mapping.gear_ratio = gear_ratio;
+            assert!(invalid(&geared).contains("gear ratio"));
+        }
+
+        let mut no_axis = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        no_axis.joints[1].frame = JointFrame::Axis { axis: [0.0; 3], origin: [0.0; 3] };
+        assert!(invalid(&no_axis).contains("zero length axis"));
+    }
+
+    #[test]
+    fn bundled_five_servo_chain_matches_the_template() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("arms").join("five_servo.toml");
+        assert_eq!(ChainDescription::load(&path).unwrap(), ChainDescription::five_servo(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn saved_chains_load_back() {
+        let chain = ChainDescription {
+            name: "Mixed".to_owned(),
+            joints: vec![dh("First", 2, 0.4, 90.0, 0.1), ChainDescription::five_servo(1.0, 0.8, 0.0).joints[3].clone()],
+            tool: [0.0, 0.0, 0.05],
+        };
+        for extension in ["toml", "json"] {
+            let path = std::env::temp_dir().join(format!("controller-chain-{}.{}", std::process::id(), extension));
+            chain.save(&path).unwrap();
+            let loaded = ChainDescription::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), chain);
+        }
+        let unknown = chain.save(Path::new("chain.yaml"));
+        assert!(matches!(unknown, Err(ChainError::UnknownFormat(_))), "{:?}", unknown);
+    }
+}
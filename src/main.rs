@@ -3,20 +3,67 @@ mod gui;
 mod network;
 mod models;
 mod arm;
+mod kinematics;
+mod calibration;
+mod config;
+mod protocol;
+mod delivery;
+mod telemetry;
+mod transport;
+mod registry;
+mod capabilities;
+mod health;
+mod estop;
+mod recording;
+mod sequencer;
+mod headless;
+mod api;
+mod stream;
+mod mqtt;
+mod ros;
 mod plot;
 
-use controller::Controller;
 use gui::Gui;
 use network::Network;
 use models::SharedState; // Use this if you have a separate models.rs
+use config::Config;
+use transport::Transport;
 use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
+use clap::{CommandFactory, Parser};
+
+#[derive(Parser, Debug)]
+#[command(about = "Controller for an ESP-32 powered robotic arm")]
+struct Args {
+    /// Local address to listen on, e.g. 0.0.0.0:8081 or [::]:8081. Overrides the active profile for this run.
+    #[arg(long)]
+    bind: Option<String>,
+    /// Serve the HTTP API, on 127.0.0.1:8090 unless another address is given
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = api::DEFAULT_API_ADDRESS)]
+    api: Option<String>,
+    /// Run a command without the GUI, e.g. `--headless send --top 90 --shoulder 40`
+    #[arg(long)]
+    headless: bool,
+    #[command(subcommand)]
+    command: Option<headless::Command>,
+}
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init();
+    let args = Args::parse();
+
+    // Load the saved settings, the active profile decides what we start up with
+    let config = Config::load_or_default();
+
+    match (args.headless, args.command) {
+        (true, Some(command)) => std::process::exit(headless::run(command, config.active(), args.bind)),
+        (true, None) => Args::command().error(clap::error::ErrorKind::MissingSubcommand, "--headless needs a command to run").exit(),
+        (false, Some(_)) => Args::command().error(clap::error::ErrorKind::ArgumentConflict, "Commands only run with --headless").exit(),
+        (false, None) => {}
+    }
 
     // Shared state initialization
-    let shared_state = Arc::new(Mutex::new(SharedState::new("_controller._udp.local".to_owned())));
+    let shared_state = Arc::new(Mutex::new(SharedState::new(config.active().services)));
 
     // Create a Tokio runtime
     let rt = Runtime::new().expect("Failed to create Tokio runtime");
@@ -29,6 +76,12 @@ fn main() -> Result<(), eframe::Error> {
         Network::discover_devices(state_for_async).await;
     });
 
+    // The transport task runs on the same runtime, and is created once the UI exists so it can wake it up
+    let runtime_handle = rt.handle().clone();
+    let bind_override = args.bind;
+    let api_address = args.api;
+    let profile = config.active();
+
     // Set eframe options if required
     let options = eframe::NativeOptions::default();
 
@@ -39,7 +92,25 @@ fn main() -> Result<(), eframe::Error> {
         Box::new(move |cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
 
-            Box::new(Gui::new(shared_state.clone()))
+            let transport = Transport::new(runtime_handle.clone(), Some(cc.egui_ctx.clone()));
+
+            // The HTTP API, the MQTT bridge and the ROS publisher all hand requests to the controller, which the UI context wakes up
+            let mut api = None;
+            if api_address.is_some() || profile.mqtt.enabled || profile.ros.enabled {
+                let (client, link) = api::channel(cc.egui_ctx.clone());
+                if let Some(address) = api_address {
+                    runtime_handle.spawn(api::serve(address, client.clone()));
+                }
+                if profile.mqtt.enabled {
+                    runtime_handle.spawn(mqtt::run(profile.mqtt, client.clone()));
+                }
+                if profile.ros.enabled {
+                    runtime_handle.spawn(ros::run(profile.ros, profile.chain, client));
+                }
+                api = Some(link);
+            }
+
+            Box::new(Gui::new(shared_state.clone(), config, transport, bind_override, api))
         }),
     )
 }
\ No newline at end of file
@@ -1,29 +1,58 @@
-use std::net::{SocketAddr};
+use tokio::sync::watch;
+use crate::registry::DeviceRegistry;
 
 pub struct SharedState {
     // Fields go here
-    pub discovered_ips: Vec<SocketAddr>,
-    pub service: String,
+    pub devices: DeviceRegistry,
+    // mDNS service types being browsed, discovery watches this and restarts when it changes
+    services: watch::Sender<Vec<String>>,
 }
 
 impl SharedState {
     // Define a new method to create an instance of SharedState
-    pub fn new(service: String) -> Self {
+    pub fn new(services: Vec<String>) -> Self {
         SharedState {
             // Initialize the fields
-            discovered_ips: Vec::new(),
-            service,
+            devices: DeviceRegistry::new(),
+            services: watch::Sender::new(normalise_services(services)),
         }
     }
-    pub fn set_service(&mut self, service: String) {
-        self.service = service;
+
+    pub fn services(&self) -> Vec<String> {
+        self.services.borrow().clone()
+    }
+
+    // Switches discovery over to a new set of service types, forgetting everything found under the old ones
+    pub fn set_services(&mut self, services: Vec<String>) {
+        let services = normalise_services(services);
+        if *self.services.borrow() != services {
+            self.devices = DeviceRegistry::new();
+            self.services.send_replace(services);
+        }
+    }
+
+    pub fn subscribe_services(&self) -> watch::Receiver<Vec<String>> {
+        self.services.subscribe()
+    }
+}
+
+// mDNS names are often written with a trailing dot, which the browser doesn't want
+fn normalise_services(services: Vec<String>) -> Vec<String> {
+    let mut normalised: Vec<String> = Vec::new();
+    for service in services {
+        let service = service.trim().trim_end_matches('.').to_owned();
+        if !service.is_empty() && !normalised.contains(&service) {
+            normalised.push(service);
+        }
     }
+    normalised
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Mode {
     Sending,
     Receiving,
+    Sequencer,
     Stopped,
     Settings,
 }
\ No newline at end of file
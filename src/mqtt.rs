@@ -0,0 +1,283 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::MissedTickBehavior;
+
+use crate::api::{ApiClient, ApiCommand, MoveTarget, ServoAngles};
+
+// How long to wait before reconnecting after the broker goes away
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+// Requests rumqttc queues up for the broker before publishing starts failing
+const REQUEST_CAPACITY: usize = 64;
+
+// Where the MQTT bridge connects and what it publishes, off unless enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub publish_interval_ms: u64,
+    pub topics: MqttTopics,
+    // Commands accepted on the command topic, anything else is refused
+    pub allowed_commands: Vec<CommandKind>,
+    // Servo and target commands accepted per second, with up to a second's worth in a burst, and 0 refuses them all.
+    // Stops are never limited.
+    pub max_commands_per_second: f64,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        MqttSettings {
+            enabled: false,
+            host: "localhost".to_owned(),
+            port: 1883,
+            client_id: "arm-controller".to_owned(),
+            publish_interval_ms: 200,
+            topics: MqttTopics::default(),
+            allowed_commands: vec![CommandKind::Servos, CommandKind::Target, CommandKind::EmergencyStop],
+            max_commands_per_second: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttTopics {
+    // Servo angles in degrees by name, and the state of the latest command
+    pub servos: String,
+    // End effector i/j/k
+    pub position: String,
+    pub health: String,
+    pub adc: String,
+    pub command: String,
+    // Where the result of each command is published
+    pub reply: String,
+}
+
+impl Default for MqttTopics {
+    fn default() -> Self {
+        MqttTopics {
+            servos: "arm/servos".to_owned(),
+            position: "arm/position".to_owned(),
+            health: "arm/health".to_owned(),
+            adc: "arm/adc".to_owned(),
+            command: "arm/command".to_owned(),
+            reply: "arm/command/reply".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    Servos,
+    Target,
+    EmergencyStop,
+}
+
+impl fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandKind::Servos => write!(f, "servos"),
+            CommandKind::Target => write!(f, "target"),
+            CommandKind::EmergencyStop => write!(f, "emergency_stop"),
+        }
+    }
+}
+
+// A command as published on the command topic, e.g. {"type": "servos", "top": 90}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum MqttCommand {
+    Servos(ServoAngles),
+    Target(MoveTarget),
+    EmergencyStop,
+}
+
+impl MqttCommand {
+    fn kind(&self) -> CommandKind {
+        match self {
+            MqttCommand::Servos(_) => CommandKind::Servos,
+            MqttCommand::Target(_) => CommandKind::Target,
+            MqttCommand::EmergencyStop => CommandKind::EmergencyStop,
+        }
+    }
+
+    fn into_api(self) -> ApiCommand {
+        match self {
+            MqttCommand::Servos(angles) => ApiCommand::SetServos(angles),
+            MqttCommand::Target(target) => ApiCommand::MoveTo(target),
+            MqttCommand::EmergencyStop => ApiCommand::EmergencyStop,
+        }
+    }
+}
+
+// Token bucket, refilled at a steady rate up to a second's worth
+struct RateLimit {
+    per_second: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    fn new(per_second: f64, now: Instant) -> Self {
+        let per_second = per_second.max(0.0);
+        let mut limit = RateLimit { per_second, tokens: 0.0, last: now };
+        limit.tokens = limit.burst();
+        limit
+    }
+
+    // A second's worth, but at least one command so rates below one a second still get through. A rate of 0 allows nothing.
+    fn burst(&self) -> f64 {
+        if self.per_second > 0.0 {
+            self.per_second.max(1.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst());
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Runs the bridge until the process exits, reconnecting whenever the broker goes away.
+// Commands go through the controller like the HTTP API's do, so they get the same limits and checks.
+pub async fn run(settings: MqttSettings, api: ApiClient) {
+    let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    let (client, mut event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+    let (commands, received) = mpsc::unbounded_channel();
+    tokio::spawn(publish_state(settings.clone(), client.clone(), api.clone()));
+    tokio::spawn(handle_commands(settings.clone(), client.clone(), api, received));
+
+    loop {
+        match event_loop.poll().await {
+            // The broker forgets subscriptions along with the session, so subscribe on every connect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("MQTT connected to {}:{}", settings.host, settings.port);
+                if let Err(e) = client.try_subscribe(&settings.topics.command, QoS::AtLeastOnce) {
+                    log::error!("Unable to subscribe to {}: {}", settings.topics.command, e);
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == settings.topics.command => {
+                let _ = commands.send(publish.payload.to_vec());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::warn!("MQTT connection to {}:{} failed: {}", settings.host, settings.port, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn publish_state(settings: MqttSettings, client: AsyncClient, api: ApiClient) {
+    let mut interval = tokio::time::interval(Duration::from_millis(settings.publish_interval_ms.max(10)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        let (Ok(servos), Ok(telemetry)) = (api.call(ApiCommand::GetServos).await, api.call(ApiCommand::GetTelemetry).await) else {
+            continue;
+        };
+        let topics = &settings.topics;
+        let messages = [
+            (&topics.servos, json!({ "angles": servos["angles"], "command": servos["command"] })),
+            (&topics.position, servos["position"].clone()),
+            (&topics.health, telemetry["link"].clone()),
+            (&topics.adc, json!({ "values": telemetry["adc"] })),
+        ];
+        for (topic, payload) in messages {
+            // Old state is no use to anyone, so it's dropped rather than queued while the broker is away
+            if client.try_publish(topic, QoS::AtMostOnce, false, payload.to_string()).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// Handles commands one at a time in the order they arrived, publishing each result on the reply topic
+async fn handle_commands(settings: MqttSettings, client: AsyncClient, api: ApiClient, mut received: UnboundedReceiver<Vec<u8>>) {
+    let mut limit = RateLimit::new(settings.max_commands_per_second, Instant::now());
+    while let Some(payload) = received.recv().await {
+        let reply = match serde_json::from_slice::<MqttCommand>(&payload) {
+            Ok(command) => command_reply(&settings, &mut limit, &api, command).await,
+            Err(e) => json!({ "error": format!("Unable to parse command: {}", e) }),
+        };
+        if let Err(e) = client.publish(&settings.topics.reply, QoS::AtLeastOnce, false, reply.to_string()).await {
+            log::warn!("Unable to publish MQTT reply: {}", e);
+        }
+    }
+}
+
+async fn command_reply(settings: &MqttSettings, limit: &mut RateLimit, api: &ApiClient, command: MqttCommand) -> Value {
+    let kind = command.kind();
+    if !settings.allowed_commands.contains(&kind) {
+        return json!({ "error": format!("{} commands are not allowed", kind) });
+    }
+    if kind != CommandKind::EmergencyStop && !limit.allow(Instant::now()) {
+        if settings.max_commands_per_second > 0.0 {
+            return json!({ "error": format!("Rate limited to {} commands per second", settings.max_commands_per_second) });
+        }
+        return json!({ "error": format!("{} commands are disabled by a rate limit of 0", kind) });
+    }
+    match api.call(command.into_api()).await {
+        Ok(value) => value,
+        Err(e) => json!({ "error": e.message }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(limit: &mut RateLimit, now: Instant) -> usize {
+        (0..100).take_while(|_| limit.allow(now)).count()
+    }
+
+    #[test]
+    fn burst_is_a_second_of_commands() {
+        let start = Instant::now();
+        assert_eq!(drain(&mut RateLimit::new(5.0, start), start), 5);
+        // Slower than once a second still allows a single command
+        assert_eq!(drain(&mut RateLimit::new(0.5, start), start), 1);
+    }
+
+    #[test]
+    fn refills_over_time_up_to_a_burst() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(5.0, start);
+        assert_eq!(drain(&mut limit, start), 5);
+        assert!(!limit.allow(start + Duration::from_millis(100)));
+        assert_eq!(drain(&mut limit, start + Duration::from_millis(400)), 2);
+        assert_eq!(drain(&mut limit, start + Duration::from_millis(1000)), 3);
+        // Idling for longer doesn't save up more than a second's worth
+        assert_eq!(drain(&mut limit, start + Duration::from_secs(60)), 5);
+    }
+
+    #[test]
+    fn zero_rate_refuses_every_command() {
+        let start = Instant::now();
+        for per_second in [0.0, -1.0, f64::NAN] {
+            let mut limit = RateLimit::new(per_second, start);
+            assert!(!limit.allow(start));
+            assert!(!limit.allow(start + Duration::from_secs(60)));
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::net::SocketAddr;
 use std::net::{IpAddr};
 use std::sync::{Arc, Mutex};
-use mdns::{Record, RecordKind};
-use std::time::Duration;
-use futures_util::{pin_mut, stream::StreamExt};
+use mdns::{RecordKind, Response};
+use std::time::{Duration, Instant};
+use futures_util::stream::{select_all, StreamExt};
 use crate::SharedState;
 
 pub struct Network {
@@ -12,75 +12,75 @@ pub struct Network {
 }
 
 impl Network {
-    // Constructor for Network
-    pub fn new() -> Self {
-        Network {
-            // Initialize fields
-        }
-    }
 
+    // Browses for every service type in the shared state, starting over whenever the list changes
     pub async fn discover_devices(shared_state: Arc<Mutex<SharedState>>) {
-        let stream = mdns::discover::all("_controller._udp.local", Duration::from_secs(15))
-            .expect("Failed to start mDNS discovery")
-            .listen();
-        pin_mut!(stream);
-
-        let mut recent_port: Option<u16> = None;
-        let mut recent_target: Option<String> = None;
+        let mut services = shared_state.lock().unwrap().subscribe_services();
+        loop {
+            let current = services.borrow_and_update().clone();
+            log::info!("Browsing for {:?}", current);
+            let streams: Vec<_> = current
+                .into_iter()
+                .filter_map(|service| match mdns::discover::all(&service, Duration::from_secs(15)) {
+                    Ok(discovery) => Some(Box::pin(discovery.listen().map(move |response| (service.clone(), response)))),
+                    Err(e) => {
+                        log::error!("Failed to start mDNS discovery for {}: {}", service, e);
+                        None
+                    }
+                })
+                .collect();
+            let mut responses = select_all(streams);
 
-        while let Some(Ok(response)) = stream.next().await {
-            for record in response.records() {
-                match &record.kind {
-                    RecordKind::SRV { port, target, .. } => {
-                        recent_port = Some(*port);
-                        recent_target = Some(target.to_string());
-                    },
-                    RecordKind::A(ipv4_addr) => {
-                        if let Some(port) = recent_port {
-                            if Some(record.name.clone()) == recent_target {
-                                let addr = SocketAddr::new(IpAddr::V4(*ipv4_addr), port);
-                                let mut state = shared_state.lock().unwrap();
-                                state.discovered_ips.push(addr);
-                                recent_port = None;
-                                recent_target = None;
-                            }
+            loop {
+                tokio::select! {
+                    response = responses.next() => match response {
+                        Some((service, Ok(response))) => {
+                            let mut state = shared_state.lock().unwrap();
+                            Network::record_response(&mut state, &service, &response);
                         }
-                    },
-                    RecordKind::AAAA(ipv6_addr) => {
-                        if let Some(port) = recent_port {
-                            if Some(record.name.clone()) == recent_target {
-                                let addr = SocketAddr::new(IpAddr::V6(*ipv6_addr), port);
-                                let mut state = shared_state.lock().unwrap();
-                                state.discovered_ips.push(addr);
-                                recent_port = None;
-                                recent_target = None;
+                        Some((service, Err(e))) => log::warn!("mDNS error while browsing for {}: {}", service, e),
+                        // Nothing left to browse, so just wait for a new list
+                        None => {
+                            if services.changed().await.is_err() {
+                                return;
                             }
+                            break;
                         }
                     },
-                    _ => (),
+                    changed = services.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        break;
+                    }
                 }
             }
         }
     }
 
-
-
-    fn extract_port(record: &Record) -> Option<u16> {
-        if let RecordKind::SRV { port, .. } = record.kind {
-            Some(port)
-        } else {
-            None
+    fn record_response(state: &mut SharedState, service: &str, response: &Response) {
+        let now = Instant::now();
+        for record in response.records() {
+            match &record.kind {
+                // Responses can carry records for other services too, only the SRV and TXT records name the service
+                RecordKind::SRV { port, target, .. } if record.name.ends_with(service) => {
+                    state.devices.update_service(&record.name, target, *port, record.ttl, now);
+                },
+                RecordKind::TXT(entries) if record.name.ends_with(service) => {
+                    state.devices.update_txt(&record.name, entries, record.ttl, now);
+                },
+                RecordKind::A(ipv4_addr) => {
+                    state.devices.update_address(&record.name, IpAddr::V4(*ipv4_addr), record.ttl, now);
+                },
+                RecordKind::AAAA(ipv6_addr) => {
+                    state.devices.update_address(&record.name, IpAddr::V6(*ipv6_addr), record.ttl, now);
+                },
+                _ => (),
+            }
         }
+        state.devices.expire(now);
     }
 
-    // Helper function to convert mDNS record to IP address
-    fn to_ip_addr(record: &Record) -> Option<IpAddr> {
-        match record.kind {
-            RecordKind::A(addr) => Some(IpAddr::V4(addr)),
-            RecordKind::AAAA(addr) => Some(IpAddr::V6(addr)),
-            _ => None,
-        }
-    }
 
 
     pub fn check_ip_string(ip_string: &str) -> bool {
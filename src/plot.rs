@@ -1,15 +1,11 @@
 use plotters::prelude::*;
-use plotters::backend::{BGRXPixel, RGBPixel};
-use std::borrow::BorrowMut;
-use std::borrow::Borrow;
-use eframe::egui::Color32;
-use eframe::egui::ecolor::HexColor::Hex6;
-use image::Rgba;
+use plotters::backend::RGBPixel;
 use crate::arm::Arm;
 const OPACITY: f64 = 0.25;
 const STROKE_WIDTH: u32 = 4;
+const AXIS_LENGTH: f64 = 0.3;
 
-pub fn generate_plot(buf: &mut Vec<u8>, w: u32, h: u32, arm: &Arm, yaw: f64, scale: f64) -> Result<(), Box<dyn std::error::Error>> {
+pub fn generate_plot(buf: &mut [u8], w: u32, h: u32, arm: &Arm, yaw: f64, scale: f64) -> Result<(), Box<dyn std::error::Error>> {
     let area =
         BitMapBackend::<RGBPixel>::with_buffer_and_format(buf, (w , h))?
             .into_drawing_area();
@@ -22,7 +18,7 @@ pub fn generate_plot(buf: &mut Vec<u8>, w: u32, h: u32, arm: &Arm, yaw: f64, sca
 
 
     let mut chart = ChartBuilder::on(&area)
-        .caption("Arm Locations".to_string(), ("times", 20, &WHITE))
+        .caption("Arm Locations", ("times", 20, &WHITE))
         .build_cartesian_3d(x_axis.clone(), y_axis, z_axis)?;
 
     chart.with_projection(|mut pb| {
@@ -45,32 +41,25 @@ pub fn generate_plot(buf: &mut Vec<u8>, w: u32, h: u32, arm: &Arm, yaw: f64, sca
         .draw_series(std::iter::once(Circle::new(
             (0.0,0.0,0.0), // Position of the circle (end effector point)
             7, // Radius of the circle
-            *&YELLOW.filled(), // Style of the circle
+            YELLOW.filled(), // Style of the circle
         )))?;
 
+    // Draw the links between each joint along the chain
+    let positions = arm.get_joint_positions();
     chart
         .draw_series(LineSeries::new(
-            vec![(0.0, 0.0, 0.0), arm.get_elbow()], // Points from Origin to Elbow
-            *&YELLOW.stroke_width(STROKE_WIDTH), // Line color
+            positions.clone(), // Points from the Origin through every joint to the End Effector
+            YELLOW.stroke_width(STROKE_WIDTH), // Line color
         ))?
-        .label("Lower Arm")
+        .label("Arm")
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], YELLOW));
 
-
+    // Mark each joint with a circle
     chart
-        .draw_series(LineSeries::new(
-            vec![arm.get_elbow(), arm.get_ijk()], // Points from Elbow to End Effector)
-            *&RED.stroke_width(STROKE_WIDTH), // Line color
-        ))?
-        .label("Upper Arm")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
-
-    // Mark the elbow point with a circle
-    chart
-        .draw_series(std::iter::once(Circle::new(
-            arm.get_elbow(), // Position of the circle (elbow point)
+        .draw_series(positions[1..positions.len() - 1].iter().map(|position| Circle::new(
+            *position, // Position of the circle (joint)
             5, // Radius of the circle
-            *&RED.filled(), // Style of the circle
+            RED.filled(), // Style of the circle
         )))?;
 
     // Mark the IJK (End Effector) point with a circle
@@ -78,9 +67,20 @@ pub fn generate_plot(buf: &mut Vec<u8>, w: u32, h: u32, arm: &Arm, yaw: f64, sca
         .draw_series(std::iter::once(Circle::new(
             arm.get_ijk(), // Position of the circle (end effector point)
             5, // Radius of the circle
-            *&BLUE.filled(), // Style of the circle
+            BLUE.filled(), // Style of the circle
         )))?;
 
+    // Draw the tool frame's axes at the end effector, i in red, j in green and k in blue
+    let tool = arm.poses().tool;
+    let origin = tool.translation.vector;
+    for (axis, colour) in [(nalgebra::Vector3::x_axis(), RED), (nalgebra::Vector3::y_axis(), GREEN), (nalgebra::Vector3::z_axis(), BLUE)] {
+        let tip = origin + tool.rotation * axis.into_inner() * AXIS_LENGTH;
+        chart.draw_series(LineSeries::new(
+            vec![(origin.x, origin.y, origin.z), (tip.x, tip.y, tip.z)],
+            colour.stroke_width(2),
+        ))?;
+    }
+
     chart.configure_series_labels().border_style(WHITE.mix(OPACITY)).label_font(("times", 12, &WHITE)).draw()?;
 
     area.present().expect("Unable to write result to memory buffer");
@@ -0,0 +1,492 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::arm::SERVO_COUNT;
+
+// Every datagram starts with a header:
+//   [0]     protocol version
+//   [1]     message type
+//   [2..4]  sequence number, incremented by the sender for every datagram
+//   [4..6]  payload length
+// followed by the payload, then a CRC-16/CCITT-FALSE over the header and payload.
+// All multi-byte values are big endian.
+pub const PROTOCOL_VERSION: u8 = 5;
+pub const HEADER_LENGTH: usize = 6;
+pub const CRC_LENGTH: usize = 2;
+// Large enough for any message we send or expect back
+pub const MAX_PACKET_LENGTH: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    Hello = 0x01,
+    Ping = 0x02,
+    Pong = 0x03,
+    ServoCommand = 0x10,
+    ServoFeedback = 0x11,
+    EmergencyStop = 0x12,
+    SensorReport = 0x20,
+    Ack = 0x30,
+    Error = 0x31,
+}
+
+impl MessageType {
+    pub fn from_byte(byte: u8) -> Option<MessageType> {
+        match byte {
+            0x01 => Some(MessageType::Hello),
+            0x02 => Some(MessageType::Ping),
+            0x03 => Some(MessageType::Pong),
+            0x10 => Some(MessageType::ServoCommand),
+            0x11 => Some(MessageType::ServoFeedback),
+            0x12 => Some(MessageType::EmergencyStop),
+            0x20 => Some(MessageType::SensorReport),
+            0x30 => Some(MessageType::Ack),
+            0x31 => Some(MessageType::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // Sent by either side when it starts up, so the other knows what it is talking to
+    Hello { protocol_version: u8, servo_count: u8, name: String },
+    // Heartbeat, answered straight away with a Pong carrying the same ID
+    Ping { id: u16 },
+    Pong { id: u16 },
+    // Wire values for every servo, in wire order. The arm acks the ID once it has the command.
+    ServoCommand { id: u16, servos: [u16; SERVO_COUNT] },
+    // Where the arm reports each servo to be, in wire values
+    ServoFeedback { servos: [u16; SERVO_COUNT] },
+    // Stop all motion and relax every servo. The arm acks the ID, and ignores any servo command in a datagram
+    // with an older sequence number than the stop, so a late retry can't move it again.
+    EmergencyStop { id: u16 },
+    // Raw ADC readings, one per channel
+    SensorReport { adc: Vec<u16> },
+    // Acknowledges a message of the given type, with the ID it carried or 0 if it had none
+    Ack { message_type: u8, id: u16 },
+    Error { code: u8, message: String },
+}
+
+impl Message {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Hello { .. } => MessageType::Hello,
+            Message::Ping { .. } => MessageType::Ping,
+            Message::Pong { .. } => MessageType::Pong,
+            Message::ServoCommand { .. } => MessageType::ServoCommand,
+            Message::ServoFeedback { .. } => MessageType::ServoFeedback,
+            Message::EmergencyStop { .. } => MessageType::EmergencyStop,
+            Message::SensorReport { .. } => MessageType::SensorReport,
+            Message::Ack { .. } => MessageType::Ack,
+            Message::Error { .. } => MessageType::Error,
+        }
+    }
+
+    pub fn encode(&self, sequence: u16) -> Result<Vec<u8>, ProtocolError> {
+        let mut payload = Vec::new();
+        match self {
+            Message::Hello { protocol_version, servo_count, name } => {
+                payload.push(*protocol_version);
+                payload.push(*servo_count);
+                put_string(&mut payload, name)?;
+            }
+            Message::Ping { id } | Message::Pong { id } | Message::EmergencyStop { id } => {
+                payload.extend_from_slice(&id.to_be_bytes());
+            }
+            Message::ServoCommand { id, servos } => {
+                payload.extend_from_slice(&id.to_be_bytes());
+                for value in servos {
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Message::ServoFeedback { servos } => {
+                for value in servos {
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Message::SensorReport { adc } => {
+                let count = u8::try_from(adc.len()).map_err(|_| ProtocolError::TooLong)?;
+                payload.push(count);
+                for value in adc {
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Message::Ack { message_type, id } => {
+                payload.push(*message_type);
+                payload.extend_from_slice(&id.to_be_bytes());
+            }
+            Message::Error { code, message } => {
+                payload.push(*code);
+                put_string(&mut payload, message)?;
+            }
+        }
+
+        if HEADER_LENGTH + payload.len() + CRC_LENGTH > MAX_PACKET_LENGTH {
+            return Err(ProtocolError::TooLong);
+        }
+        let mut packet = Vec::with_capacity(HEADER_LENGTH + payload.len() + CRC_LENGTH);
+        packet.push(PROTOCOL_VERSION);
+        packet.push(self.message_type() as u8);
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&payload);
+        let crc = crc16(&packet);
+        packet.extend_from_slice(&crc.to_be_bytes());
+        Ok(packet)
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<Packet, ProtocolError> {
+        if packet.len() < HEADER_LENGTH + CRC_LENGTH {
+            return Err(ProtocolError::TooShort { expected: HEADER_LENGTH + CRC_LENGTH, actual: packet.len() });
+        }
+        // Check the CRC first, a corrupted header can't be trusted to say anything else
+        let (body, crc) = packet.split_at(packet.len() - CRC_LENGTH);
+        let expected = u16::from_be_bytes([crc[0], crc[1]]);
+        let actual = crc16(body);
+        if expected != actual {
+            return Err(ProtocolError::BadChecksum { expected, actual });
+        }
+        if packet[0] != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(packet[0]));
+        }
+        let message_type = MessageType::from_byte(packet[1]).ok_or(ProtocolError::UnknownType(packet[1]))?;
+        let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+        let length = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+        if packet.len() != HEADER_LENGTH + length + CRC_LENGTH {
+            return Err(ProtocolError::LengthMismatch { expected: HEADER_LENGTH + length + CRC_LENGTH, actual: packet.len() });
+        }
+
+        let mut reader = Reader { bytes: &body[HEADER_LENGTH..] };
+        let message = match message_type {
+            MessageType::Hello => Message::Hello {
+                protocol_version: reader.u8()?,
+                servo_count: reader.u8()?,
+                name: reader.string()?,
+            },
+            MessageType::Ping => Message::Ping { id: reader.u16()? },
+            MessageType::Pong => Message::Pong { id: reader.u16()? },
+            MessageType::ServoCommand => Message::ServoCommand {
+                id: reader.u16()?,
+                servos: reader.servos()?,
+            },
+            MessageType::ServoFeedback => Message::ServoFeedback { servos: reader.servos()? },
+            MessageType::EmergencyStop => Message::EmergencyStop { id: reader.u16()? },
+            MessageType::SensorReport => {
+                let count = reader.u8()? as usize;
+                let adc = (0..count).map(|_| reader.u16()).collect::<Result<Vec<u16>, ProtocolError>>()?;
+                Message::SensorReport { adc }
+            }
+            MessageType::Ack => Message::Ack {
+                message_type: reader.u8()?,
+                id: reader.u16()?,
+            },
+            MessageType::Error => Message::Error {
+                code: reader.u8()?,
+                message: reader.string()?,
+            },
+        };
+        // Anything left over means the payload doesn't match its type
+        if !reader.bytes.is_empty() {
+            return Err(ProtocolError::TrailingBytes(reader.bytes.len()));
+        }
+        Ok(Packet { sequence, message })
+    }
+}
+
+// A decoded datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence: u16,
+    pub message: Message,
+}
+
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, no reflection
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Keeps track of the sequence numbers coming from one sender, and counts what went missing on the way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketStats {
+    pub received: u64,
+    // Sequence numbers that were skipped over, so were lost or are still on their way
+    pub dropped: u64,
+    // Packets that arrived after a newer one, or twice
+    pub reordered: u64,
+    // Packets that failed to decode, usually a bad checksum
+    pub corrupted: u64,
+    last_sequence: Option<u16>,
+}
+
+impl PacketStats {
+    // Returns whether a packet with this sequence number is newer than everything seen so far.
+    // Sequence numbers wrap, so anything up to half the range ahead counts as newer.
+    pub fn accept(&mut self, sequence: u16) -> bool {
+        if let Some(last) = self.last_sequence {
+            let ahead = sequence.wrapping_sub(last) as i16;
+            if ahead <= 0 {
+                self.reordered += 1;
+                return false;
+            }
+            self.dropped += (ahead - 1) as u64;
+        }
+        self.last_sequence = Some(sequence);
+        self.received += 1;
+        true
+    }
+
+    pub fn corrupted(&mut self) {
+        self.corrupted += 1;
+    }
+
+    // Forgets the last sequence number, for when the sender has restarted and is counting from zero again
+    pub fn restart(&mut self) {
+        self.last_sequence = None;
+    }
+}
+
+// Strings are a length byte followed by UTF-8
+fn put_string(payload: &mut Vec<u8>, value: &str) -> Result<(), ProtocolError> {
+    let length = u8::try_from(value.len()).map_err(|_| ProtocolError::TooLong)?;
+    payload.push(length);
+    payload.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ProtocolError> {
+        if self.bytes.len() < count {
+            return Err(ProtocolError::TruncatedPayload);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn servos(&mut self) -> Result<[u16; SERVO_COUNT], ProtocolError> {
+        let mut servos = [0; SERVO_COUNT];
+        for value in servos.iter_mut() {
+            *value = self.u16()?;
+        }
+        Ok(servos)
+    }
+
+    fn string(&mut self) -> Result<String, ProtocolError> {
+        let length = self.u8()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| ProtocolError::InvalidString)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    TooShort { expected: usize, actual: usize },
+    TooLong,
+    UnsupportedVersion(u8),
+    UnknownType(u8),
+    LengthMismatch { expected: usize, actual: usize },
+    TruncatedPayload,
+    TrailingBytes(usize),
+    InvalidString,
+    BadChecksum { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::TooShort { expected, actual } => write!(f, "Packet is {} bytes, at least {} are needed", actual, expected),
+            ProtocolError::TooLong => write!(f, "Message is too long to send"),
+            ProtocolError::UnsupportedVersion(version) => write!(f, "Unsupported protocol version {}, expected {}", version, PROTOCOL_VERSION),
+            ProtocolError::UnknownType(message_type) => write!(f, "Unknown message type {:#04x}", message_type),
+            ProtocolError::LengthMismatch { expected, actual } => write!(f, "Packet is {} bytes, but the header says {}", actual, expected),
+            ProtocolError::TruncatedPayload => write!(f, "Payload is shorter than its message type needs"),
+            ProtocolError::TrailingBytes(count) => write!(f, "Payload has {} unexpected bytes at the end", count),
+            ProtocolError::InvalidString => write!(f, "Payload string is not valid UTF-8"),
+            ProtocolError::BadChecksum { expected, actual } => write!(f, "Checksum is {:#06x}, but the packet says {:#06x}", actual, expected),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small xorshift generator so the property tests are reproducible without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+
+        fn string(&mut self) -> String {
+            let length = self.below(40) as usize;
+            (0..length).map(|_| ['a', 'Z', '7', ' ', 'é', '°'][self.below(6) as usize]).collect()
+        }
+
+        fn message(&mut self) -> Message {
+            match self.below(9) {
+                0 => Message::Hello {
+                    protocol_version: self.next() as u8,
+                    servo_count: self.next() as u8,
+                    name: self.string(),
+                },
+                1 => Message::ServoCommand {
+                    id: self.next() as u16,
+                    servos: std::array::from_fn(|_| self.next() as u16),
+                },
+                2 => Message::ServoFeedback { servos: std::array::from_fn(|_| self.next() as u16) },
+                3 => {
+                    let count = self.below(64) as usize;
+                    Message::SensorReport { adc: (0..count).map(|_| self.next() as u16).collect() }
+                }
+                4 => Message::Ack { message_type: self.next() as u8, id: self.next() as u16 },
+                5 => Message::Ping { id: self.next() as u16 },
+                6 => Message::Pong { id: self.next() as u16 },
+                7 => Message::EmergencyStop { id: self.next() as u16 },
+                _ => Message::Error { code: self.next() as u8, message: self.string() },
+            }
+        }
+    }
+
+    // Recomputes the CRC after a test has edited a packet by hand
+    fn reseal(packet: &mut [u8]) {
+        let end = packet.len() - CRC_LENGTH;
+        let crc = crc16(&packet[..end]);
+        packet[end..].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    #[test]
+    fn random_messages_round_trip() {
+        let mut rng = Rng(0x5eed_1234_abcd_0001);
+        for _ in 0..10_000 {
+            let message = rng.message();
+            let sequence = rng.next() as u16;
+            let packet = message.encode(sequence).unwrap();
+            assert_eq!(packet.len(), HEADER_LENGTH + u16::from_be_bytes([packet[4], packet[5]]) as usize + CRC_LENGTH);
+            assert_eq!(Message::decode(&packet), Ok(Packet { sequence, message }));
+        }
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = Rng(0x0bad_cafe_0000_0042);
+        for _ in 0..10_000 {
+            let length = rng.below(64) as usize;
+            let mut packet: Vec<u8> = (0..length).map(|_| rng.next() as u8).collect();
+            // Make most packets get past the header checks so the payload parsing is exercised too
+            if packet.len() >= HEADER_LENGTH + CRC_LENGTH && rng.below(4) != 0 {
+                packet[0] = PROTOCOL_VERSION;
+                packet[1] = [0x01, 0x02, 0x03, 0x10, 0x11, 0x12, 0x20, 0x30, 0x31][rng.below(9) as usize];
+                let payload = (packet.len() - HEADER_LENGTH - CRC_LENGTH) as u16;
+                packet[4..6].copy_from_slice(&payload.to_be_bytes());
+                reseal(&mut packet);
+            }
+            let _ = Message::decode(&packet);
+        }
+    }
+
+    #[test]
+    fn corrupted_packets_are_rejected() {
+        let mut rng = Rng(0xfeed_0000_1111_2222);
+        for _ in 0..10_000 {
+            let mut packet = rng.message().encode(rng.next() as u16).unwrap();
+            // A CRC-16 catches every single bit error
+            let bit = rng.below(packet.len() as u64 * 8) as usize;
+            packet[bit / 8] ^= 1 << (bit % 8);
+            assert!(matches!(Message::decode(&packet), Err(ProtocolError::BadChecksum { .. })));
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let packet = Message::ServoCommand { id: 6, servos: [1, 2, 3, 4, 5] }.encode(7).unwrap();
+        for length in 0..packet.len() {
+            assert!(Message::decode(&packet[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn servo_command_layout() {
+        let packet = Message::ServoCommand { id: 0x0506, servos: [0x0102, 0, 0, 0, 0xffff] }.encode(0x0304).unwrap();
+        assert_eq!(&packet[..10], &[PROTOCOL_VERSION, 0x10, 0x03, 0x04, 0, 12, 0x05, 0x06, 0x01, 0x02]);
+        assert_eq!(&packet[packet.len() - 4..packet.len() - 2], &[0xff, 0xff]);
+    }
+
+    #[test]
+    fn header_errors() {
+        let mut packet = Message::Ack { message_type: 0x10, id: 1 }.encode(0).unwrap();
+        packet[0] = PROTOCOL_VERSION + 1;
+        reseal(&mut packet);
+        assert_eq!(Message::decode(&packet), Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+        packet[0] = PROTOCOL_VERSION;
+        packet[1] = 0x7f;
+        reseal(&mut packet);
+        assert_eq!(Message::decode(&packet), Err(ProtocolError::UnknownType(0x7f)));
+        packet[1] = MessageType::Ack as u8;
+        packet.insert(HEADER_LENGTH, 0);
+        reseal(&mut packet);
+        assert_eq!(Message::decode(&packet), Err(ProtocolError::LengthMismatch { expected: 11, actual: 12 }));
+        packet[5] = 4;
+        reseal(&mut packet);
+        assert_eq!(Message::decode(&packet), Err(ProtocolError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn oversized_messages_are_refused() {
+        let name = "x".repeat(256);
+        assert_eq!(Message::Hello { protocol_version: 1, servo_count: 5, name }.encode(0), Err(ProtocolError::TooLong));
+        let adc = vec![0; 254];
+        assert_eq!(Message::SensorReport { adc }.encode(0), Err(ProtocolError::TooLong));
+    }
+
+    #[test]
+    fn sequence_tracking() {
+        let mut stats = PacketStats::default();
+        assert!(stats.accept(65534));
+        assert!(stats.accept(65535));
+        // Wraps around
+        assert!(stats.accept(1));
+        assert_eq!(stats.dropped, 1);
+        // Late arrival of the one that was skipped, and a duplicate
+        assert!(!stats.accept(0));
+        assert!(!stats.accept(1));
+        assert_eq!(stats.reordered, 2);
+        assert_eq!(stats.received, 3);
+        stats.restart();
+        assert!(stats.accept(0));
+    }
+}
@@ -0,0 +1,322 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::arm::SERVO_COUNT;
+
+// Samples closer together than this are dropped, the sliders can't be moved meaningfully faster
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 4.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub at: DateTime<Local>,
+    // Servo angles in degrees, ordered as in arm::SERVO_NAMES
+    pub angles: [f64; SERVO_COUNT],
+}
+
+// Servo angles recorded from the sliders, to be played back later. Saved as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub name: String,
+    pub samples: Vec<Sample>,
+}
+
+impl Trajectory {
+    pub fn new(name: &str) -> Self {
+        Trajectory {
+            name: name.to_owned(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let contents = fs::read_to_string(path)?;
+        let trajectory: Trajectory = serde_json::from_str(&contents)?;
+        if trajectory.samples.is_empty() {
+            return Err(RecordingError::Empty);
+        }
+        Ok(trajectory)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RecordingError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // Only keeps samples where the pose changed, so holding still doesn't fill the trajectory up.
+    // After a hold the held pose is sampled again just before the change, so playback holds for as long as the recording did.
+    pub fn record(&mut self, angles: [f64; SERVO_COUNT], at: DateTime<Local>) {
+        let Some(last) = self.samples.last() else {
+            self.samples.push(Sample { at, angles });
+            return;
+        };
+        let since = (at - last.at).to_std().unwrap_or_default();
+        if last.angles == angles || since < SAMPLE_INTERVAL {
+            return;
+        }
+        if since > 2 * SAMPLE_INTERVAL {
+            let held = Sample { at: at - chrono::Duration::from_std(SAMPLE_INTERVAL).unwrap_or_default(), angles: last.angles };
+            self.samples.push(held);
+        }
+        self.samples.push(Sample { at, angles });
+    }
+
+    // Keeps the hold at the end of the recording
+    pub fn finish(&mut self, at: DateTime<Local>) {
+        if let Some(last) = self.samples.last() {
+            if at > last.at {
+                let angles = last.angles;
+                self.samples.push(Sample { at, angles });
+            }
+        }
+    }
+
+    pub fn started_at(&self) -> Option<DateTime<Local>> {
+        self.samples.first().map(|sample| sample.at)
+    }
+
+    // Seconds from the first sample to the last
+    pub fn duration(&self) -> f64 {
+        match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => seconds_between(first.at, last.at),
+            _ => 0.0,
+        }
+    }
+
+    // The pose at a time into the trajectory, interpolated between the samples either side
+    pub fn angles_at(&self, time: f64) -> Option<[f64; SERVO_COUNT]> {
+        let start = self.started_at()?;
+        let next = self.samples.iter().position(|sample| seconds_between(start, sample.at) > time);
+        let angles = match next {
+            Some(0) => self.samples[0].angles,
+            Some(index) => {
+                let (before, after) = (&self.samples[index - 1], &self.samples[index]);
+                let (from, to) = (seconds_between(start, before.at), seconds_between(start, after.at));
+                let t = (time - from) / (to - from);
+                std::array::from_fn(|servo| before.angles[servo] + (after.angles[servo] - before.angles[servo]) * t)
+            }
+            None => self.samples.last()?.angles,
+        };
+        Some(angles)
+    }
+}
+
+fn seconds_between(from: DateTime<Local>, to: DateTime<Local>) -> f64 {
+    (to - from).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0
+}
+
+// Where playback of a trajectory or sequence has got to
+pub struct Playback {
+    // Seconds from the start
+    pub position: f64,
+    pub speed: f64,
+    pub looping: bool,
+    pub paused: bool,
+    last_tick: Instant,
+}
+
+impl Playback {
+    pub fn new(speed: f64, looping: bool, now: Instant) -> Self {
+        Playback {
+            position: 0.0,
+            speed,
+            looping,
+            paused: false,
+            last_tick: now,
+        }
+    }
+
+    // Moves the position on by the time since the last tick. Pauses at the end unless looping.
+    pub fn tick(&mut self, duration: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_tick).as_secs_f64();
+        self.last_tick = now;
+        if self.paused {
+            return;
+        }
+        self.position += elapsed * self.speed.clamp(MIN_SPEED, MAX_SPEED);
+        if self.position >= duration {
+            if self.looping && duration > 0.0 {
+                self.position %= duration;
+            } else {
+                self.position = duration;
+                self.paused = true;
+            }
+        }
+    }
+
+    // Carries on from where it was paused, or from the start if it had reached the end
+    pub fn resume(&mut self, duration: f64, now: Instant) {
+        if self.position >= duration {
+            self.position = 0.0;
+        }
+        self.paused = false;
+        self.last_tick = now;
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Empty,
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "Unable to access trajectory file: {}", e),
+            RecordingError::Json(e) => write!(f, "Unable to parse trajectory JSON: {}", e),
+            RecordingError::Empty => write!(f, "The trajectory has no samples"),
+        }
+    }
+}
+
+impl Error for RecordingError {}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(e: std::io::Error) -> Self {
+        RecordingError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RecordingError {
+    fn from(e: serde_json::Error) -> Self {
+        RecordingError::Json(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(angle: f64) -> [f64; SERVO_COUNT] {
+        [angle; SERVO_COUNT]
+    }
+
+    fn seconds(seconds: f64) -> chrono::Duration {
+        chrono::Duration::microseconds((seconds * 1_000_000.0) as i64)
+    }
+
+    // Samples at 0 s, 1 s and 3 s
+    fn trajectory() -> Trajectory {
+        let start = Local::now();
+        let mut trajectory = Trajectory::new("wave");
+        for (at, angle) in [(0.0, 90.0), (1.0, 100.0), (3.0, 80.0)] {
+            trajectory.samples.push(Sample { at: start + seconds(at), angles: pose(angle) });
+        }
+        trajectory
+    }
+
+    fn assert_pose(angles: Option<[f64; SERVO_COUNT]>, angle: f64) {
+        let angles = angles.expect("Trajectory should have a pose");
+        assert!(angles.iter().all(|servo| (servo - angle).abs() < 1e-9), "{:?} isn't {}", angles, angle);
+    }
+
+    #[test]
+    fn angles_interpolate_between_samples() {
+        let trajectory = trajectory();
+        assert_eq!(trajectory.duration(), 3.0);
+        assert_pose(trajectory.angles_at(0.0), 90.0);
+        assert_pose(trajectory.angles_at(0.5), 95.0);
+        assert_pose(trajectory.angles_at(1.0), 100.0);
+        assert_pose(trajectory.angles_at(2.5), 85.0);
+        // Before the start and at or past the end it holds the first and last poses
+        assert_pose(trajectory.angles_at(-1.0), 90.0);
+        assert_pose(trajectory.angles_at(3.0), 80.0);
+        assert_pose(trajectory.angles_at(30.0), 80.0);
+    }
+
+    #[test]
+    fn empty_trajectory_has_no_pose() {
+        let trajectory = Trajectory::new("empty");
+        assert_eq!(trajectory.duration(), 0.0);
+        assert_eq!(trajectory.angles_at(0.0), None);
+        assert_eq!(trajectory.started_at(), None);
+    }
+
+    #[test]
+    fn recording_skips_repeats_and_keeps_holds() {
+        let start = Local::now();
+        let mut trajectory = Trajectory::new("hold");
+        trajectory.record(pose(90.0), start);
+        // The same pose, and changes too soon after the last sample, are dropped
+        trajectory.record(pose(90.0), start + seconds(0.1));
+        trajectory.record(pose(91.0), start + seconds(0.01));
+        assert_eq!(trajectory.samples.len(), 1);
+        // After holding for a second the held pose is sampled again just before the move
+        trajectory.record(pose(100.0), start + seconds(1.0));
+        let times: Vec<f64> = trajectory.samples.iter().map(|sample| seconds_between(start, sample.at)).collect();
+        assert_eq!(times, [0.0, 1.0 - SAMPLE_INTERVAL.as_secs_f64(), 1.0]);
+        assert_pose(trajectory.angles_at(0.5), 90.0);
+        trajectory.finish(start + seconds(2.0));
+        assert_eq!(trajectory.duration(), 2.0);
+        assert_pose(trajectory.angles_at(1.5), 100.0);
+    }
+
+    #[test]
+    fn playback_stops_at_the_end_unless_looping() {
+        let start = Instant::now();
+        let mut playback = Playback::new(2.0, false, start);
+        playback.tick(3.0, start + Duration::from_millis(500));
+        assert_eq!((playback.position, playback.paused), (1.0, false));
+        playback.tick(3.0, start + Duration::from_secs(5));
+        assert_eq!((playback.position, playback.paused), (3.0, true));
+        // Paused playback holds its position however long it's left
+        playback.tick(3.0, start + Duration::from_secs(10));
+        assert_eq!(playback.position, 3.0);
+        // Resuming from the end starts again from the beginning, without counting the time spent paused
+        playback.resume(3.0, start + Duration::from_secs(10));
+        assert_eq!((playback.position, playback.paused), (0.0, false));
+        playback.tick(3.0, start + Duration::from_millis(10_250));
+        assert_eq!(playback.position, 0.5);
+
+        let mut looping = Playback::new(1.0, true, start);
+        looping.tick(3.0, start + Duration::from_millis(3_500));
+        assert_eq!((looping.position, looping.paused), (0.5, false));
+    }
+
+    #[test]
+    fn playback_speed_is_limited() {
+        let start = Instant::now();
+        let mut fast = Playback::new(100.0, false, start);
+        fast.tick(60.0, start + Duration::from_secs(1));
+        assert_eq!(fast.position, MAX_SPEED);
+        let mut slow = Playback::new(0.0, false, start);
+        slow.tick(60.0, start + Duration::from_secs(1));
+        assert_eq!(slow.position, MIN_SPEED);
+    }
+
+    #[test]
+    fn resume_carries_on_from_a_pause() {
+        let start = Instant::now();
+        let mut playback = Playback::new(1.0, false, start);
+        playback.tick(3.0, start + Duration::from_secs(1));
+        playback.paused = true;
+        playback.tick(3.0, start + Duration::from_secs(2));
+        playback.resume(3.0, start + Duration::from_secs(5));
+        assert_eq!((playback.position, playback.paused), (1.0, false));
+    }
+
+    #[test]
+    fn saved_trajectories_load_back() {
+        let path = std::env::temp_dir().join(format!("controller-trajectory-{}.json", std::process::id()));
+        let trajectory = trajectory();
+        trajectory.save(&path).unwrap();
+        let loaded = Trajectory::load(&path).unwrap();
+        assert_eq!(loaded.name, trajectory.name);
+        assert_eq!(loaded.samples.len(), 3);
+        assert_pose(loaded.angles_at(2.5), 85.0);
+
+        Trajectory::new("empty").save(&path).unwrap();
+        let empty = Trajectory::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(empty, Err(RecordingError::Empty)), "{:?}", empty);
+    }
+}
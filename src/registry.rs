@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::capabilities::Capabilities;
+
+// Offline devices stay in the list this long after they expire, so a device that drops out briefly keeps its place
+pub const FORGET_AFTER: Duration = Duration::from_secs(600);
+
+// One arm found over mDNS, keyed by its instance name, e.g. "arm-1._controller._udp.local"
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub instance: String,
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub txt: BTreeMap<String, String>,
+    pub capabilities: Capabilities,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    // When the SRV record's TTL runs out, after which the device counts as offline
+    pub expires: Instant,
+}
+
+impl Device {
+    fn new(instance: &str, now: Instant) -> Self {
+        Device {
+            instance: instance.to_owned(),
+            hostname: None,
+            port: None,
+            txt: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            first_seen: now,
+            last_seen: now,
+            expires: now,
+        }
+    }
+
+    pub fn is_online(&self, now: Instant) -> bool {
+        now < self.expires
+    }
+
+    // The instance name without the service type, which is what a person would call the device
+    pub fn name(&self) -> &str {
+        self.instance.split('.').next().unwrap_or(&self.instance)
+    }
+
+    fn seen(&mut self, ttl: u32, now: Instant) {
+        self.last_seen = now;
+        self.expires = now + Duration::from_secs(ttl as u64);
+    }
+}
+
+// Addresses of one host, each with its own expiry
+#[derive(Debug, Clone, Default)]
+struct Host {
+    addresses: Vec<(IpAddr, Instant)>,
+}
+
+// Everything discovery has heard about, with entries expiring by their record TTLs.
+// mDNS answers arrive as separate SRV, TXT and A/AAAA records, so devices and hosts are kept apart and joined on the hostname.
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    devices: BTreeMap<String, Device>,
+    hosts: HashMap<String, Host>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        DeviceRegistry::default()
+    }
+
+    // A TTL of 0 is a goodbye, the device is going away
+    pub fn update_service(&mut self, instance: &str, hostname: &str, port: u16, ttl: u32, now: Instant) {
+        let device = self.devices.entry(instance.to_owned()).or_insert_with(|| Device::new(instance, now));
+        device.hostname = Some(hostname.to_owned());
+        device.port = Some(port);
+        device.seen(ttl, now);
+    }
+
+    // TXT entries are "key=value", or just "key" for a flag
+    pub fn update_txt(&mut self, instance: &str, entries: &[String], ttl: u32, now: Instant) {
+        let device = self.devices.entry(instance.to_owned()).or_insert_with(|| Device::new(instance, now));
+        device.txt = entries
+            .iter()
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once('=') {
+                Some((key, value)) => (key.to_ascii_lowercase(), value.to_owned()),
+                None => (entry.to_ascii_lowercase(), String::new()),
+            })
+            .collect();
+        device.capabilities = Capabilities::from_txt(&device.txt);
+        // A TXT record alone isn't enough to reach the device, so it only keeps an already known device alive
+        if device.port.is_some() {
+            device.seen(ttl, now);
+        }
+    }
+
+    pub fn update_address(&mut self, hostname: &str, address: IpAddr, ttl: u32, now: Instant) {
+        let host = self.hosts.entry(hostname.to_owned()).or_default();
+        let expires = now + Duration::from_secs(ttl as u64);
+        match host.addresses.iter_mut().find(|(existing, _)| *existing == address) {
+            Some(entry) => entry.1 = expires,
+            None => host.addresses.push((address, expires)),
+        }
+    }
+
+    // Drops addresses whose TTL has run out, and devices that have been offline for a long time
+    pub fn expire(&mut self, now: Instant) {
+        for host in self.hosts.values_mut() {
+            host.addresses.retain(|(_, expires)| now < *expires);
+        }
+        self.hosts.retain(|_, host| !host.addresses.is_empty());
+        self.devices.retain(|_, device| now < device.expires + FORGET_AFTER);
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &Device> {
+        self.devices.values()
+    }
+
+    pub fn get(&self, instance: &str) -> Option<&Device> {
+        self.devices.get(instance)
+    }
+
+    // Every address the device can currently be reached on, IPv4 first
+    pub fn addresses(&self, device: &Device, now: Instant) -> Vec<SocketAddr> {
+        let (Some(hostname), Some(port)) = (&device.hostname, device.port) else {
+            return Vec::new();
+        };
+        let mut addresses: Vec<SocketAddr> = self
+            .hosts
+            .get(hostname)
+            .map(|host| {
+                host.addresses
+                    .iter()
+                    .filter(|(_, expires)| now < *expires)
+                    .map(|(address, _)| SocketAddr::new(*address, port))
+                    .collect()
+            })
+            .unwrap_or_default();
+        addresses.sort_by_key(|address| address.is_ipv6());
+        addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const INSTANCE: &str = "arm-1._controller._udp.local";
+    const HOSTNAME: &str = "arm-1.local";
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn txt(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn devices_expire_with_their_srv_ttl() {
+        let start = Instant::now();
+        let mut registry = DeviceRegistry::new();
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 120, start);
+        let device = registry.get(INSTANCE).unwrap();
+        assert_eq!(device.name(), "arm-1");
+        assert!(device.is_online(start + secs(119)));
+        assert!(!device.is_online(start + secs(120)));
+
+        // Hearing from it again pushes the expiry out, but it was first seen when it was first seen
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 120, start + secs(100));
+        let device = registry.get(INSTANCE).unwrap();
+        assert!(device.is_online(start + secs(219)));
+        assert_eq!((device.first_seen, device.last_seen), (start, start + secs(100)));
+    }
+
+    #[test]
+    fn goodbye_takes_a_device_offline_straight_away() {
+        let start = Instant::now();
+        let mut registry = DeviceRegistry::new();
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 120, start);
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 0, start + secs(5));
+        assert!(!registry.get(INSTANCE).unwrap().is_online(start + secs(5)));
+        // It stays in the list, offline, until it has been gone for a while
+        registry.expire(start + secs(5) + FORGET_AFTER - secs(1));
+        assert!(registry.get(INSTANCE).is_some());
+        registry.expire(start + secs(5) + FORGET_AFTER);
+        assert!(registry.get(INSTANCE).is_none());
+        assert_eq!(registry.devices().count(), 0);
+    }
+
+    #[test]
+    fn txt_entries_become_lowercase_keys() {
+        let start = Instant::now();
+        let mut registry = DeviceRegistry::new();
+        registry.update_txt(INSTANCE, &txt(&["FW=1.4.2", "debug", "", "note=a=b"]), 120, start);
+        let device = registry.get(INSTANCE).unwrap();
+        let expected: BTreeMap<String, String> =
+            [("fw", "1.4.2"), ("debug", ""), ("note", "a=b")].into_iter().map(|(key, value)| (key.to_owned(), value.to_owned())).collect();
+        assert_eq!(device.txt, expected);
+        // Without an SRV record there is nowhere to send to, so it isn't online yet
+        assert!(!device.is_online(start));
+
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 10, start);
+        registry.update_txt(INSTANCE, &txt(&["fw=1.5.0"]), 120, start + secs(5));
+        let device = registry.get(INSTANCE).unwrap();
+        assert!(device.is_online(start + secs(100)));
+        assert_eq!(device.txt.get("fw").map(String::as_str), Some("1.5.0"));
+    }
+
+    #[test]
+    fn addresses_list_ipv4_first_and_expire() {
+        let start = Instant::now();
+        let mut registry = DeviceRegistry::new();
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        let v6 = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        registry.update_address(HOSTNAME, v6, 120, start);
+        registry.update_address(HOSTNAME, v4, 10, start);
+        registry.update_service(INSTANCE, HOSTNAME, 1234, 120, start);
+        let device = registry.get(INSTANCE).unwrap().clone();
+        assert_eq!(registry.addresses(&device, start), [SocketAddr::new(v4, 1234), SocketAddr::new(v6, 1234)]);
+
+        // The IPv4 address runs out first, unless it is heard again
+        assert_eq!(registry.addresses(&device, start + secs(10)), [SocketAddr::new(v6, 1234)]);
+        registry.update_address(HOSTNAME, v4, 10, start + secs(8));
+        assert_eq!(registry.addresses(&device, start + secs(10)).len(), 2);
+
+        registry.expire(start + secs(120));
+        assert!(registry.addresses(&device, start + secs(10)).is_empty());
+    }
+
+    #[test]
+    fn addresses_need_a_hostname_and_port() {
+        let start = Instant::now();
+        let mut registry = DeviceRegistry::new();
+        registry.update_address(HOSTNAME, IpAddr::V4(Ipv4Addr::LOCALHOST), 120, start);
+        registry.update_txt(INSTANCE, &txt(&["fw=1.4.2"]), 120, start);
+        let device = registry.get(INSTANCE).unwrap().clone();
+        assert!(registry.addresses(&device, start).is_empty());
+    }
+}
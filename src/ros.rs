@@ -0,0 +1,318 @@
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::api::{ApiClient, ApiCommand};
+use crate::arm::{self, Arm, ServoLimit, SERVO_COUNT};
+use crate::kinematics::{ChainDescription, JointDescription};
+
+// How long to wait before reconnecting after rosbridge goes away
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+// Root of the URDF, the frame the chain starts from
+pub const BASE_LINK: &str = "base_link";
+const TOOL_LINK: &str = "tool_link";
+// Radius of the cylinders drawn for each link, in metres
+const LINK_RADIUS: f64 = 0.02;
+// URDF needs effort and velocity limits on revolute joints, the servos don't report theirs
+const JOINT_EFFORT: f64 = 1.0;
+const JOINT_VELOCITY: f64 = 1.0;
+
+// Where the arm is published for ROS 2 tools like RViz, off unless enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RosSettings {
+    pub enabled: bool,
+    // rosbridge's WebSocket server
+    pub url: String,
+    pub joint_state_topic: String,
+    pub pose_topic: String,
+    pub publish_interval_ms: u64,
+}
+
+impl Default for RosSettings {
+    fn default() -> Self {
+        RosSettings {
+            enabled: false,
+            url: "ws://localhost:9090".to_owned(),
+            joint_state_topic: "/joint_states".to_owned(),
+            pose_topic: "/arm/pose".to_owned(),
+            publish_interval_ms: 100,
+        }
+    }
+}
+
+// ROS names can't have spaces or capitals, so "Upper Arm" becomes "upper_arm"
+fn ros_name(name: &str) -> String {
+    name.trim().to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn link_name(joint: &JointDescription) -> String {
+    format!("{}_link", ros_name(&joint.name))
+}
+
+// A sensor_msgs/msg/JointState with the chain's joint angles in radians, named as in the URDF
+pub fn joint_state(chain: &ChainDescription, servo_angles: &[f64; SERVO_COUNT], stamp: Duration) -> Value {
+    let names: Vec<String> = chain.joints.iter().map(|joint| ros_name(&joint.name)).collect();
+    let positions: Vec<f64> = chain.joints.iter().map(|joint| joint.mapping.to_joint(servo_angles[joint.servo]).to_radians()).collect();
+    json!({
+        "header": { "stamp": { "sec": stamp.as_secs(), "nanosec": stamp.subsec_nanos() }, "frame_id": BASE_LINK },
+        "name": names,
+        "position": positions,
+        "velocity": [],
+        "effort": [],
+    })
+}
+
+// A geometry_msgs/msg/Pose of the end effector, relative to the base link
+pub fn pose(arm: &Arm) -> Value {
+    let tool = arm.poses().tool;
+    let (position, rotation) = (tool.translation.vector, tool.rotation);
+    json!({
+        "position": { "x": position.x, "y": position.y, "z": position.z },
+        "orientation": { "x": rotation.i, "y": rotation.j, "z": rotation.k, "w": rotation.w },
+    })
+}
+
+// Describes the chain as a URDF, with a cylinder along each link and joint limits from the servo limits
+pub fn urdf(chain: &ChainDescription, limits: &[ServoLimit; SERVO_COUNT]) -> String {
+    // Where each joint sits in its parent link's frame, and then where the tool sits in the last link's
+    let mut origins = Vec::with_capacity(chain.joints.len() + 1);
+    let mut after = na::Isometry3::identity();
+    let mut axes = Vec::with_capacity(chain.joints.len());
+    for joint in &chain.joints {
+        let (before, axis, next) = joint.frame.split();
+        origins.push(after * before);
+        axes.push(axis);
+        after = next;
+    }
+    origins.push(after * na::Translation3::new(chain.tool[0], chain.tool[1], chain.tool[2]));
+
+    let mut links = vec![BASE_LINK.to_owned()];
+    links.extend(chain.joints.iter().map(link_name));
+
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0"?>"#);
+    let _ = writeln!(out, r#"<robot name="{}">"#, escape(&chain.name));
+    // Each link reaches to the next joint, or to the tool for the last one
+    for (link, origin) in links.iter().zip(&origins) {
+        write_link(&mut out, link, &origin.translation.vector);
+    }
+    let _ = writeln!(out, r#"  <link name="{}"/>"#, TOOL_LINK);
+    for (index, joint) in chain.joints.iter().enumerate() {
+        let limit = limits[joint.servo];
+        let (start, end) = (joint.mapping.to_joint(limit.min).to_radians(), joint.mapping.to_joint(limit.max).to_radians());
+        let axis = axes[index];
+        let _ = writeln!(out, r#"  <joint name="{}" type="revolute">"#, ros_name(&joint.name));
+        let _ = writeln!(out, r#"    <parent link="{}"/>"#, links[index]);
+        let _ = writeln!(out, r#"    <child link="{}"/>"#, links[index + 1]);
+        let _ = writeln!(out, "    {}", origin_element(&origins[index]));
+        let _ = writeln!(out, r#"    <axis xyz="{} {} {}"/>"#, axis.x, axis.y, axis.z);
+        let _ = writeln!(
+            out,
+            r#"    <limit lower="{}" upper="{}" effort="{}" velocity="{}"/>"#,
+            start.min(end),
+            start.max(end),
+            JOINT_EFFORT,
+            JOINT_VELOCITY
+        );
+        let _ = writeln!(out, "  </joint>");
+    }
+    let _ = writeln!(out, r#"  <joint name="tool" type="fixed">"#);
+    let _ = writeln!(out, r#"    <parent link="{}"/>"#, links[links.len() - 1]);
+    let _ = writeln!(out, r#"    <child link="{}"/>"#, TOOL_LINK);
+    let _ = writeln!(out, "    {}", origin_element(&origins[origins.len() - 1]));
+    let _ = writeln!(out, "  </joint>");
+    let _ = writeln!(out, "</robot>");
+    out
+}
+
+// A link with a cylinder from its origin to the given point, or nothing to see if the next joint is in the same place
+fn write_link(out: &mut String, name: &str, to: &na::Vector3<f64>) {
+    let length = to.norm();
+    if length < 1e-9 {
+        let _ = writeln!(out, r#"  <link name="{}"/>"#, name);
+        return;
+    }
+    // Cylinders run along z, centred on their origin
+    let rotation = na::UnitQuaternion::rotation_between(&na::Vector3::z(), to)
+        .unwrap_or_else(|| na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), std::f64::consts::PI));
+    let origin = na::Isometry3::from_parts(na::Translation3::from(to / 2.0), rotation);
+    let _ = writeln!(out, r#"  <link name="{}">"#, name);
+    let _ = writeln!(out, "    <visual>");
+    let _ = writeln!(out, "      {}", origin_element(&origin));
+    let _ = writeln!(out, r#"      <geometry><cylinder radius="{}" length="{}"/></geometry>"#, LINK_RADIUS, length);
+    let _ = writeln!(out, "    </visual>");
+    let _ = writeln!(out, "  </link>");
+}
+
+fn origin_element(pose: &na::Isometry3<f64>) -> String {
+    let position = pose.translation.vector;
+    let (roll, pitch, yaw) = pose.rotation.euler_angles();
+    // Adding 0 turns -0 into 0, which reads better
+    let [x, y, z, roll, pitch, yaw] = [position.x, position.y, position.z, roll, pitch, yaw].map(|value| value + 0.0);
+    format!(r#"<origin xyz="{} {} {}" rpy="{} {} {}"/>"#, x, y, z, roll, pitch, yaw)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Publishes the arm to rosbridge until the process exits, reconnecting whenever it goes away.
+// Starts from the profile's chain and follows the controller's when another one is loaded in Settings.
+pub async fn run(settings: RosSettings, chain: ChainDescription, api: ApiClient) {
+    let mut arm = Arm::from_chain(chain);
+    loop {
+        if let Err(e) = publish(&settings, &mut arm, &api).await {
+            log::warn!("rosbridge connection to {} failed: {}", settings.url, e);
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn publish(settings: &RosSettings, arm: &mut Arm, api: &ApiClient) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(settings.url.as_str()).await?;
+    log::info!("Publishing to rosbridge at {}", settings.url);
+    let advertise = [
+        (&settings.joint_state_topic, "sensor_msgs/msg/JointState"),
+        (&settings.pose_topic, "geometry_msgs/msg/Pose"),
+    ];
+    for (topic, message_type) in advertise {
+        socket.send(Message::Text(json!({ "op": "advertise", "topic": topic, "type": message_type }).to_string())).await?;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_millis(settings.publish_interval_ms.max(10)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            // rosbridge only talks back about errors, but it has to be read to notice it going away
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    log::warn!("rosbridge: {}", text);
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Err(e)) => return Err(e),
+                Some(Ok(_)) => continue,
+            },
+        }
+        let Ok(servos) = api.call(ApiCommand::GetServos).await else {
+            continue;
+        };
+        if let Some(chain) = api.call(ApiCommand::GetChain).await.ok().and_then(|chain| serde_json::from_value::<ChainDescription>(chain).ok()) {
+            if chain != *arm.chain() {
+                arm.set_chain(chain);
+            }
+        }
+        let angles: [f64; SERVO_COUNT] =
+            std::array::from_fn(|servo| servos["angles"][arm::SERVO_NAMES[servo].to_lowercase()].as_f64().unwrap_or(arm::HOME_SERVO_ANGLES[servo]));
+        arm.set_servo_angles(&angles);
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let messages = [
+            (&settings.joint_state_topic, joint_state(arm.chain(), &angles, stamp)),
+            (&settings.pose_topic, pose(arm)),
+        ];
+        for (topic, message) in messages {
+            socket.send(Message::Text(json!({ "op": "publish", "topic": topic, "msg": message }).to_string())).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Element names in document order, checking every opened element is closed in the right order
+    fn elements(document: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut open: Vec<String> = Vec::new();
+        for tag in document.split('<').skip(1) {
+            let tag = tag.split('>').next().expect("Unterminated tag");
+            if tag.starts_with('?') {
+                continue;
+            }
+            if let Some(closing) = tag.strip_prefix('/') {
+                assert_eq!(open.pop().as_deref(), Some(closing), "Mismatched </{}>", closing);
+                continue;
+            }
+            let name = tag.split_whitespace().next().unwrap_or_default().trim_end_matches('/').to_owned();
+            assert!(!open.is_empty() || names.is_empty(), "More than one root element, {} after the first", name);
+            if !tag.ends_with('/') {
+                open.push(name.clone());
+            }
+            names.push(name);
+        }
+        assert!(open.is_empty(), "Unclosed {:?}", open);
+        names
+    }
+
+    fn attribute<'a>(element: &'a str, name: &str) -> &'a str {
+        let start = element.find(&format!(r#" {}=""#, name)).unwrap_or_else(|| panic!("No {} in {}", name, element)) + name.len() + 3;
+        let length = element[start..].find('"').expect("Unterminated attribute");
+        &element[start..start + length]
+    }
+
+    fn full_travel() -> [ServoLimit; SERVO_COUNT] {
+        [ServoLimit { min: 0.0, max: 180.0 }; SERVO_COUNT]
+    }
+
+    #[test]
+    fn urdf_is_well_formed() {
+        let chain = ChainDescription::five_servo(1.0, 0.8, 0.1);
+        let urdf = urdf(&chain, &full_travel());
+        assert!(urdf.starts_with(r#"<?xml version="1.0"?>"#));
+        let elements = elements(&urdf);
+        assert_eq!(elements[0], "robot");
+        // A link for the base, each joint and the tool, and a joint for each joint plus the fixed tool joint
+        assert_eq!(elements.iter().filter(|name| *name == "link").count(), chain.joints.len() + 2);
+        assert_eq!(elements.iter().filter(|name| *name == "joint").count(), chain.joints.len() + 1);
+        for link in ["base_link", "top_link", "shoulder_link", "upper_link", "elbow_link", "lower_link", "tool_link"] {
+            assert!(urdf.contains(&format!(r#"<link name="{}""#, link)), "No {}", link);
+        }
+    }
+
+    #[test]
+    fn urdf_joints_chain_links_together_within_their_limits() {
+        let chain = ChainDescription::five_servo(1.0, 0.8, 0.1);
+        let urdf = urdf(&chain, &full_travel());
+        let joints: Vec<&str> = urdf.split("<joint").skip(1).collect();
+        let mut parent = BASE_LINK.to_owned();
+        for (joint, description) in joints.iter().zip(&chain.joints) {
+            assert_eq!(attribute(joint, "name"), ros_name(&description.name));
+            assert_eq!(attribute(joint, "type"), "revolute");
+            assert!(joint.contains(&format!(r#"<parent link="{}"/>"#, parent)));
+            parent = link_name(description);
+            assert!(joint.contains(&format!(r#"<child link="{}"/>"#, parent)));
+        }
+        // The elbow sits up the upper arm, and the tool past the lower arm
+        assert!(joints[3].contains(r#"<origin xyz="0 0 1" rpy="0 0 0"/>"#), "{}", joints[3]);
+        assert_eq!(attribute(joints[5], "type"), "fixed");
+        assert!(joints[5].contains(r#"<parent link="lower_link"/>"#) && joints[5].contains(r#"<child link="tool_link"/>"#));
+        assert!(joints[5].contains(r#"<origin xyz="0 0 0.1" rpy="0 0 0"/>"#), "{}", joints[5]);
+
+        // Limits are the servo limits as joint angles in radians, lowest first even for reversed joints
+        let limit = |joint: &str| -> (f64, f64) {
+            let element = joint.split("<limit").nth(1).expect("No limit");
+            (attribute(element, "lower").parse().unwrap(), attribute(element, "upper").parse().unwrap())
+        };
+        let (lower, upper) = limit(joints[0]);
+        assert!(approx::abs_diff_eq!(lower, -95f64.to_radians()) && approx::abs_diff_eq!(upper, 85f64.to_radians()));
+        let (lower, upper) = limit(joints[2]);
+        assert!(approx::abs_diff_eq!(lower, -90f64.to_radians()) && approx::abs_diff_eq!(upper, 90f64.to_radians()));
+    }
+
+    #[test]
+    fn urdf_escapes_the_chain_name() {
+        let mut chain = ChainDescription::five_servo(1.0, 0.8, 0.0);
+        chain.name = r#"Tom & Jerry's "<arm>""#.to_owned();
+        let urdf = urdf(&chain, &full_travel());
+        assert!(urdf.contains(r#"<robot name="Tom &amp; Jerry's &quot;&lt;arm&gt;&quot;">"#));
+        elements(&urdf);
+    }
+}
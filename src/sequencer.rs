@@ -0,0 +1,365 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::arm::{Arm, IkError, IkOptions, ServoLimit, SERVO_COUNT};
+
+// How a segment speeds up and slows down between two keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // Holds the previous pose, then jumps at the end of the segment
+    Step,
+}
+
+impl Easing {
+    pub const ALL: [Easing; 5] = [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut, Easing::Step];
+
+    // Maps the fraction of the segment's time that has passed to the fraction of the way to the next pose
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Step => {
+                if t >= 1.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Easing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Easing::Linear => write!(f, "Linear"),
+            Easing::EaseIn => write!(f, "Ease In"),
+            Easing::EaseOut => write!(f, "Ease Out"),
+            Easing::EaseInOut => write!(f, "Ease In/Out"),
+            Easing::Step => write!(f, "Step"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Pose {
+    // Servo angles in degrees, ordered as in arm::SERVO_NAMES
+    Joints { angles: [f64; SERVO_COUNT] },
+    // Tool position in metres, reached with the numeric IK solver
+    Cartesian { i: f64, j: f64, k: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub pose: Pose,
+    // Seconds taken to get here from the previous keyframe, or from wherever the arm was for the first one
+    pub duration: f64,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+// What is interpolated between keyframes. Joint space moves every servo at its own steady rate,
+// Cartesian space moves the tool in a straight line and solves IK along the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Joint,
+    Cartesian,
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpolation::Joint => write!(f, "Joint Space"),
+            Interpolation::Cartesian => write!(f, "Cartesian Space"),
+        }
+    }
+}
+
+// A motion authored as keyframes, saved as JSON
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Sequence {
+    pub name: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Sequence {
+    pub fn load(path: &Path) -> Result<Self, SequenceError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SequenceError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.keyframes.iter().map(|keyframe| keyframe.duration.max(0.0)).sum()
+    }
+
+    // Works out the servo angles and tool position of every keyframe, starting from the given pose.
+    // Joint poses are clamped to the limits, and Cartesian poses are solved from the keyframe before them.
+    pub fn plan(&self, arm: &Arm, start: [f64; SERVO_COUNT], limits: &[ServoLimit; SERVO_COUNT], options: &IkOptions) -> Result<Plan, SequenceError> {
+        if self.keyframes.is_empty() {
+            return Err(SequenceError::Empty);
+        }
+        let mut solver = Arm::from_chain(arm.chain().clone());
+        let start = clamp(start, limits);
+        let mut points = vec![PlannedKeyframe { angles: start, position: arm.forward(&start), time: 0.0, easing: Easing::Linear }];
+        let mut time = 0.0;
+        for (index, keyframe) in self.keyframes.iter().enumerate() {
+            let previous = points.last().map_or(start, |point| point.angles);
+            let angles = match keyframe.pose {
+                Pose::Joints { angles } => clamp(angles, limits),
+                Pose::Cartesian { i, j, k } => {
+                    solver.set_servo_angles(&previous);
+                    let report = solver.solve_numeric(i, j, k, limits, options)?;
+                    if !report.converged() {
+                        return Err(SequenceError::Unreachable { keyframe: index + 1, report: report.to_string() });
+                    }
+                    report.angles
+                }
+            };
+            time += keyframe.duration.max(0.0);
+            points.push(PlannedKeyframe { angles, position: arm.forward(&angles), time, easing: keyframe.easing });
+        }
+        Ok(Plan {
+            interpolation: self.interpolation,
+            points,
+            limits: *limits,
+            options: *options,
+            solver,
+        })
+    }
+}
+
+fn clamp(angles: [f64; SERVO_COUNT], limits: &[ServoLimit; SERVO_COUNT]) -> [f64; SERVO_COUNT] {
+    std::array::from_fn(|servo| limits[servo].clamp(angles[servo]))
+}
+
+struct PlannedKeyframe {
+    angles: [f64; SERVO_COUNT],
+    position: na::Vector3<f64>,
+    // Seconds from the start of the sequence
+    time: f64,
+    easing: Easing,
+}
+
+// A sequence resolved against the arm, ready to be played
+pub struct Plan {
+    interpolation: Interpolation,
+    // The starting pose, then one per keyframe
+    points: Vec<PlannedKeyframe>,
+    limits: [ServoLimit; SERVO_COUNT],
+    options: IkOptions,
+    solver: Arm,
+}
+
+impl Plan {
+    pub fn duration(&self) -> f64 {
+        self.points.last().map_or(0.0, |point| point.time)
+    }
+
+    // Seconds from the start at which each keyframe is reached
+    pub fn keyframe_times(&self) -> impl Iterator<Item = f64> + '_ {
+        self.points.iter().skip(1).map(|point| point.time)
+    }
+
+    // Servo angles at a time into the sequence. Cartesian interpolation solves IK from current, which should be the last pose sent,
+    // so the arm doesn't flip between solutions.
+    pub fn pose_at(&mut self, time: f64, current: [f64; SERVO_COUNT]) -> Result<[f64; SERVO_COUNT], SequenceError> {
+        let Some(next) = self.points.iter().position(|point| point.time > time) else {
+            return Ok(self.points.last().map_or(current, |point| point.angles));
+        };
+        if next == 0 {
+            return Ok(self.points[0].angles);
+        }
+        let (from, to) = (&self.points[next - 1], &self.points[next]);
+        let t = to.easing.apply((time - from.time) / (to.time - from.time));
+        match self.interpolation {
+            Interpolation::Joint => Ok(std::array::from_fn(|servo| from.angles[servo] + (to.angles[servo] - from.angles[servo]) * t)),
+            Interpolation::Cartesian => {
+                let position = from.position.lerp(&to.position, t);
+                self.solver.set_servo_angles(&current);
+                let report = self.solver.solve_numeric(position.x, position.y, position.z, &self.limits, &self.options)?;
+                if !report.converged() {
+                    return Err(SequenceError::OffPath { time, report: report.to_string() });
+                }
+                Ok(report.angles)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SequenceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Empty,
+    // A Cartesian keyframe the arm can't reach within its limits, numbered from 1
+    Unreachable { keyframe: usize, report: String },
+    // The straight line between two reachable keyframes passes somewhere the arm can't reach
+    OffPath { time: f64, report: String },
+    // The solver refused to start, e.g. over the servo limits
+    Ik(IkError),
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::Io(e) => write!(f, "Unable to access sequence file: {}", e),
+            SequenceError::Json(e) => write!(f, "Unable to parse sequence JSON: {}", e),
+            SequenceError::Empty => write!(f, "The sequence has no keyframes"),
+            SequenceError::Unreachable { keyframe, report } => write!(f, "Keyframe {} is out of reach: {}", keyframe, report),
+            SequenceError::OffPath { time, report } => write!(f, "The path leaves the arm's reach at {:.2} s: {}", time, report),
+            SequenceError::Ik(e) => write!(f, "Unable to plan the sequence: {}", e),
+        }
+    }
+}
+
+impl Error for SequenceError {}
+
+impl From<std::io::Error> for SequenceError {
+    fn from(e: std::io::Error) -> Self {
+        SequenceError::Io(e)
+    }
+}
+
+impl From<IkError> for SequenceError {
+    fn from(e: IkError) -> Self {
+        SequenceError::Ik(e)
+    }
+}
+
+impl From<serde_json::Error> for SequenceError {
+    fn from(e: serde_json::Error) -> Self {
+        SequenceError::Json(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_travel() -> [ServoLimit; SERVO_COUNT] {
+        [ServoLimit { min: 0.0, max: 180.0 }; SERVO_COUNT]
+    }
+
+    fn joints(angle: f64, duration: f64, easing: Easing) -> Keyframe {
+        Keyframe { pose: Pose::Joints { angles: [angle; SERVO_COUNT] }, duration, easing }
+    }
+
+    fn sequence(interpolation: Interpolation, keyframes: Vec<Keyframe>) -> Sequence {
+        Sequence { name: "test".to_owned(), interpolation, keyframes }
+    }
+
+    fn assert_angles(angles: [f64; SERVO_COUNT], expected: [f64; SERVO_COUNT]) {
+        for (angle, expected) in angles.iter().zip(expected) {
+            assert!((angle - expected).abs() < 1e-9, "{:?} isn't {:?}", angles, expected);
+        }
+    }
+
+    #[test]
+    fn easing_starts_at_0_and_ends_at_1() {
+        for easing in Easing::ALL {
+            assert_eq!(easing.apply(0.0), 0.0, "{}", easing);
+            assert_eq!(easing.apply(1.0), 1.0, "{}", easing);
+            // Times outside the segment are held at its ends
+            assert_eq!(easing.apply(-0.5), 0.0, "{}", easing);
+            assert_eq!(easing.apply(1.5), 1.0, "{}", easing);
+        }
+        assert_eq!(Easing::Linear.apply(0.25), 0.25);
+        assert!(Easing::EaseIn.apply(0.25) < 0.25);
+        assert!(Easing::EaseOut.apply(0.25) > 0.25);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::EaseInOut.apply(0.25) < 0.25 && Easing::EaseInOut.apply(0.75) > 0.75);
+        assert_eq!(Easing::Step.apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn empty_sequence_has_no_plan() {
+        let arm = Arm::new(1.0, 0.8);
+        let empty = sequence(Interpolation::Joint, Vec::new());
+        assert_eq!(empty.duration(), 0.0);
+        let result = empty.plan(&arm, arm.servo_angles(), &full_travel(), &IkOptions::default());
+        assert!(matches!(result, Err(SequenceError::Empty)));
+    }
+
+    #[test]
+    fn joint_plan_interpolates_between_keyframes() {
+        let arm = Arm::new(1.0, 0.8);
+        let start = [90.0; SERVO_COUNT];
+        let keyframes = vec![joints(100.0, 1.0, Easing::Linear), joints(80.0, 2.0, Easing::EaseIn), joints(70.0, 0.0, Easing::Linear)];
+        let mut plan = sequence(Interpolation::Joint, keyframes).plan(&arm, start, &full_travel(), &IkOptions::default()).unwrap();
+        assert_eq!(plan.duration(), 3.0);
+        assert_eq!(plan.keyframe_times().collect::<Vec<_>>(), [1.0, 3.0, 3.0]);
+
+        assert_angles(plan.pose_at(0.0, start).unwrap(), start);
+        assert_angles(plan.pose_at(0.5, start).unwrap(), [95.0; SERVO_COUNT]);
+        assert_angles(plan.pose_at(1.0, start).unwrap(), [100.0; SERVO_COUNT]);
+        // Half way through an ease in segment is a quarter of the way there
+        assert_angles(plan.pose_at(2.0, start).unwrap(), [95.0; SERVO_COUNT]);
+        // At and past the end it holds the last keyframe, including one that takes no time
+        assert_angles(plan.pose_at(3.0, start).unwrap(), [70.0; SERVO_COUNT]);
+        assert_angles(plan.pose_at(10.0, start).unwrap(), [70.0; SERVO_COUNT]);
+    }
+
+    #[test]
+    fn joint_keyframes_and_start_are_clamped_to_the_limits() {
+        let arm = Arm::new(1.0, 0.8);
+        let limits = [ServoLimit { min: 60.0, max: 120.0 }; SERVO_COUNT];
+        let keyframes = vec![joints(150.0, 1.0, Easing::Linear), joints(-10.0, 1.0, Easing::Linear)];
+        let mut plan = sequence(Interpolation::Joint, keyframes).plan(&arm, [0.0; SERVO_COUNT], &limits, &IkOptions::default()).unwrap();
+        assert_angles(plan.pose_at(0.0, [0.0; SERVO_COUNT]).unwrap(), [60.0; SERVO_COUNT]);
+        assert_angles(plan.pose_at(1.0, [0.0; SERVO_COUNT]).unwrap(), [120.0; SERVO_COUNT]);
+        assert_angles(plan.pose_at(2.0, [0.0; SERVO_COUNT]).unwrap(), [60.0; SERVO_COUNT]);
+    }
+
+    #[test]
+    fn cartesian_plan_moves_the_tool_in_a_straight_line() {
+        let arm = Arm::new(1.0, 0.8);
+        let start = arm.servo_angles();
+        let options = IkOptions::default();
+        let (from, to) = (arm.forward(&start), na::Vector3::new(0.6, 0.2, 1.2));
+        let keyframes = vec![Keyframe { pose: Pose::Cartesian { i: to.x, j: to.y, k: to.z }, duration: 2.0, easing: Easing::Linear }];
+        let mut plan = sequence(Interpolation::Cartesian, keyframes).plan(&arm, start, &full_travel(), &options).unwrap();
+
+        let mut current = start;
+        for step in 0..=8 {
+            let time = step as f64 * 0.25;
+            current = plan.pose_at(time, current).unwrap();
+            let expected = from.lerp(&to, time / 2.0);
+            assert!((arm.forward(&current) - expected).norm() <= options.tolerance, "Off the line at {} s", time);
+        }
+    }
+
+    #[test]
+    fn unreachable_cartesian_keyframes_are_reported() {
+        let arm = Arm::new(1.0, 0.8);
+        let keyframes = vec![
+            joints(90.0, 1.0, Easing::Linear),
+            Keyframe { pose: Pose::Cartesian { i: 0.0, j: 0.0, k: 5.0 }, duration: 1.0, easing: Easing::Linear },
+        ];
+        let result = sequence(Interpolation::Joint, keyframes).plan(&arm, arm.servo_angles(), &full_travel(), &IkOptions::default());
+        assert!(matches!(result, Err(SequenceError::Unreachable { keyframe: 2, .. })));
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::arm::SERVO_COUNT;
+
+// Events kept for clients that fall behind before they start missing some
+pub const STREAM_CAPACITY: usize = 1024;
+
+// What a client can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Feedback,
+    Adc,
+    Command,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Feedback, Channel::Adc, Channel::Command];
+
+    pub fn all() -> HashSet<Channel> {
+        Channel::ALL.into_iter().collect()
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Feedback => write!(f, "feedback"),
+            Channel::Adc => write!(f, "adc"),
+            Channel::Command => write!(f, "command"),
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Channel::ALL
+            .into_iter()
+            .find(|channel| channel.to_string() == s)
+            .ok_or_else(|| format!("Unknown channel {}, expected one of feedback, adc, command", s))
+    }
+}
+
+// One decoded packet from the arm, or one packet sent to it, as pushed to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamEvent {
+    // Milliseconds since the Unix epoch, when the controller handled it
+    pub time_ms: i64,
+    #[serde(flatten)]
+    pub data: EventData,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum EventData {
+    // Where the arm reports each servo to be, in degrees and in wire values
+    Feedback { angles: [f64; SERVO_COUNT], wire: [u16; SERVO_COUNT] },
+    Adc { values: Vec<u16> },
+    // Every attempt at sending a command, so retries show up with the same ID
+    ServoCommand { sequence: u16, id: u16, angles: [f64; SERVO_COUNT], wire: [u16; SERVO_COUNT] },
+    EmergencyStop { sequence: u16, id: u16 },
+}
+
+impl StreamEvent {
+    pub fn now(data: EventData) -> Self {
+        StreamEvent { time_ms: Local::now().timestamp_millis(), data }
+    }
+
+    pub fn channel(&self) -> Channel {
+        match self.data {
+            EventData::Feedback { .. } => Channel::Feedback,
+            EventData::Adc { .. } => Channel::Adc,
+            EventData::ServoCommand { .. } | EventData::EmergencyStop { .. } => Channel::Command,
+        }
+    }
+}
+
+// Sent by a client to change what it's subscribed to, e.g. {"subscribe": ["feedback", "adc"]}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    pub subscribe: HashSet<Channel>,
+}
+
+// Parses the comma separated channels in a stream URL
+pub fn parse_channels(list: &str) -> Result<HashSet<Channel>, String> {
+    list.split(',').map(str::trim).filter(|name| !name.is_empty()).map(Channel::from_str).collect()
+}
+
+pub fn channel() -> broadcast::Sender<StreamEvent> {
+    broadcast::channel(STREAM_CAPACITY).0
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::arm::SERVO_COUNT;
+
+// How far back the charts can look, older samples are thrown away
+pub const MAX_HISTORY_SECONDS: f64 = 300.0;
+
+// A series of (seconds since start, value) samples, ready to hand to egui_plot
+pub type Series = VecDeque<[f64; 2]>;
+
+// Everything the arm has reported recently, kept as time series for the dashboard
+pub struct Telemetry {
+    start: Instant,
+    last_received: Option<Instant>,
+    // Servo angles in wire order, after undoing the calibration
+    pub servos: [Series; SERVO_COUNT],
+    // One series per ADC channel, growing if the arm starts reporting more channels
+    pub adc: Vec<Series>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Telemetry {
+            start: Instant::now(),
+            last_received: None,
+            servos: std::array::from_fn(|_| VecDeque::new()),
+            adc: Vec::new(),
+        }
+    }
+
+    // Seconds since the telemetry started, the x axis of every series
+    pub fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    pub fn seconds_since_last_received(&self) -> Option<f64> {
+        self.last_received.map(|instant| instant.elapsed().as_secs_f64())
+    }
+
+    // Samples are placed at the time they came off the socket, not when the GUI got around to them
+    pub fn record_servos(&mut self, angles: &[f64; SERVO_COUNT], at: Instant) {
+        let now = self.received(at);
+        for (series, angle) in self.servos.iter_mut().zip(angles) {
+            push(series, now, *angle);
+        }
+    }
+
+    pub fn record_adc(&mut self, values: &[u16], at: Instant) {
+        let now = self.received(at);
+        if self.adc.len() < values.len() {
+            self.adc.resize_with(values.len(), VecDeque::new);
+        }
+        for (series, value) in self.adc.iter_mut().zip(values) {
+            push(series, now, *value as f64);
+        }
+    }
+
+    // Latest value of a series, for the numeric readouts
+    pub fn latest(series: &Series) -> Option<f64> {
+        series.back().map(|[_, value]| *value)
+    }
+
+    pub fn clear(&mut self) {
+        self.last_received = None;
+        self.servos.iter_mut().for_each(VecDeque::clear);
+        self.adc.clear();
+    }
+
+    fn received(&mut self, at: Instant) -> f64 {
+        self.last_received = Some(at);
+        at.saturating_duration_since(self.start).as_secs_f64()
+    }
+}
+
+fn push(series: &mut Series, time: f64, value: f64) {
+    series.push_back([time, value]);
+    while series.front().is_some_and(|[oldest, _]| time - oldest > MAX_HISTORY_SECONDS) {
+        series.pop_front();
+    }
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use eframe::egui;
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::protocol::{Message, Packet, ProtocolError, MAX_PACKET_LENGTH};
+
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:8080";
+
+pub enum TransportCommand {
+    // Already encoded datagram, sent to an address that may still need resolving
+    Send { to: String, packet: Vec<u8> },
+}
+
+pub enum TransportEvent {
+    // A datagram arrived, stamped with when it came off the socket rather than when the GUI got to it
+    Received { from: SocketAddr, at: Instant, packet: Result<Packet, ProtocolError> },
+    SendFailed { to: String, error: String },
+}
+
+// The GUI's end of the transport task. The task owns the socket and drains it as fast as
+// datagrams arrive, queueing them up here until the next frame picks them up.
+pub struct Transport {
+    runtime: Handle,
+    // None when running headless, with no UI to wake up
+    repaint: Option<egui::Context>,
+    // What we were last asked to bind to, whether or not it worked
+    address: Option<String>,
+    link: Option<Link>,
+}
+
+// Channels to the task running for the currently bound socket
+struct Link {
+    commands: UnboundedSender<TransportCommand>,
+    events: UnboundedReceiver<TransportEvent>,
+    local_address: SocketAddr,
+}
+
+impl Transport {
+    // Nothing is bound until bind() is called. The UI, if there is one, is woken whenever something arrives.
+    pub fn new(runtime: Handle, repaint: Option<egui::Context>) -> Self {
+        Transport {
+            runtime,
+            repaint,
+            address: None,
+            link: None,
+        }
+    }
+
+    // Binds the socket and spawns a task for it on the runtime, then closes any socket that was already bound.
+    // If the new address can't be bound the old socket stays open, so the arm isn't lost over a typo.
+    // An IPv6 address also accepts IPv4 traffic, so binding to [::] covers both.
+    pub fn bind(&mut self, address: &str) -> Result<SocketAddr, TransportError> {
+        self.address = Some(address.to_owned());
+        let socket_address: SocketAddr = address.trim().parse().map_err(|_| TransportError::InvalidAddress(address.to_owned()))?;
+        // A second socket on the same address would fail to bind while this one holds it
+        if let Some(link) = self.link.as_ref().filter(|link| link.local_address == socket_address) {
+            return Ok(link.local_address);
+        }
+        let socket = {
+            // Registering the socket with tokio needs to happen inside the runtime
+            let _guard = self.runtime.enter();
+            bind_socket(socket_address)
+        }
+        .map_err(|error| TransportError::Bind { address: address.to_owned(), error })?;
+        let local_address = socket.local_addr().map_err(|error| TransportError::Bind { address: address.to_owned(), error })?;
+
+        let (command_sender, command_receiver) = mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        self.runtime.spawn(run(socket, command_receiver, event_sender, self.repaint.clone()));
+        // Dropping the old link closes its command channel, which stops its task and closes its socket
+        self.link = Some(Link {
+            commands: command_sender,
+            events: event_receiver,
+            local_address,
+        });
+        log::info!("Listening on {}", local_address);
+        Ok(local_address)
+    }
+
+    pub fn send(&self, to: &str, packet: Vec<u8>) -> Result<(), TransportError> {
+        let link = self.link.as_ref().ok_or(TransportError::NotBound)?;
+        link.commands
+            .send(TransportCommand::Send { to: to.to_owned(), packet })
+            .map_err(|_| TransportError::Closed)
+    }
+
+    // Next event waiting for the GUI, if there is one
+    pub fn try_recv(&mut self) -> Option<TransportEvent> {
+        self.link.as_mut()?.events.try_recv().ok()
+    }
+
+    // Waits for the next event, for when there's no frame loop to poll from. None once the socket has closed.
+    pub async fn recv(&mut self) -> Option<TransportEvent> {
+        self.link.as_mut()?.events.recv().await
+    }
+
+    pub fn address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+
+    // Where the socket actually ended up, which tells you the port when binding to port 0
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.link.as_ref().map(|link| link.local_address)
+    }
+}
+
+fn bind_socket(address: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+    if address.is_ipv6() {
+        // Not every platform defaults to dual stack, Windows in particular doesn't
+        socket.set_only_v6(false)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+// Picks an address for the peer that this socket can actually send to
+async fn resolve(to: &str, local: SocketAddr) -> std::io::Result<SocketAddr> {
+    let candidates: Vec<SocketAddr> = tokio::net::lookup_host(to).await?.collect();
+    if let Some(address) = candidates.iter().find(|address| address.is_ipv6() == local.is_ipv6()) {
+        return Ok(*address);
+    }
+    match candidates.first() {
+        // A dual stack socket reaches IPv4 peers through mapped addresses
+        Some(SocketAddr::V4(address)) if local.is_ipv6() => Ok(SocketAddr::new(address.ip().to_ipv6_mapped().into(), address.port())),
+        Some(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AddrNotAvailable,
+            "An IPv4 socket can't reach an IPv6 address, bind to [::] instead",
+        )),
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "Address didn't resolve")),
+    }
+}
+
+async fn run(
+    socket: UdpSocket,
+    mut commands: UnboundedReceiver<TransportCommand>,
+    events: UnboundedSender<TransportEvent>,
+    repaint: Option<egui::Context>,
+) {
+    let mut buffer = vec![0; MAX_PACKET_LENGTH];
+    let Ok(local_address) = socket.local_addr() else {
+        return;
+    };
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok((length, from)) => {
+                    // Show IPv4 peers of a dual stack socket as plain IPv4
+                    let from = SocketAddr::new(from.ip().to_canonical(), from.port());
+                    let event = TransportEvent::Received { from, at: Instant::now(), packet: Message::decode(&buffer[..length]) };
+                    if events.send(event).is_err() {
+                        break;
+                    }
+                    if let Some(repaint) = &repaint {
+                        repaint.request_repaint();
+                    }
+                }
+                // Windows reports an unreachable peer as an error on the next receive, which is nothing to stop for
+                Err(e) => log::warn!("Receive failed: {}", e),
+            },
+            command = commands.recv() => match command {
+                Some(TransportCommand::Send { to, packet }) => {
+                    let sent = match resolve(&to, local_address).await {
+                        Ok(address) => socket.send_to(&packet, address).await.map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        let _ = events.send(TransportEvent::SendFailed { to, error: e.to_string() });
+                        if let Some(repaint) = &repaint {
+                            repaint.request_repaint();
+                        }
+                    }
+                }
+                // The GUI has gone away, or rebound to another socket
+                None => break,
+            },
+        }
+    }
+    log::info!("Transport stopped");
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Encode(ProtocolError),
+    InvalidAddress(String),
+    Bind { address: String, error: std::io::Error },
+    NotBound,
+    Closed,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Encode(e) => write!(f, "Unable to encode message: {}", e),
+            TransportError::InvalidAddress(address) => write!(f, "{} is not an address to listen on, expected something like 0.0.0.0:8080 or [::]:8080", address),
+            TransportError::Bind { address, error } => write!(f, "Unable to listen on {}: {}", address, error),
+            TransportError::NotBound => write!(f, "Not listening on any address"),
+            TransportError::Closed => write!(f, "The network task has stopped"),
+        }
+    }
+}
+
+impl Error for TransportError {}
+
+impl From<ProtocolError> for TransportError {
+    fn from(e: ProtocolError) -> Self {
+        TransportError::Encode(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_bind_keeps_the_old_socket() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut transport = Transport::new(runtime.handle().clone(), None);
+        assert!(matches!(transport.send("127.0.0.1:9", Vec::new()), Err(TransportError::NotBound)));
+        let bound = transport.bind("127.0.0.1:0").unwrap();
+        assert_eq!(transport.local_address(), Some(bound));
+
+        assert!(matches!(transport.bind("not an address"), Err(TransportError::InvalidAddress(_))));
+        let holder = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let taken = holder.local_addr().unwrap();
+        assert!(matches!(transport.bind(&taken.to_string()), Err(TransportError::Bind { .. })));
+        assert_eq!(transport.local_address(), Some(bound));
+        assert_eq!(transport.address(), Some(taken.to_string().as_str()));
+        assert!(transport.send("127.0.0.1:9", Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn binding_the_same_address_again_keeps_the_socket() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut transport = Transport::new(runtime.handle().clone(), None);
+        let bound = transport.bind("127.0.0.1:0").unwrap();
+        assert_eq!(transport.bind(&bound.to_string()).unwrap(), bound);
+        let moved = transport.bind("127.0.0.1:0").unwrap();
+        assert_ne!(moved, bound);
+        assert_eq!(transport.local_address(), Some(moved));
+    }
+}